@@ -0,0 +1,161 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use storage::{Table, TableError, Tuple};
+use vector_clocks::{ApplyInsert, Cluster, Envelope, Message};
+
+// A single process that is both a cluster member and a table store. Inserts
+// made locally are sent to every peer, and inserts received from peers are
+// applied once the cluster has delivered them in causal order, so every
+// node ends up with the same rows.
+//
+// The table can't join the cluster behind its mutex, because its schema is
+// held in an `Rc` and so it can never leave the thread it was made on. It
+// sits alongside instead, and the whole node stays on one thread.
+pub struct Node {
+    cluster: Arc<Mutex<Cluster>>,
+    table: Table,
+}
+
+impl Node {
+    pub fn new(cluster: Arc<Mutex<Cluster>>, table: Table) -> Self {
+        Node {
+            cluster,
+            table,
+        }
+    }
+
+    pub fn cluster(&self) -> Arc<Mutex<Cluster>> {
+        self.cluster.clone()
+    }
+
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    // Inserts a row locally and sends it to every peer, returning the
+    // message that was sent
+    pub fn insert(&mut self, tuple: &Tuple) -> Result<Envelope, TableError> {
+        let index = self.table.insert(tuple)?;
+        let (row_bytes, heap_bytes) = self.table.export_row(index)?;
+
+        let mut cluster = self.cluster.lock().unwrap();
+        let mut clock = cluster.clock().clone();
+        clock.increment(cluster.node_id());
+        Ok(cluster.publish(ApplyInsert {
+            table_name: self.table.name().to_string(),
+            row_bytes,
+            heap_bytes,
+            clock,
+        }))
+    }
+
+    // Passes a message from a peer to the cluster and applies every insert
+    // it delivers as a result. Each delivered message is returned alongside
+    // the outcome of applying it; inserts for other tables are ignored.
+    pub fn receive(&mut self, envelope: Envelope) -> Vec<(Envelope, Result<(), TableError>)> {
+        let delivered = self.cluster.lock().unwrap().receive(envelope);
+        self.apply(delivered)
+    }
+
+    // Like `receive`, for a message that arrived from the peer at `origin`:
+    // the cluster also records the peer's handshake, answers its sync and
+    // status requests, and drops it if it sent anything before its handshake
+    pub fn receive_from(&mut self, origin: SocketAddr, envelope: Envelope) -> Vec<(Envelope, Result<(), TableError>)> {
+        let delivered = self.cluster.lock().unwrap().receive_from(origin, envelope);
+        self.apply(delivered)
+    }
+
+    fn apply(&mut self, delivered: Vec<Envelope>) -> Vec<(Envelope, Result<(), TableError>)> {
+        delivered.into_iter()
+            .map(|envelope| {
+                let applied = match envelope.message {
+                    Message::ApplyInsertMsg(ref insert) if insert.table_name == self.table.name() =>
+                        self.table.import_row(&insert.row_bytes, &insert.heap_bytes).map(|_| ()),
+                    _ => Ok(()),
+                };
+                (envelope, applied)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::db_value::{DBExternalString, DBUInt32};
+    use futures::{Future, Stream};
+    use storage::{DbType, FieldSpec, TypeSpec};
+    use std::rc::Rc;
+    use vector_clocks::clock::VectorClock;
+    use vector_clocks::compression::Handshake;
+    use vector_clocks::status::StatusRequest;
+    use vector_clocks::peer_channel;
+
+    fn test_node(node_id: &str) -> Node {
+        let table = Table::new("notes", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("note", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        Node::new(Arc::new(Mutex::new(Cluster::new(node_id))), table)
+    }
+
+    fn note(id: u32, text: &str) -> Tuple {
+        Tuple::new().with(DBUInt32(id)).with(DBExternalString(text.to_string()))
+    }
+
+    #[test]
+    fn insert_on_one_node_is_visible_on_another() {
+        let mut node_a = test_node("A");
+        let mut node_b = test_node("B");
+
+        let sent = node_a.insert(&note(1, "replicated")).unwrap();
+        let applied = node_b.receive(sent);
+
+        assert_eq!(1, applied.len());
+        assert_eq!(Ok(()), applied[0].1);
+        assert_eq!(1, node_b.table().row_count());
+        assert_eq!("1", node_b.table().get_field(0, "id").unwrap().to_display_string());
+        assert_eq!("replicated", node_b.table().get_field(0, "note").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn inserts_are_applied_in_causal_order() {
+        let mut node_a = test_node("A");
+        let mut node_b = test_node("B");
+
+        let first = node_a.insert(&note(1, "first")).unwrap();
+        let second = node_a.insert(&note(2, "second")).unwrap();
+        if let Message::ApplyInsertMsg(ref insert) = second.message {
+            assert_eq!(second.clock, insert.clock);
+        }
+
+        assert!(node_b.receive(second).is_empty());
+        assert_eq!(0, node_b.table().row_count());
+        assert_eq!(2, node_b.receive(first).len());
+        assert_eq!("first", node_b.table().get_field(0, "note").unwrap().to_display_string());
+        assert_eq!("second", node_b.table().get_field(1, "note").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn status_requests_from_peers_are_answered() {
+        let mut node = test_node("A");
+        let peer: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let (peer_tx, peer_rx) = peer_channel();
+        node.cluster().lock().unwrap().add_peer(peer, peer_tx);
+
+        let handshake = Envelope::new("monitor", VectorClock::new(), Handshake { compression: false, cluster_id: String::new() });
+        assert!(node.receive_from(peer, handshake).is_empty());
+        let mut clock = VectorClock::new();
+        clock.increment("monitor");
+        assert_eq!(1, node.receive_from(peer, Envelope::new("monitor", clock, StatusRequest)).len());
+        drop(node);
+
+        let frames = peer_rx.collect().wait().unwrap();
+        let answer = Envelope::decode(&frames[0]).unwrap();
+        match answer.message {
+            Message::StatusResponseMsg(status) => assert_eq!("A", status.node_id),
+            other => panic!("Expected a status response, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use futures::sync::mpsc;
+use tokio::codec::{FramedWrite, LengthDelimitedCodec};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::*;
+use tokio::runtime::current_thread;
+use tokio::timer::{Delay, Interval};
+
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use node::Node;
+use storage::db_value::DBExternalString;
+use storage::{DbType, FieldSpec, Table, Tuple, TypeSpec};
+use vector_clocks::writer::{self, DEFAULT_SEND_TIMEOUT};
+use vector_clocks::bind::{self, BIND_ADDR_VAR};
+use vector_clocks::blacklist::{Blacklist, BLACKLIST_VAR};
+use vector_clocks::idle::{self, IdleTimer, DEFAULT_IDLE_TIMEOUT};
+use vector_clocks::rate_limit::{RateLimit, RateLimiter, Verdict};
+use vector_clocks::wire;
+use vector_clocks::snapshot::ClusterSnapshotData;
+use vector_clocks::connections::PeerConnections;
+use vector_clocks::{peer_channel, Cluster, Message, Tx, CLUSTER_ID_VAR, DEFAULT_CLUSTER_ID};
+
+// How often the cluster membership is saved
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+// Peers from a saved snapshot are dropped if they can't be reached after
+// this many attempts
+const RECONNECT_ATTEMPTS: u32 = 3;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// How often connections are checked for having gone idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+// Runs one node of a replicated notes table. Each line read from stdin is
+// inserted as a note and sent to every connected peer.
+//
+// The peers a node is connected to are saved to `node-<port>.cluster` in the
+// working directory, and reconnected when it restarts. Peers that connected
+// to this node are saved at the address they connected from, so only peers
+// this node dialed can usually be reconnected.
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        println!("Usage: {} <port> [peer address...]", args[0]);
+        println!("Listens on ${} if set, else 127.0.0.1", BIND_ADDR_VAR);
+        println!("Connections from the comma-separated addresses in ${} are refused", BLACKLIST_VAR);
+        println!("Only peers in the cluster named by ${} are kept", CLUSTER_ID_VAR);
+        return;
+    }
+    let port: u16 = args[1].parse().map_err(|_| "could not parse port").unwrap();
+    let peers: Vec<SocketAddr> = args[2..].iter()
+        .map(|peer| peer.parse().map_err(|_| "could not parse peer address").unwrap())
+        .collect();
+    let addr = match bind::bind_addr(None, port) {
+        Ok(addr) => addr,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let blacklist = match Blacklist::parse(&env::var(BLACKLIST_VAR).unwrap_or_default()) {
+        Ok(blacklist) => blacklist,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+
+    let table = Table::new("notes", Rc::new(vec![
+        FieldSpec::new("note", TypeSpec::new(DbType::Varchar(1000), false, None)),
+    ]));
+    let snapshot_path = format!("node-{}.cluster", port);
+    let (cluster, known_peers) = match ClusterSnapshotData::load_from_path(&snapshot_path) {
+        Ok(snapshot) => {
+            println!("Restoring {} peers from {}", snapshot.peers.len(), snapshot_path);
+            (Cluster::from_snapshot(&snapshot), snapshot.peers)
+        }
+        Err(_) => (Cluster::new(addr.to_string()), vec![]),
+    };
+    let cluster_id = env::var(CLUSTER_ID_VAR).unwrap_or_else(|_| String::from(DEFAULT_CLUSTER_ID));
+    let cluster = cluster.with_local_addr(addr).with_blacklist(blacklist).with_cluster_id(cluster_id);
+    let cluster = Arc::new(Mutex::new(cluster));
+    let node = Rc::new(RefCell::new(Node::new(cluster, table)));
+
+    // The table can't leave this thread, so everything runs on it
+    let mut runtime = current_thread::Runtime::new().unwrap();
+
+    let events = node.borrow().cluster().lock().unwrap().events();
+    let listener = TcpListener::bind(&addr).map_err(|_| "failed to bind").unwrap();
+    println!("Listening on: {}", addr);
+    // Peers this node dials are connected to once, however many times
+    // they are asked for
+    let connections = PeerConnections::new();
+    let accepting = node.clone();
+    let accepted_connections = connections.clone();
+    let accept_events = events.clone();
+    runtime.spawn(listener.incoming()
+        .map_err(move |e| accept_events.on_error(&format!("error accepting socket; error = {:?}", e)))
+        .for_each(move |socket| {
+            // Dropping a refused socket closes it
+            let admitted = socket.peer_addr()
+                .map(|peer_addr| accepting.borrow().cluster().lock().unwrap().admit(&peer_addr))
+                .unwrap_or(false);
+            if !admitted {
+                return Ok(());
+            }
+            // Only the address an inbound peer connected from is known,
+            // which can't be dialed, so its connection isn't pooled
+            let _ = connect_peer(socket, accepting.clone(), accepted_connections.clone());
+            Ok(())
+        }));
+
+    for peer in peers {
+        runtime.spawn(dial_peer(peer, node.clone(), connections.clone()));
+    }
+    for peer in known_peers {
+        runtime.spawn(redial_peer(peer, node.clone(), connections.clone(), RECONNECT_ATTEMPTS));
+    }
+
+    let saving = node.borrow().cluster();
+    let (timer_events, save_events, insert_events) = (events.clone(), events.clone(), events);
+    runtime.spawn(Interval::new(Instant::now() + SNAPSHOT_INTERVAL, SNAPSHOT_INTERVAL)
+        .map_err(move |e| timer_events.on_error(&format!("snapshot timer failed; error = {:?}", e)))
+        .for_each(move |_| {
+            let snapshot = saving.lock().unwrap().snapshot();
+            if let Err(err) = snapshot.save_to_path(&snapshot_path) {
+                save_events.on_error(&format!("could not save {}; error = {}", snapshot_path, err));
+            }
+            Ok(())
+        }));
+
+    // Reading stdin blocks, so it gets its own thread
+    let (line_tx, line_rx) = mpsc::unbounded();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if line_tx.unbounded_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    runtime.spawn(line_rx.for_each(move |line| {
+        if let Err(err) = node.borrow_mut().insert(&Tuple::new().with(DBExternalString(line))) {
+            insert_events.on_error(&format!("could not insert note; error = {}", err));
+        }
+        Ok(())
+    }));
+
+    runtime.run().unwrap();
+}
+
+fn dial_peer(peer: SocketAddr, node: Rc<RefCell<Node>>, connections: PeerConnections)
+    -> impl Future<Item = (), Error = ()>
+{
+    let events = node.borrow().cluster().lock().unwrap().events();
+    let dialed = connections.clone();
+    connections.connect(peer, move |socket| connect_peer(socket, node, dialed))
+        .map(|_| ())
+        .map_err(move |e| events.on_error(&format!("could not connect to {}; error = {:?}", peer, e)))
+}
+
+// Dials a previously known peer, retrying a few times in case it is still
+// starting up, and gives up on it after the last attempt
+fn redial_peer(peer: SocketAddr, node: Rc<RefCell<Node>>, connections: PeerConnections, attempts: u32)
+    -> impl Future<Item = (), Error = ()>
+{
+    let events = node.borrow().cluster().lock().unwrap().events();
+    future::loop_fn(1, move |attempt| {
+        let node = node.clone();
+        let events = events.clone();
+        let dialed = connections.clone();
+        connections.connect(peer, move |socket| connect_peer(socket, node, dialed))
+            .then(move |result| -> Box<dyn Future<Item = future::Loop<(), u32>, Error = ()>> {
+                match result {
+                    Ok(_) => Box::new(future::ok(future::Loop::Break(()))),
+                    Err(_) if attempt < attempts => Box::new(Delay::new(Instant::now() + RECONNECT_DELAY)
+                        .map(move |_| future::Loop::Continue(attempt + 1))
+                        .map_err(move |e| events.on_error(&format!("reconnect timer failed; error = {:?}", e)))),
+                    Err(e) => {
+                        events.on_error(&format!("dropping unreachable peer {}; error = {:?}", peer, e));
+                        Box::new(future::ok(future::Loop::Break(())))
+                    }
+                }
+            })
+    })
+}
+
+// Registers a connected peer and starts the tasks that read from and write
+// to it, returning the channel its messages are queued on
+fn connect_peer(socket: TcpStream, node: Rc<RefCell<Node>>, connections: PeerConnections) -> io::Result<Tx> {
+    let peer_addr = socket.peer_addr()?;
+    let (read_half, write_half) = socket.split();
+
+    let (tx, rx) = peer_channel();
+    let cluster = node.borrow().cluster();
+    let events = cluster.lock().unwrap().events();
+    let read_events = events.clone();
+    let reader_connections = connections.clone();
+    cluster.lock().unwrap().add_peer(peer_addr, tx.clone());
+    // A fresh channel always has room. Anything this node missed while
+    // the two were apart is asked for straight away.
+    cluster.lock().unwrap().send_handshake(peer_addr).unwrap();
+    cluster.lock().unwrap().send_sync_request(peer_addr).unwrap();
+    current_thread::spawn(writer::write_to_peer(
+        peer_addr,
+        rx,
+        FramedWrite::new(write_half, LengthDelimitedCodec::new()),
+        cluster,
+        DEFAULT_SEND_TIMEOUT,
+    ));
+
+    let envelopes = wire::read_envelopes(read_half);
+    let limit_metrics = node.borrow().cluster().lock().unwrap().metrics();
+    let mut limiter = RateLimiter::new(RateLimit::default(), Instant::now());
+    let idle_timer = Arc::new(Mutex::new(IdleTimer::new(DEFAULT_IDLE_TIMEOUT, Instant::now())));
+    let watch = idle::watch_idle(peer_addr, idle_timer.clone(), node.borrow().cluster(), IDLE_CHECK_INTERVAL);
+    current_thread::spawn(envelopes
+        .for_each(move |envelope| {
+            idle_timer.lock().unwrap().touch(Instant::now());
+            match limiter.check(Instant::now()) {
+                Verdict::Allow => (),
+                Verdict::Drop => {
+                    limit_metrics.record_rate_limited();
+                    return Ok(());
+                }
+                Verdict::Disconnect => {
+                    limit_metrics.record_rate_limited();
+                    node.borrow().cluster().lock().unwrap().evict(&peer_addr);
+                    // Ending the stream closes the connection
+                    return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                        "peer exceeded its rate limit").into());
+                }
+            }
+            // Handshakes are recorded for this connection, and sync and
+            // status requests answered, rather than delivered
+            let delivered = node.borrow_mut().receive_from(peer_addr, envelope);
+            // A handshake from another cluster, or anything sent before the
+            // peer's handshake, drops the peer
+            if !node.borrow().cluster().lock().unwrap().peers_tx.contains_key(&peer_addr) {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                    "peer is no longer connected").into());
+            }
+            for (envelope, applied) in delivered {
+                // Delivery itself was already reported by the cluster
+                if let Err(err) = applied {
+                    events.on_error(&format!("could not apply {:?}; error = {}", envelope.message, err));
+                }
+                // Joins pointing back at this node or at a connected peer
+                // are ignored rather than dialed
+                if let Message::JoinClusterMsg(ref join) = envelope.message {
+                    let target = node.borrow().cluster().lock().unwrap().join_target(join);
+                    if let Some(target) = target {
+                        current_thread::spawn(dial_peer(target, node.clone(), connections.clone()));
+                    }
+                }
+            }
+            Ok(())
+        })
+        .map_err(move |e| read_events.on_error(&format!("connection to {} failed; error = {:?}", peer_addr, e)))
+        // Closing an idle connection drops its reader
+        .select(watch)
+        // The pool's sender would keep the writer running
+        .then(move |_| {
+            reader_connections.remove(&peer_addr);
+            Ok(())
+        }));
+    Ok(tx)
+}
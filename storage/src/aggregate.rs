@@ -0,0 +1,170 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::db_value::{DbValue, DBFloat64, DBUInt32, DBUInt64};
+use crate::{DbType, Table, TableError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AggKind {
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+impl Table {
+    // Combines a UInt32, UInt64 or Float64 field's values across the live
+    // rows, skipping NULLs. Min and Max keep the field's type, and Avg is a
+    // Float64. The Sum of an integer field is always a UInt64, and fails
+    // rather than wrapping; a Float64 field's Sum is a Float64. Min, Max and
+    // Avg of a field with no values fail; its Sum is 0.
+    pub fn aggregate(&self, field_name: &str, agg: AggKind) -> Result<Box<dyn DbValue>, TableError> {
+        let field_index = self.field_index(field_name)?;
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        if *db_type == DbType::Float64 {
+            return self.aggregate_floats(field_name, field_index, agg);
+        }
+        if !matches!(*db_type, DbType::UInt32 | DbType::UInt64) {
+            return Err(TableError::UnsupportedType(format!("{:?}", db_type)));
+        }
+        let size = db_type.size();
+        let mut values = self.live_field_bytes(field_index).map(|bytes| LittleEndian::read_uint(bytes, size));
+
+        let no_values = || TableError::NoValues(field_name.to_string());
+        let extreme = match agg {
+            AggKind::Sum => {
+                let sum = values.try_fold(0u64, |sum, value| sum.checked_add(value))
+                    .ok_or_else(|| TableError::Overflow(field_name.to_string()))?;
+                return Ok(Box::new(DBUInt64(sum)));
+            }
+            AggKind::Avg => {
+                // Every u64 sum of at most u64::MAX values fits a u128
+                let (sum, count) = values.fold((0u128, 0u64), |(sum, count), value| (sum + value as u128, count + 1));
+                if count == 0 {
+                    return Err(no_values());
+                }
+                return Ok(Box::new(DBFloat64(sum as f64 / count as f64)));
+            }
+            AggKind::Min => values.min().ok_or_else(no_values)?,
+            AggKind::Max => values.max().ok_or_else(no_values)?,
+        };
+        Ok(match *db_type {
+            DbType::UInt32 => Box::new(DBUInt32(extreme as u32)),
+            _ => Box::new(DBUInt64(extreme)),
+        })
+    }
+
+    // NaNs are skipped by Min and Max, and make the Sum and Avg NaN
+    fn aggregate_floats(&self, field_name: &str, field_index: usize, agg: AggKind) -> Result<Box<dyn DbValue>, TableError> {
+        let values = self.live_field_bytes(field_index).map(LittleEndian::read_f64);
+        let no_values = || TableError::NoValues(field_name.to_string());
+        let result = match agg {
+            AggKind::Sum => values.fold(0.0, |sum, value| sum + value),
+            AggKind::Avg => {
+                let (sum, count) = values.fold((0.0, 0u64), |(sum, count), value| (sum + value, count + 1));
+                if count == 0 {
+                    return Err(no_values());
+                }
+                sum / count as f64
+            }
+            AggKind::Min => values.reduce(f64::min).ok_or_else(no_values)?,
+            AggKind::Max => values.reduce(f64::max).ok_or_else(no_values)?,
+        };
+        Ok(Box::new(DBFloat64(result)))
+    }
+
+    // The bytes of a field in each live row where it isn't NULL
+    fn live_field_bytes(&self, field_index: usize) -> impl Iterator<Item = &[u8]> {
+        let offset = self.field_offset(field_index);
+        (0..self.row_count())
+            .filter(move |&index| !self.tombstones[index])
+            .map(move |index| self.row(index))
+            .filter(move |row| !self.field_is_null(row, field_index))
+            .map(move |row| &row[offset..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::DBExternalString;
+    use crate::{FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn scores() -> Table {
+        let mut table = Table::new("scores", Rc::new(vec![
+            FieldSpec::new("points", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("total", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("player", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        for &(points, total) in &[(Some(7), 1), (Some(42), u64::MAX), (None, 0), (Some(u32::MAX), 2), (Some(3), 3)] {
+            let tuple = Tuple::new();
+            let tuple = match points {
+                Some(points) => tuple.with(DBUInt32(points)),
+                None => tuple.with_null(),
+            };
+            table.insert(&tuple.with(DBUInt64(total)).with(DBExternalString("someone".to_string()))).unwrap();
+        }
+        table.delete(3).unwrap();
+        table
+    }
+
+    #[test]
+    fn sum_and_max_of_uint32() {
+        let table = scores();
+        let sum = table.aggregate("points", AggKind::Sum).unwrap();
+        assert_eq!(DbType::UInt64, sum.db_type());
+        assert_eq!("52", sum.to_display_string());
+        let max = table.aggregate("points", AggKind::Max).unwrap();
+        assert_eq!(DbType::UInt32, max.db_type());
+        assert_eq!("42", max.to_display_string());
+        assert_eq!("3", table.aggregate("points", AggKind::Min).unwrap().to_display_string());
+        let avg = table.aggregate("points", AggKind::Avg).unwrap();
+        assert_eq!(DbType::Float64, avg.db_type());
+        assert_eq!("17.333333333333332", avg.to_display_string());
+    }
+
+    #[test]
+    fn float64_columns_aggregate_as_floats() {
+        let mut table = Table::new("readings", Rc::new(vec![
+            FieldSpec::new("celsius", TypeSpec::new(DbType::Float64, true, None)),
+        ]));
+        for &celsius in &[Some(-2.5), None, Some(10.0), Some(4.0)] {
+            table.insert(&match celsius {
+                Some(celsius) => Tuple::new().with(DBFloat64(celsius)),
+                None => Tuple::new().with_null(),
+            }).unwrap();
+        }
+
+        let sum = table.aggregate("celsius", AggKind::Sum).unwrap();
+        assert_eq!(DbType::Float64, sum.db_type());
+        assert_eq!("11.5", sum.to_display_string());
+        assert_eq!("-2.5", table.aggregate("celsius", AggKind::Min).unwrap().to_display_string());
+        assert_eq!("10", table.aggregate("celsius", AggKind::Max).unwrap().to_display_string());
+        let avg = table.aggregate("celsius", AggKind::Avg).unwrap();
+        assert_eq!(DbType::Float64, avg.db_type());
+        assert!(avg.eq_dyn(&DBFloat64(11.5 / 3.0)));
+
+        for index in 0..table.row_count() {
+            table.delete(index).unwrap();
+        }
+        assert_eq!("0", table.aggregate("celsius", AggKind::Sum).unwrap().to_display_string());
+        assert_eq!(Err(TableError::NoValues("celsius".to_string())),
+            table.aggregate("celsius", AggKind::Max).map(|max| max.to_display_string()));
+    }
+
+    #[test]
+    fn overflow_and_unsupported_columns_are_errors() {
+        let mut table = scores();
+        assert_eq!(Err(TableError::Overflow("total".to_string())),
+            table.aggregate("total", AggKind::Sum).map(|sum| sum.to_display_string()));
+        assert_eq!(Err(TableError::UnsupportedType("Varchar(1000)".to_string())),
+            table.aggregate("player", AggKind::Max).map(|max| max.to_display_string()));
+
+        for index in 0..table.row_count() {
+            table.delete(index).unwrap();
+        }
+        assert_eq!("0", table.aggregate("points", AggKind::Sum).unwrap().to_display_string());
+        assert_eq!(Err(TableError::NoValues("points".to_string())),
+            table.aggregate("points", AggKind::Avg).map(|avg| avg.to_display_string()));
+    }
+}
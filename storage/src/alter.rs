@@ -0,0 +1,159 @@
+use std::rc::Rc;
+
+use crate::{Schema, Table, TableError};
+
+impl Table {
+    // Removes a field from the schema and from every row, freeing any heap
+    // data the field referenced. The remaining fields keep their order.
+    // Rows taken with `share_rows` or `export_row` beforehand no longer
+    // match the table.
+    pub fn drop_column(&mut self, field_name: &str) -> Result<(), TableError> {
+        let dropped = self.field_index(field_name)?;
+        let primary_key = self.primary_key().map(|key| key.to_string());
+        if primary_key.as_deref() == Some(field_name) {
+            return Err(TableError::PrimaryKeyField(field_name.to_string()));
+        }
+
+        let mut schema: Schema = (*self.schema).clone();
+        schema.remove(dropped);
+        let mut narrowed = Table::new(self.name.clone(), Rc::new(schema));
+        if self.packed_booleans {
+            narrowed = narrowed.with_packed_booleans();
+        }
+
+        let mut rows = Vec::with_capacity(self.row_count() * narrowed.row_length());
+        let mut spans = vec![];
+        for index in 0..self.row_count() {
+            let old_row = self.row(index);
+            let mut row = vec![0u8; narrowed.row_length()];
+            for (new_index, field_index) in (0..self.schema.len()).filter(|&i| i != dropped).enumerate() {
+                if self.field_is_null(old_row, field_index) {
+                    let bit = narrowed.null_bit(new_index).unwrap();
+                    row[bit / 8] |= 1 << (bit % 8);
+                }
+                narrowed.put_field_bytes(&mut row, new_index, &self.field_bytes(old_row, field_index));
+            }
+            rows.extend_from_slice(&row);
+            spans.extend(self.heap_span(index, dropped));
+        }
+
+        // Rows only get shorter, so one write over the start of the old
+        // rows replaces them all
+        self.fixed_data.write_at(0, &rows)?;
+        self.fixed_data.truncate(rows.len());
+        for (offset, len) in spans {
+            self.variable_data.free(offset, len);
+        }
+        self.set_schema(narrowed.schema);
+        self.record_all_checksums();
+        self.stats = None;
+
+        // The key field may have moved
+        match primary_key {
+            Some(key) => self.set_primary_key(&key),
+            None => Ok(()),
+        }
+    }
+
+    // Gives a field a new name. Rows are laid out the same whatever fields
+    // are called, so only the schema changes.
+    pub fn rename_field(&mut self, old_name: &str, new_name: &str) -> Result<(), TableError> {
+        let field_index = self.field_index(old_name)?;
+        if old_name == new_name {
+            return Ok(());
+        }
+        if self.field_index(new_name).is_ok() {
+            return Err(TableError::FieldExists(new_name.to_string()));
+        }
+
+        let mut schema: Schema = (*self.schema).clone();
+        schema[field_index].name = new_name.to_string();
+        self.set_schema(Rc::new(schema));
+        for fields in self.reserved.values_mut() {
+            if fields.remove(old_name) {
+                fields.insert(new_name.to_string());
+            }
+        }
+        if let Some(ref mut stats) = self.stats {
+            for column in stats.columns.iter_mut().filter(|column| column.name == old_name) {
+                column.name = new_name.to_string();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBUInt32};
+    use crate::{DbType, FieldSpec, Tuple, TypeSpec, POINTER_SIZE};
+
+    fn people() -> Table {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(1)).with(DBExternalString("tall".to_string())).with(DBUInt32(30)))
+            .unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(2)).with_null().with_null()).unwrap();
+        table
+    }
+
+    #[test]
+    fn drop_middle_column() {
+        let mut table = people();
+        table.set_primary_key("age").unwrap();
+        table.drop_column("notes").unwrap();
+
+        assert_eq!(vec!["id", "age"], table.schema().iter().map(|field| field.name.as_str()).collect::<Vec<_>>());
+        assert_eq!(9, table.row_length());
+        assert_eq!(2, table.row_count());
+        assert_eq!("1", table.get_field(0, "id").unwrap().to_display_string());
+        assert_eq!("30", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!("2", table.get_field(1, "id").unwrap().to_display_string());
+        assert!(table.get_field(1, "age").unwrap().is_null());
+        assert!(table.get_field(0, "notes").is_err());
+        assert_eq!(POINTER_SIZE + 4, table.variable_data.free_bytes());
+
+        assert_eq!(Some("age"), table.primary_key());
+        assert_eq!(Err(TableError::DuplicateKey("age".to_string())),
+            table.insert(&Tuple::new().with(DBUInt32(3)).with(DBUInt32(30))));
+    }
+
+    #[test]
+    fn rename_keeps_values_and_key() {
+        let mut table = people();
+        table.set_primary_key("age").unwrap();
+        let schema = table.schema.clone();
+        table.rename_field("age", "years").unwrap();
+
+        assert!(table.get_field(0, "age").is_err());
+        assert_eq!("30", table.get_field(0, "years").unwrap().to_display_string());
+        assert_eq!(Some("years"), table.primary_key());
+        assert_eq!(Err(TableError::DuplicateKey("years".to_string())),
+            table.insert(&Tuple::new().with(DBUInt32(3)).with_null().with(DBUInt32(30))));
+        // Anything else sharing the old schema keeps its names
+        assert_eq!("age", schema[2].name);
+    }
+
+    #[test]
+    fn rename_rejects_unknown_and_taken_names() {
+        let mut table = people();
+        assert_eq!(Err(TableError::UnknownField("height".to_string())), table.rename_field("height", "size"));
+        assert_eq!(Err(TableError::FieldExists("id".to_string())), table.rename_field("age", "id"));
+        assert_eq!(Ok(()), table.rename_field("age", "age"));
+        assert_eq!("30", table.get_field(0, "age").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn key_column_cannot_be_dropped() {
+        let mut table = people();
+        table.set_primary_key("id").unwrap();
+
+        assert_eq!(Err(TableError::PrimaryKeyField("id".to_string())), table.drop_column("id"));
+        assert_eq!(3, table.schema().len());
+        assert_eq!("tall", table.get_field(0, "notes").unwrap().to_display_string());
+    }
+}
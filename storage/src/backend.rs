@@ -0,0 +1,165 @@
+use std::fmt;
+use std::io;
+
+// Byte storage underneath a table's fixed rows or its heap. Reads hand out
+// borrowed slices, so a backend must be able to expose its contents in
+// memory (an owned buffer or a memory map, for example). Writes can fail,
+// and those errors are passed back to the caller.
+pub trait Backend: fmt::Debug {
+    fn len(&self) -> usize;
+
+    // Panics if the range is out of bounds, like slice indexing
+    fn read_at(&self, offset: usize, len: usize) -> &[u8];
+
+    // Overwrites existing bytes; the range must already be in bounds
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()>;
+
+    // Adds bytes to the end and returns the offset they start at
+    fn append(&mut self, bytes: &[u8]) -> io::Result<usize>;
+
+    // Discards everything from `len` onwards
+    fn truncate(&mut self, len: usize);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Bytes the backend can hold without growing
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    // Releases any space held beyond `len`
+    fn shrink_to_fit(&mut self) {}
+}
+
+impl Backend for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn read_at(&self, offset: usize, len: usize) -> &[u8] {
+        &self[offset..(offset+len)]
+    }
+
+    fn write_at(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        if offset + bytes.len() > Vec::len(self) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Write of {} bytes at {} is out of bounds", bytes.len(), offset)));
+        }
+        self[offset..(offset+bytes.len())].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let prev_len = Vec::len(self);
+        self.extend_from_slice(bytes);
+        Ok(prev_len)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len);
+    }
+
+    fn capacity(&self) -> usize {
+        Vec::capacity(self)
+    }
+
+    fn shrink_to_fit(&mut self) {
+        Vec::shrink_to_fit(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DbHeap, DBExternalString, DBUInt32};
+    use crate::{DbType, FieldSpec, Schema, Table, TableError, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    // Accepts reads but refuses every write
+    #[derive(Debug, Default)]
+    struct ReadOnlyBackend(Vec<u8>);
+
+    impl Backend for ReadOnlyBackend {
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn read_at(&self, offset: usize, len: usize) -> &[u8] {
+            self.0.read_at(offset, len)
+        }
+
+        fn write_at(&mut self, _offset: usize, _bytes: &[u8]) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"))
+        }
+
+        fn append(&mut self, _bytes: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"))
+        }
+
+        fn truncate(&mut self, len: usize) {
+            self.0.truncate(len);
+        }
+    }
+
+    fn test_schema() -> Rc<Schema> {
+        Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ])
+    }
+
+    fn test_tuple() -> Tuple {
+        Tuple::new()
+            .with(DBUInt32(42))
+            .with(DBExternalString("stored on the heap".to_string()))
+    }
+
+    #[test]
+    fn vec_backend_roundtrip() {
+        let mut backend: Vec<u8> = vec![];
+        assert_eq!(0, Backend::append(&mut backend, &[1, 2, 3]).unwrap());
+        assert_eq!(3, Backend::append(&mut backend, &[4, 5]).unwrap());
+        backend.write_at(1, &[9, 9]).unwrap();
+
+        assert_eq!(5, Backend::len(&backend));
+        assert_eq!(&[1, 9, 9, 4, 5], backend.read_at(0, 5));
+        assert!(backend.write_at(4, &[0, 0]).is_err());
+    }
+
+    #[test]
+    fn table_roundtrip_with_explicit_backends() {
+        let mut table = Table::with_backends("people", test_schema(),
+            Box::new(Vec::new()), DbHeap::with_backend(Box::new(Vec::new())));
+        table.insert(&test_tuple()).unwrap();
+
+        assert_eq!("42", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!("stored on the heap", table.get_field(0, "notes").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn failing_fixed_backend_surfaces_error() {
+        let mut table = Table::with_backends("people", test_schema(),
+            Box::new(ReadOnlyBackend::default()), DbHeap::new());
+
+        match table.insert(&test_tuple()) {
+            Err(TableError::Io(_)) => (),
+            other => panic!("Expected an I/O error, got {:?}", other),
+        }
+        assert_eq!(0, table.row_count());
+        assert!(table.variable_data.is_empty());
+    }
+
+    #[test]
+    fn failing_heap_backend_surfaces_error() {
+        let mut table = Table::with_backends("people", test_schema(),
+            Box::new(Vec::new()), DbHeap::with_backend(Box::new(ReadOnlyBackend::default())));
+
+        match table.insert(&test_tuple()) {
+            Err(TableError::Value(_)) => (),
+            other => panic!("Expected a value error, got {:?}", other),
+        }
+        assert_eq!(0, table.row_count());
+    }
+}
@@ -0,0 +1,93 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{DbType, Table, TableError, POINTER_SIZE};
+
+impl Table {
+    // Adds bytes to the end of a blob field, for blobs that grow like logs.
+    // The blob grows where it is when the heap has room right after it, and
+    // is otherwise copied to the end of the heap with `extra` after it,
+    // freeing the old copy. A NULL blob becomes one holding just `extra`.
+    pub fn append_to_blob(&mut self, index: usize, field_name: &str, extra: &[u8]) -> Result<(), TableError> {
+        let field_index = self.field_index(field_name)?;
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        if *db_type != DbType::Blob {
+            return Err(TableError::UnsupportedType(format!("{:?}", db_type)));
+        }
+
+        let old_span = self.heap_span(index, field_index);
+        if let Some((heap_offset, _)) = old_span {
+            if self.variable_data.grow_entry(heap_offset, extra)? {
+                // Only the heap changed, but the checksum covers it
+                self.row_versions[index] += 1;
+                self.record_checksum(index);
+                self.stats = None;
+                return Ok(());
+            }
+        }
+
+        let mut entry = match old_span {
+            Some((heap_offset, len)) => self.variable_data.get_slice(heap_offset, len).to_vec(),
+            None => vec![0; POINTER_SIZE],
+        };
+        entry.extend_from_slice(extra);
+        let data_len = entry.len() - POINTER_SIZE;
+        LittleEndian::write_uint(&mut entry, data_len as u64, POINTER_SIZE);
+        let heap_len = self.variable_data.len();
+        let heap_offset = self.variable_data.append_data(&mut entry)?;
+
+        let mut row = self.row(index).to_vec();
+        if let Some(bit) = self.null_bit(field_index) {
+            row[bit / 8] &= !(1 << (bit % 8));
+        }
+        let offset = self.field_offset(field_index);
+        LittleEndian::write_uint(&mut row[offset..], heap_offset as u64, POINTER_SIZE);
+        self.overwrite_row(index, &row, heap_len, old_span.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::DBUInt32;
+    use crate::{FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn read_blob(table: &Table, index: usize) -> Vec<u8> {
+        let mut blob = vec![];
+        table.read_field_into(index, "log", &mut blob).unwrap();
+        blob
+    }
+
+    #[test]
+    fn appends_accumulate_in_the_blob() {
+        let mut table = Table::new("events", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("log", TypeSpec::new(DbType::Blob, true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(1)).with_null()).unwrap();
+
+        table.append_to_blob(0, "log", b"started;").unwrap();
+        let heap_offset = table.heap_offset_of(0, "log").unwrap();
+        table.append_to_blob(0, "log", b"stopped;").unwrap();
+        assert_eq!(b"started;stopped;".to_vec(), read_blob(&table, 0));
+        // The blob was last in the heap, so it grew where it was
+        assert_eq!(Some(heap_offset), table.heap_offset_of(0, "log"));
+        assert_eq!(POINTER_SIZE + 16, table.variable_data.len());
+        assert_eq!(2, table.row_version(0).unwrap());
+
+        // With another blob after it, it is moved to grow
+        table.insert(&Tuple::new().with(DBUInt32(2)).with_null()).unwrap();
+        table.append_to_blob(1, "log", b"other").unwrap();
+        table.append_to_blob(0, "log", b"started;").unwrap();
+        assert_eq!(b"started;stopped;started;".to_vec(), read_blob(&table, 0));
+        assert_eq!(b"other".to_vec(), read_blob(&table, 1));
+        assert_eq!(POINTER_SIZE + 16, table.variable_data.free_bytes());
+        assert!(table.verify_all().is_ok());
+
+        assert_eq!(Err(TableError::UnsupportedType("UInt32".to_string())), table.append_to_blob(0, "id", b"x"));
+        assert_eq!(Err(TableError::RowOutOfBounds(2)), table.append_to_blob(2, "log", b"x"));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::Table;
+
+impl Table {
+    // A CRC-32 of the row's fixed bytes with its heap references replaced by
+    // the data they point at, so it covers heap-backed values too and
+    // doesn't change when that data moves. Panics if the row does not
+    // exist, like slice indexing.
+    pub fn row_checksum(&self, index: usize) -> u32 {
+        crc32(&self.resolved_row(index))
+    }
+
+    // Recomputes every row's checksum, including deleted rows, and compares
+    // it with the one recorded when the row was last written or loaded.
+    // Returns the indices of the rows that no longer match.
+    pub fn verify_all(&self) -> Result<(), Vec<usize>> {
+        let corrupt: Vec<usize> = (0..self.row_count())
+            .filter(|&index| self.row_checksum(index) != self.checksums[index])
+            .collect();
+        if corrupt.is_empty() {
+            Ok(())
+        } else {
+            Err(corrupt)
+        }
+    }
+
+    // Records the checksum of a row that has just been written
+    pub(crate) fn record_checksum(&mut self, index: usize) {
+        let checksum = self.row_checksum(index);
+        if index == self.checksums.len() {
+            self.checksums.push(checksum);
+        } else {
+            self.checksums[index] = checksum;
+        }
+    }
+
+    pub(crate) fn record_all_checksums(&mut self) {
+        self.checksums = (0..self.row_count()).map(|index| self.row_checksum(index)).collect();
+    }
+}
+
+// CRC-32 (IEEE), bit by bit. Rows are short enough that a lookup table
+// isn't worth it.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBUInt32};
+    use crate::{DbType, FieldSpec, Table, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn test_table() -> Table {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        for age in 0..3 {
+            table.insert(&Tuple::new().with(DBUInt32(age)).with(DBExternalString(format!("note {}", age)))).unwrap();
+        }
+        table
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn flipped_byte_is_reported() {
+        let mut table = test_table();
+        table.update_field(2, "age", DBUInt32(40)).unwrap();
+        table.delete(0).unwrap();
+        table.compact_tombstones().unwrap();
+        assert_eq!(Ok(()), table.verify_all());
+
+        let offset = table.row_length() + table.field_offset(0);
+        let byte = table.fixed_data.read_at(offset, 1)[0];
+        table.fixed_data.write_at(offset, &[byte ^ 0x01]).unwrap();
+        assert_eq!(Err(vec![1]), table.verify_all());
+    }
+
+    #[test]
+    fn heap_corruption_is_reported() {
+        let mut table = test_table();
+        let (offset, _) = table.heap_span(1, 1).unwrap();
+        let last = offset + table.variable_data.get_prefixed_slice(offset).len() - 1;
+        table.variable_data.write_at(last, b"X").unwrap();
+
+        assert_eq!(Err(vec![1]), table.verify_all());
+    }
+}
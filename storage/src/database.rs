@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::db_value::NullableValue;
+use crate::{Schema, Table, TableError, Tuple};
+
+// A set of tables, each with a unique name
+#[derive(Debug, Default)]
+pub struct Database {
+    tables: HashMap<String, Table>,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            tables: HashMap::new(),
+        }
+    }
+
+    pub fn create_table<S>(&mut self, name: S, schema: Rc<Schema>) -> Result<&mut Table, TableError>
+        where S: Into<String>
+    {
+        let name = name.into();
+        if self.tables.contains_key(&name) {
+            return Err(TableError::TableExists(name));
+        }
+        let table = Table::new(name.clone(), schema);
+        table.checked_row_length()?;
+        Ok(self.tables.entry(name).or_insert(table))
+    }
+
+    pub fn get_table(&self, name: &str) -> Option<&Table> {
+        self.tables.get(name)
+    }
+
+    pub fn get_table_mut(&mut self, name: &str) -> Option<&mut Table> {
+        self.tables.get_mut(name)
+    }
+
+    // Removes a table and hands it back
+    pub fn drop_table(&mut self, name: &str) -> Result<Table, TableError> {
+        self.tables.remove(name).ok_or_else(|| TableError::UnknownTable(name.to_string()))
+    }
+
+    // Every table name, sorted
+    pub fn table_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn insert(&mut self, table_name: &str, tuple: &Tuple) -> Result<usize, TableError> {
+        self.table_mut(table_name)?.insert(tuple)
+    }
+
+    pub fn get_field(&self, table_name: &str, index: usize, field_name: &str) -> Result<NullableValue, TableError> {
+        self.table(table_name)?.get_field(index, field_name)
+    }
+
+    pub fn drop_column(&mut self, table_name: &str, field_name: &str) -> Result<(), TableError> {
+        self.table_mut(table_name)?.drop_column(field_name)
+    }
+
+    fn table(&self, name: &str) -> Result<&Table, TableError> {
+        self.get_table(name).ok_or_else(|| TableError::UnknownTable(name.to_string()))
+    }
+
+    fn table_mut(&mut self, name: &str) -> Result<&mut Table, TableError> {
+        self.get_table_mut(name).ok_or_else(|| TableError::UnknownTable(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::DBUInt32;
+    use crate::{DbType, FieldSpec, TypeSpec};
+
+    fn test_schema() -> Rc<Schema> {
+        Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+        ])
+    }
+
+    #[test]
+    fn create_list_and_drop_tables() {
+        let mut db = Database::new();
+        db.create_table("users", test_schema()).unwrap();
+        db.create_table("orders", test_schema()).unwrap();
+        assert_eq!(vec!["orders", "users"], db.table_names());
+
+        db.insert("users", &Tuple::new().with(DBUInt32(7))).unwrap();
+        db.drop_table("orders").unwrap();
+
+        assert_eq!(vec!["users"], db.table_names());
+        assert!(db.get_table("orders").is_none());
+        assert_eq!(Err(TableError::UnknownTable("orders".to_string())),
+            db.insert("orders", &Tuple::new().with(DBUInt32(1))));
+        assert_eq!("7", db.get_field("users", 0, "id").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn table_names_are_unique() {
+        let mut db = Database::new();
+        db.create_table("users", test_schema()).unwrap();
+        db.insert("users", &Tuple::new().with(DBUInt32(7))).unwrap();
+
+        match db.create_table("users", test_schema()) {
+            Err(TableError::TableExists(name)) => assert_eq!("users", name),
+            other => panic!("Expected a duplicate table error, got {:?}", other),
+        }
+        assert_eq!(1, db.get_table("users").unwrap().row_count());
+    }
+}
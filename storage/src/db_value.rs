@@ -1,58 +1,463 @@
 use byteorder::{ByteOrder, LittleEndian};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
 use std::ptr;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::ops::Deref;
+use std::str::FromStr;
 
-#[cfg(target_pointer_width = "64")]
-const POINTER_SIZE: usize = 8;
-#[cfg(target_pointer_width = "32")]
-const POINTER_SIZE: usize = 4;
+use crate::backend::Backend;
+use crate::{DbType, POINTER_SIZE};
+
+// Largest piece of a heap entry handed to a writer at once
+const READ_CHUNK_SIZE: usize = 8 * 1024;
 
 #[derive(Debug)]
-struct DbHeap {
-    buf: Vec<u8>,
+pub struct DbHeap {
+    buf: Box<dyn Backend>,
+    // (offset, len) spans that are no longer referenced by any row
+    free_list: Vec<(usize, usize)>,
+    // Every appended entry starts at a multiple of this
+    alignment: usize,
 }
 
 impl DbHeap {
 
-    fn new() -> Self {
-        DbHeap {
-            buf: vec![]
-        }
+    pub fn new() -> Self {
+        DbHeap::from_vec(vec![])
     }
 
     // Create a buffer with an initial size for its internal
     // byte buffer.
-    fn new_sized(size: usize) -> Self {
+    pub fn new_sized(size: usize) -> Self {
+        DbHeap::from_vec(Vec::with_capacity(size))
+    }
+
+    // Wraps bytes that were previously taken from a heap
+    pub fn from_vec(buf: Vec<u8>) -> Self {
+        DbHeap::with_backend(Box::new(buf))
+    }
+
+    pub fn with_backend(backend: Box<dyn Backend>) -> Self {
         DbHeap {
-            buf: Vec::with_capacity(size)
+            buf: backend,
+            free_list: vec![],
+            alignment: 1,
         }
     }
 
+    // Pads the heap before each append so entries start at multiples of
+    // `alignment`. Only affects later appends; the alignment isn't persisted,
+    // so a loaded heap must be given it again.
+    pub fn with_alignment(mut self, alignment: usize) -> Self {
+        assert!(alignment > 0, "heap alignment must be at least 1");
+        self.alignment = alignment;
+        self
+    }
+
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    // Where an entry appended to a heap of `len` bytes would start
+    pub(crate) fn aligned(&self, len: usize) -> usize {
+        len.next_multiple_of(self.alignment)
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    // Releases spare capacity and forgets every freed span. Meant for after
+    // a vacuum has moved the live data over the freed spans, as
+    // `Table::shrink_to_fit` does.
+    pub fn shrink_to_fit(&mut self) {
+        self.buf.shrink_to_fit();
+        self.free_list.clear();
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.buf.read_at(0, self.buf.len())
+    }
+
     // Adds data to internal memory and returns the starting offset
     // at which the data resides
-    fn append_data(&mut self, data: &mut Vec<u8>) -> usize {
+    pub fn append_data(&mut self, data: &mut Vec<u8>) -> io::Result<usize> {
         let prev_len = self.buf.len();
-        self.buf.append(data);
+        let start = self.aligned(prev_len);
+        if start > prev_len {
+            self.buf.append(&vec![0; start - prev_len])?;
+        }
+        if let Err(err) = self.buf.append(data) {
+            self.buf.truncate(prev_len);
+            return Err(err);
+        }
+        data.clear();
+
+        Ok(start)
+    }
+
+    // Discards everything from `len` onwards, undoing any appends made
+    // since the heap was that long
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+        self.free_list.retain(|&(offset, _)| offset < len);
+    }
+
+    // Overwrites bytes already in the heap
+    pub(crate) fn write_at(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        self.buf.write_at(offset, bytes)
+    }
+
+    // Records that a span is no longer referenced. The space is not reused
+    // or reclaimed yet, only accounted for.
+    pub fn free(&mut self, offset: usize, len: usize) {
+        self.free_list.push((offset, len));
+    }
+
+    // Total size of the spans that have been freed
+    pub fn free_bytes(&self) -> usize {
+        self.free_list.iter().map(|&(_, len)| len).sum()
+    }
+
+    // Moves live entries down over the lowest freed span, about `max_bytes`
+    // of them, calling `relocate(old_offset, new_offset)` for each entry
+    // moved so whatever points at it can follow. Returns whether freed space
+    // is left, so a loop can compact the heap a little at a time. At least
+    // one entry is moved when there is one, even if it is larger than
+    // `max_bytes`, so every step makes progress. Freed space at the end of
+    // the heap is reclaimed by truncating it.
+    //
+    // The bytes between freed spans must be length-prefixed entries laid out
+    // at the heap's alignment, so a loaded heap needs its alignment again.
+    pub fn defragment_step<F>(&mut self, max_bytes: usize, mut relocate: F) -> io::Result<bool>
+        where F: FnMut(usize, usize)
+    {
+        self.merge_free_spans();
+        let (start, len) = match self.free_list.first() {
+            Some(&span) => span,
+            None => return Ok(false),
+        };
+        // Where the live entries after the span stop
+        let end = self.free_list.get(1).map(|&(offset, _)| offset).unwrap_or_else(|| self.buf.len());
+
+        // `cursor` is where the next entry moves to, `next` where the last
+        // one considered ended
+        let (mut cursor, mut next, mut moved) = (start, start + len, 0);
+        loop {
+            let old_offset = self.aligned(next);
+            if old_offset >= end {
+                break;
+            }
+            let entry = self.get_prefixed_slice(old_offset).to_vec();
+            if moved > 0 && moved + entry.len() > max_bytes {
+                break;
+            }
+            let new_offset = self.aligned(cursor);
+            if new_offset != old_offset {
+                self.buf.write_at(new_offset, &entry)?;
+                relocate(old_offset, new_offset);
+            }
+            cursor = new_offset + entry.len();
+            next = old_offset + entry.len();
+            moved += entry.len();
+        }
+
+        if self.aligned(next) < end {
+            self.free_list[0] = (cursor, next - cursor);
+        } else if end == self.buf.len() {
+            // The span starts before `cursor`, so truncating would keep it
+            self.free_list.remove(0);
+            self.truncate(cursor);
+        } else {
+            // Runs into the next span, which it is merged with next time
+            self.free_list[0] = (cursor, end - cursor);
+        }
+        self.free_list.retain(|&(_, len)| len > 0);
+        Ok(!self.free_list.is_empty())
+    }
+
+    // Adds `extra` to the end of the length-prefixed entry at `offset` without
+    // moving it, if that fits: when the entry is the last in the heap, or is
+    // followed by a freed span with room for it. Returns whether it did.
+    pub(crate) fn grow_entry(&mut self, offset: usize, extra: &[u8]) -> io::Result<bool> {
+        let entry_len = self.get_prefixed_slice(offset).len();
+        let end = offset + entry_len;
+        if end == self.buf.len() {
+            self.buf.append(extra)?;
+        } else {
+            self.merge_free_spans();
+            let span = self.free_list.iter().position(|&(start, len)| start == end && len >= extra.len());
+            let span = match span {
+                Some(span) => span,
+                None => return Ok(false),
+            };
+            self.buf.write_at(end, extra)?;
+            let (start, len) = self.free_list[span];
+            self.free_list[span] = (start + extra.len(), len - extra.len());
+            self.free_list.retain(|&(_, len)| len > 0);
+        }
+
+        let mut prefix = [0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut prefix, (entry_len - POINTER_SIZE + extra.len()) as u64, POINTER_SIZE);
+        self.buf.write_at(offset, &prefix)?;
+        Ok(true)
+    }
+
+    // Sorts the freed spans and joins those that touch or overlap
+    fn merge_free_spans(&mut self) {
+        self.free_list.sort();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(self.free_list.len());
+        for &(offset, len) in &self.free_list {
+            match merged.last_mut() {
+                Some(last) if last.0 + last.1 >= offset => last.1 = last.1.max(offset + len - last.0),
+                _ => merged.push((offset, len)),
+            }
+        }
+        self.free_list = merged;
+    }
+
+    pub fn get_slice(&self, offset: usize, len: usize) -> &[u8] {
+        self.buf.read_at(offset, len)
+    }
+
+    // Borrows the text of the length-prefixed string entry at `offset`
+    // without copying it. Fails if the entry isn't valid UTF-8.
+    pub fn get_str(&self, offset: usize) -> Result<HeapStr<'_>, String> {
+        let data = &self.get_prefixed_slice(offset)[POINTER_SIZE..];
+        std::str::from_utf8(data)
+            .map(HeapStr)
+            .map_err(|err| format!("Heap entry at {} is not a valid string: {}", offset, err))
+    }
+
+    // Returns the length-prefixed data that starts at `offset`, including
+    // its prefix
+    pub fn get_prefixed_slice(&self, offset: usize) -> &[u8] {
+        let len = LittleEndian::read_uint(self.get_slice(offset, POINTER_SIZE), POINTER_SIZE) as usize;
+        self.get_slice(offset, POINTER_SIZE + len)
+    }
+
+    // Copies the data of the length-prefixed entry at `offset` to a writer,
+    // a chunk at a time, and returns how many bytes were written. Nothing
+    // is copied into an intermediate buffer, so large values can go straight
+    // to a file or socket.
+    pub fn read_into<W: Write>(&self, offset: usize, writer: &mut W) -> io::Result<usize> {
+        let data = &self.get_prefixed_slice(offset)[POINTER_SIZE..];
+        for chunk in data.chunks(READ_CHUNK_SIZE) {
+            writer.write_all(chunk)?;
+        }
+        Ok(data.len())
+    }
+}
+
+impl Default for DbHeap {
+    fn default() -> Self {
+        DbHeap::new()
+    }
+}
+
+// Somewhere values can find the heap data their fields point to. Reading
+// only needs the bytes, so a heap doesn't have to be owned to be read: a
+// memory-mapped file can be read in place through a `SliceHeap`.
+pub trait HeapSource {
+    fn len(&self) -> usize;
 
-        prev_len
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Panics if the span runs past the end
+    fn get_slice(&self, offset: usize, len: usize) -> &[u8];
+
+    // Returns the length-prefixed data that starts at `offset`, including
+    // its prefix
+    fn get_prefixed_slice(&self, offset: usize) -> &[u8] {
+        let len = LittleEndian::read_uint(self.get_slice(offset, POINTER_SIZE), POINTER_SIZE) as usize;
+        self.get_slice(offset, POINTER_SIZE + len)
+    }
+
+    // The data of the length-prefixed entry at `offset`, without its
+    // prefix. Unlike `get_prefixed_slice`, an entry running past the end of
+    // the heap is an error rather than a panic.
+    fn checked_prefixed_data(&self, offset: usize) -> Result<&[u8], String> {
+        let data_start_offset = offset.checked_add(POINTER_SIZE)
+            .filter(|&data_start_offset| data_start_offset <= self.len())
+            .ok_or_else(|| format!("Heap offset {} is out of bounds", offset))?;
+        let size = LittleEndian::read_uint(self.get_slice(offset, POINTER_SIZE), POINTER_SIZE) as usize;
+        if size > self.len() - data_start_offset {
+            return Err(format!("Heap entry at {} of {} bytes runs past the end of the heap", offset, size));
+        }
+        Ok(self.get_slice(data_start_offset, size))
+    }
+}
+
+impl HeapSource for DbHeap {
+    fn len(&self) -> usize {
+        DbHeap::len(self)
+    }
+
+    fn get_slice(&self, offset: usize, len: usize) -> &[u8] {
+        DbHeap::get_slice(self, offset, len)
+    }
+}
+
+// Heap data borrowed from elsewhere, such as a memory-mapped file
+#[derive(Clone, Copy, Debug)]
+pub struct SliceHeap<'a>(pub &'a [u8]);
+
+impl<'a> HeapSource for SliceHeap<'a> {
+    fn len(&self) -> usize {
+        self.0.len()
     }
 
     fn get_slice(&self, offset: usize, len: usize) -> &[u8] {
-        &self.buf[offset..(offset+len)]
+        &self.0[offset..(offset + len)]
     }
 }
 
-trait DbValue {
+pub trait DbValue: fmt::Debug {
     fn size(&self) -> usize;
-    fn read_from_buffer(&mut self, buf: &[u8], heap: &DbHeap);
-    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap);
+    fn read_from_buffer(&mut self, buf: &[u8], heap: &dyn HeapSource) -> Result<(), String>;
+    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<(), String>;
+    fn to_display_string(&self) -> String;
+    // The column type this value is stored as
+    fn db_type(&self) -> DbType;
+
+    // Like `write_to_buffer`, also saying how many bytes went where, so a
+    // writer streaming values out can keep track of its offsets
+    fn write_counted(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<Written, String> {
+        let heap_len = heap.len();
+        self.write_to_buffer(buf, heap)?;
+        Ok(Written { fixed: self.db_type().size(), heap: heap.len() - heap_len })
+    }
+
+    // The value as JSON. Numbers and booleans map to their JSON types and
+    // everything else to its display string, unless the type says otherwise.
+    fn to_json(&self) -> Value {
+        Value::String(self.to_display_string())
+    }
+
+    // Whether two values are the same, regardless of their concrete types.
+    // Values of the same type are equal when they display the same, and
+    // strings are compared by their text however they are stored.
+    fn eq_dyn(&self, other: &dyn DbValue) -> bool {
+        let (db_type, other_type) = (self.db_type(), other.db_type());
+        (db_type == other_type || (db_type.is_string() && other_type.is_string()))
+            && self.to_display_string() == other.to_display_string()
+    }
+
+    // Converts an integer value to an integer type at least as wide
+    fn try_widen(&self, target: &DbType) -> Result<Box<dyn DbValue>, String> {
+        if target.size() < self.size() {
+            return Err(format!("{:?} is narrower than {:?}", target, self.db_type()));
+        }
+        convert_integer(self, target)
+    }
+
+    // Converts an integer value to an integer type no wider than its own,
+    // failing if the value is out of the target's range
+    fn try_narrow(&self, target: &DbType) -> Result<Box<dyn DbValue>, String> {
+        if target.size() > self.size() {
+            return Err(format!("{:?} is wider than {:?}", target, self.db_type()));
+        }
+        convert_integer(self, target)
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct DBUInt64(u64);
+fn convert_integer<V: DbValue + ?Sized>(value: &V, target: &DbType) -> Result<Box<dyn DbValue>, String> {
+    let db_type = value.db_type();
+    if !matches!(db_type, DbType::UInt32 | DbType::UInt64) {
+        return Err(format!("{:?} is not an integer type", db_type));
+    }
+    let mut buf = vec![0u8; value.size()];
+    value.write_to_buffer(&mut buf, &mut DbHeap::new())?;
+    let n = LittleEndian::read_uint(&buf, buf.len());
+
+    match *target {
+        DbType::UInt32 if n > u64::from(u32::MAX) => Err(format!("{} overflows {:?}", n, target)),
+        DbType::UInt32 => Ok(Box::new(DBUInt32(n as u32))),
+        DbType::UInt64 => Ok(Box::new(DBUInt64(n))),
+        _ => Err(format!("{:?} is not an integer type", target)),
+    }
+}
+
+// What `DbValue::write_counted` wrote: `fixed` bytes at the start of the
+// buffer it was given, and `heap` bytes appended to the heap, length
+// prefixes included
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Written {
+    pub fixed: usize,
+    pub heap: usize,
+}
+
+// A field value that may be NULL
+#[derive(Debug)]
+pub struct NullableValue(pub Option<Box<dyn DbValue>>);
+
+impl NullableValue {
+    pub fn new(value: Box<dyn DbValue>) -> Self {
+        NullableValue(Some(value))
+    }
+
+    pub fn null() -> Self {
+        NullableValue(None)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn value(&self) -> Option<&dyn DbValue> {
+        self.0.as_ref().map(|value| value.as_ref())
+    }
+
+    pub fn to_display_string(&self) -> String {
+        match self.0 {
+            Some(ref value) => value.to_display_string(),
+            None => "NULL".to_string(),
+        }
+    }
+
+    // Whether two values are the same, as `DbValue::eq_dyn` decides. NULL is
+    // never equal to anything, another NULL included, as in SQL; keys treat
+    // NULLs as equal instead, see `Table::key_encode`.
+    pub fn eq_dyn(&self, other: &NullableValue) -> bool {
+        match (self.value(), other.value()) {
+            (Some(value), Some(other)) => value.eq_dyn(other),
+            _ => false,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self.0 {
+            Some(ref value) => value.to_json(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<V> From<V> for NullableValue where V: DbValue + 'static {
+    fn from(value: V) -> Self {
+        NullableValue::new(Box::new(value))
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBUInt64(pub u64);
 
 impl DBUInt64 {
-    fn new() -> Self {
+    pub fn new() -> Self {
         DBUInt64(0)
     }
 }
@@ -70,20 +475,34 @@ impl DbValue for DBUInt64 {
         8
     }
 
-    fn read_from_buffer(&mut self, buf: &[u8], _heap: & DbHeap) {
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
         self.0 = LittleEndian::read_u64(buf);
+        Ok(())
     }
 
-    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) {
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
         LittleEndian::write_u64(buf, self.0);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::UInt64
+    }
+
+    fn to_json(&self) -> Value {
+        Value::from(self.0)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct DBUInt32(u32);
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBUInt32(pub u32);
 
 impl DBUInt32 {
-    fn new() -> Self {
+    pub fn new() -> Self {
         DBUInt32(0)
     }
 }
@@ -101,20 +520,80 @@ impl DbValue for DBUInt32 {
         4
     }
 
-    fn read_from_buffer(&mut self, buf: &[u8], _heap: &DbHeap) {
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
         self.0 = LittleEndian::read_u32(buf);
+        Ok(())
     }
 
-    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) {
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
         LittleEndian::write_u32(buf, self.0);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::UInt32
+    }
+
+    fn to_json(&self) -> Value {
+        Value::from(self.0)
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct DBBoolean(bool);
+#[derive(Debug, Default, PartialEq)]
+pub struct DBFloat64(pub f64);
+
+impl DBFloat64 {
+    pub fn new() -> Self {
+        DBFloat64(0.0)
+    }
+}
+
+impl Deref for DBFloat64 {
+    type Target = f64;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DbValue for DBFloat64 {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        self.0 = LittleEndian::read_f64(buf);
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
+        LittleEndian::write_f64(buf, self.0);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Float64
+    }
+
+    // NaN and the infinities have no JSON number, so they become null
+    fn to_json(&self) -> Value {
+        Value::from(self.0)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBBoolean(pub bool);
 
 impl DBBoolean {
-    fn new() -> Self {
+    pub fn new() -> Self {
         DBBoolean(false)
     }
 }
@@ -124,16 +603,30 @@ impl DbValue for DBBoolean {
         1
     }
 
-    fn read_from_buffer(&mut self, buf: &[u8], _heap: &DbHeap) {
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
         self.0 = buf[0] == 1;
+        Ok(())
     }
 
-    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) {
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
         buf[0] = if self.0 {
             1
         } else {
             0
         };
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Boolean
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Bool(self.0)
     }
 }
 
@@ -145,11 +638,11 @@ impl Deref for DBBoolean {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct DBInlineString(String);
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBInlineString(pub String);
 
 impl DBInlineString {
-    fn new() -> Self {
+    pub fn new() -> Self {
         DBInlineString("".to_string())
     }
 }
@@ -159,20 +652,39 @@ impl DbValue for DBInlineString {
         1 + self.0.len()
     }
 
-    fn read_from_buffer(&mut self, buf: &[u8], _heap: &DbHeap) {
-        let size = buf[0];
-        let data = &buf[1..(size as usize + 1)];
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        let size = *buf.first().ok_or("Empty buffer for an inline string")? as usize;
+        // An empty string is only its length byte
+        if size == 0 {
+            self.0.clear();
+            return Ok(());
+        }
+        let data = buf.get(1..(size + 1))
+            .ok_or_else(|| format!("String of {} bytes does not fit in buffer of length {}", size, buf.len()))?;
         self.0 = String::from_utf8_lossy(data).to_string();
+        Ok(())
     }
 
-    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) {
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
         let data_size = self.0.len();
+        if data_size > u8::MAX as usize || data_size >= buf.len() {
+            return Err(format!("String of {} bytes does not fit in buffer of length {}", data_size, buf.len()));
+        }
         let (size_buf, data_buf) = buf.split_at_mut(1);
-        let src_ptr = self.0.as_bytes().as_ptr() as *const u8;
+        let src_ptr = self.0.as_bytes().as_ptr();
         size_buf[0] = data_size as u8;
         unsafe {
             ptr::copy(src_ptr, data_buf.as_mut_ptr(), data_size);
         }
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.clone()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Varchar(self.0.len())
     }
 }
 
@@ -184,11 +696,11 @@ impl Deref for DBInlineString {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct DBExternalString (String);
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBExternalString(pub String);
 
 impl DBExternalString {
-    fn new() -> Self {
+    pub fn new() -> Self {
         DBExternalString("".to_string())
     }
 }
@@ -198,34 +710,54 @@ impl DbValue for DBExternalString {
         std::mem::size_of::<usize>() + self.0.len()
     }
 
-    #[cfg(target_pointer_width = "64")]
-    fn read_from_buffer(&mut self, buf: &[u8], heap: &DbHeap) {
-        let offset = LittleEndian::read_u64(buf) as usize;
-        let data_start_offset = offset + 8;
-        let size = LittleEndian::read_u64(heap.get_slice(offset, 8));
-        let data = heap.get_slice(data_start_offset, size as usize);
-        self.0 = String::from_utf8_lossy(data).to_string();
-    }
-
-    #[cfg(target_pointer_width = "32")]
-    fn read_from_buffer(&mut self, buf: &[u8], heap: &DbHeap) {
-        let offset = LittleEndian::read_u32(buf) as usize;
-        let data_start_offset = offset + 4;
-        let size = LittleEndian::read_u32(heap.get_slice(offset, 4));
-        let data = heap.get_slice(data_start_offset, size as usize);
+    // The entry is checked against the end of the heap before it is read,
+    // so a bad offset is an error rather than a panic
+    fn read_from_buffer(&mut self, buf: &[u8], heap: &dyn HeapSource) -> Result<(), String> {
+        let offset = LittleEndian::read_uint(buf, POINTER_SIZE) as usize;
+        let data_start_offset = offset.checked_add(POINTER_SIZE)
+            .filter(|&data_start_offset| data_start_offset <= heap.len())
+            .ok_or_else(|| format!("Heap offset {} is out of bounds", offset))?;
+        let size = LittleEndian::read_uint(heap.get_slice(offset, POINTER_SIZE), POINTER_SIZE) as usize;
+        // An empty string is only its length prefix, which may end the heap
+        if size == 0 {
+            self.0.clear();
+            return Ok(());
+        }
+        if size > heap.len() - data_start_offset {
+            return Err(format!("Heap entry at {} of {} bytes runs past the end of the heap", offset, size));
+        }
+        let data = heap.get_slice(data_start_offset, size);
         self.0 = String::from_utf8_lossy(data).to_string();
+        Ok(())
     }
 
     #[cfg(target_pointer_width = "64")]
-    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap) {
+    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<(), String> {
         let mut size_buf: [u8; 8] = [0; 8];
         LittleEndian::write_u64(&mut size_buf, self.0.len() as u64);
         let mut len_prefixed_string = vec![];
-        len_prefixed_string.extend_from_slice(&mut size_buf);
+        len_prefixed_string.extend_from_slice(&size_buf);
         len_prefixed_string.extend_from_slice(self.0.clone().as_bytes());
 
-        let offset = heap.append_data(&mut len_prefixed_string);
+        let offset = heap.append_data(&mut len_prefixed_string).map_err(|err| err.to_string())?;
         LittleEndian::write_u64(buf, offset as u64);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.clone()
+    }
+
+    fn db_type(&self) -> DbType {
+        // Shorter lengths would describe an inline string
+        DbType::Varchar(self.0.len().max(256))
+    }
+
+    // Only the pointer is written to the buffer, not the column's full slot
+    fn write_counted(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<Written, String> {
+        let heap_len = heap.len();
+        self.write_to_buffer(buf, heap)?;
+        Ok(Written { fixed: POINTER_SIZE, heap: heap.len() - heap_len })
     }
 }
 
@@ -237,84 +769,1012 @@ impl Deref for DBExternalString {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// A string in a `LongVarchar` column, stored in the row behind a u16
+// length so strings too long for a one-byte length still avoid the heap
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBLongString(pub String);
 
-    #[test]
-    fn uint64_serialize() {
-        let mut heap_unused = DbHeap::new();
+impl DBLongString {
+    pub fn new() -> Self {
+        DBLongString("".to_string())
+    }
+}
 
-        let test_cases: Vec<u64> = vec![0, u64::max_value(), 4538756723];
-        for x in test_cases {
-            let val = DBUInt64(x);
-            let mut new_val = DBUInt64::new();
-            let mut buf = [0u8; 8];
+impl DbValue for DBLongString {
+    fn size(&self) -> usize {
+        2 + self.0.len()
+    }
 
-            val.write_to_buffer(&mut buf, &mut heap_unused);
-            new_val.read_from_buffer(&buf, &heap_unused);
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        let size = LittleEndian::read_u16(buf) as usize;
+        if 2 + size > buf.len() {
+            return Err(format!("String of {} bytes does not fit in buffer of length {}", size, buf.len()));
+        }
+        self.0 = String::from_utf8_lossy(&buf[2..(2+size)]).to_string();
+        Ok(())
+    }
 
-            assert_eq!(val, new_val);
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
+        let data_size = self.0.len();
+        if data_size > u16::MAX as usize || 2 + data_size > buf.len() {
+            return Err(format!("String of {} bytes does not fit in buffer of length {}", data_size, buf.len()));
         }
+        LittleEndian::write_u16(buf, data_size as u16);
+        buf[2..(2+data_size)].copy_from_slice(self.0.as_bytes());
+        Ok(())
     }
 
-    #[test]
-    fn uint32_serialize() {
-        let mut heap_unused = DbHeap::new();
+    fn to_display_string(&self) -> String {
+        self.0.clone()
+    }
 
-        let test_cases: Vec<u32> = vec![0, u32::max_value(), 4538756];
-        for x in test_cases {
-            let val = DBUInt32(x);
-            let mut new_val = DBUInt32::new();
-            let mut buf = [0u8; 4];
+    fn db_type(&self) -> DbType {
+        DbType::LongVarchar(self.0.len())
+    }
+}
 
-            val.write_to_buffer(&mut buf, &mut heap_unused);
-            new_val.read_from_buffer(&buf, &heap_unused);
+impl Deref for DBLongString {
+    type Target = str;
 
-            assert_eq!(val, new_val);
-        }
+    fn deref(&self) -> &Self::Target {
+        self.0.as_str()
     }
+}
 
-    #[test]
-    fn boolean_serialize() {
-        let mut heap_unused = DbHeap::new();
+// A string read straight out of a heap's buffer rather than copied into a
+// `String`. It borrows the heap, so the heap (and the table holding it)
+// can't be changed while it is alive; the compiler enforces this, and a
+// caller that needs the text past the next write must copy it out with
+// `to_string`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapStr<'a>(&'a str);
+
+impl<'a> HeapStr<'a> {
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
 
-        let test_cases: Vec<bool> = vec![true, false];
-        for p in test_cases {
-            let val = DBBoolean(p);
-            let mut new_val = DBBoolean::new();
-            let mut buf = [0u8; 1];
+impl<'a> Deref for HeapStr<'a> {
+    type Target = str;
 
-            val.write_to_buffer(&mut buf, &mut heap_unused);
-            new_val.read_from_buffer(&buf, &heap_unused);
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
 
-            assert_eq!(val, new_val);
-        }
+impl<'a> fmt::Display for HeapStr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
     }
+}
 
-    #[test]
-    fn inline_string_serialize() {
-        let mut heap_unused = DbHeap::new();
+// The tag byte that starts an adaptive Varchar field
+pub(crate) const VARCHAR_INLINE: u8 = 0;
+pub(crate) const VARCHAR_SPILLED: u8 = 1;
 
-        let test_cases: Vec<String> = vec![
-            "".to_string(),
-            " ".to_string(),
-            "abc123!@#\n\t".to_string(),
-            "Infinite Taco -> ∞ 🌮".to_string(),
-        ];
-        for s in test_cases {
-            let val = DBInlineString(s);
-            let mut new_val = DBInlineString::new();
-            let mut buf = [0u8; 32];
+// A string in an `AdaptiveVarchar` column, which is stored in the row when
+// it fits and on the heap when it doesn't. The field starts with a tag
+// byte; inline strings follow it with a length byte and their data, and
+// spilled ones with the heap offset of their length-prefixed data.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBVarchar(pub String);
 
-            val.write_to_buffer(&mut buf, &mut heap_unused);
-            new_val.read_from_buffer(&buf, &heap_unused);
+impl DBVarchar {
+    pub fn new() -> Self {
+        DBVarchar("".to_string())
+    }
+}
 
-            assert_eq!(val, new_val);
-        }
+impl DbValue for DBVarchar {
+    fn size(&self) -> usize {
+        2 + self.0.len()
     }
 
-    #[test]
+    fn read_from_buffer(&mut self, buf: &[u8], heap: &dyn HeapSource) -> Result<(), String> {
+        if buf.len() < 1 + POINTER_SIZE {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        let data = match buf[0] {
+            VARCHAR_INLINE => {
+                let size = buf[1] as usize;
+                if 2 + size > buf.len() {
+                    return Err(format!("Inline string of {} bytes overruns buffer of length {}", size, buf.len()));
+                }
+                &buf[2..(2+size)]
+            }
+            VARCHAR_SPILLED => heap.checked_prefixed_data(LittleEndian::read_uint(&buf[1..], POINTER_SIZE) as usize)?,
+            tag => return Err(format!("Invalid string tag: {}", tag)),
+        };
+        self.0 = String::from_utf8_lossy(data).to_string();
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<(), String> {
+        if buf.len() < 1 + POINTER_SIZE {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        let data_size = self.0.len();
+        if data_size <= buf.len() - 2 && data_size <= u8::MAX as usize {
+            buf[0] = VARCHAR_INLINE;
+            buf[1] = data_size as u8;
+            buf[2..(2+data_size)].copy_from_slice(self.0.as_bytes());
+            return Ok(());
+        }
+
+        let mut len_prefixed_string = vec![0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut len_prefixed_string, data_size as u64, POINTER_SIZE);
+        len_prefixed_string.extend_from_slice(self.0.as_bytes());
+        let offset = heap.append_data(&mut len_prefixed_string).map_err(|err| err.to_string())?;
+        buf[0] = VARCHAR_SPILLED;
+        LittleEndian::write_uint(&mut buf[1..], offset as u64, POINTER_SIZE);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.clone()
+    }
+
+    fn db_type(&self) -> DbType {
+        // The smallest column that can hold this value
+        DbType::AdaptiveVarchar { max_len: self.0.len(), inline_len: 0 }
+    }
+
+    // The tag, then either the string or a pointer to it
+    fn write_counted(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<Written, String> {
+        let heap_len = heap.len();
+        self.write_to_buffer(buf, heap)?;
+        let heap = heap.len() - heap_len;
+        let fixed = if buf[0] == VARCHAR_INLINE { 2 + self.0.len() } else { 1 + POINTER_SIZE };
+        Ok(Written { fixed, heap })
+    }
+}
+
+impl Deref for DBVarchar {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_str()
+    }
+}
+
+// Fixed length binary data stored inline, e.g. hashes or keys. The buffer
+// given for reads and writes must be exactly as long as the column.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBBytes(pub Vec<u8>);
+
+impl DBBytes {
+    pub fn new() -> Self {
+        DBBytes(vec![])
+    }
+}
+
+impl DbValue for DBBytes {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        self.0 = buf.to_vec();
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
+        if self.0.len() != buf.len() {
+            return Err(format!("Invalid data length: expected {} bytes, got {}", buf.len(), self.0.len()));
+        }
+        buf.copy_from_slice(&self.0);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Bytes(self.0.len())
+    }
+
+    // Base64, as JSON has no binary type
+    fn to_json(&self) -> Value {
+        Value::String(base64(&self.0))
+    }
+}
+
+impl Deref for DBBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_slice()
+    }
+}
+
+// Any serde value, kept on the heap as its bincode encoding, for trying out
+// column types that have no value type of their own yet. The bytes are
+// stored and read back as they are; only `from_value` and `to_value` know
+// what they encode.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBSerialized(pub Vec<u8>);
+
+impl DBSerialized {
+    pub fn new() -> Self {
+        DBSerialized(vec![])
+    }
+
+    pub fn from_value<T: Serialize>(value: &T) -> Result<Self, String> {
+        bincode::serialize(value).map(DBSerialized).map_err(|err| err.to_string())
+    }
+
+    // Fails if the bytes don't decode as a `T`
+    pub fn to_value<T: DeserializeOwned>(&self) -> Result<T, String> {
+        bincode::deserialize(&self.0).map_err(|err| err.to_string())
+    }
+}
+
+impl DbValue for DBSerialized {
+    fn size(&self) -> usize {
+        POINTER_SIZE + self.0.len()
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], heap: &dyn HeapSource) -> Result<(), String> {
+        if buf.len() < POINTER_SIZE {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        self.0 = heap.checked_prefixed_data(LittleEndian::read_uint(buf, POINTER_SIZE) as usize)?.to_vec();
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<(), String> {
+        let mut entry = vec![0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut entry, self.0.len() as u64, POINTER_SIZE);
+        entry.extend_from_slice(&self.0);
+        let offset = heap.append_data(&mut entry).map_err(|err| err.to_string())?;
+        LittleEndian::write_uint(buf, offset as u64, POINTER_SIZE);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Serialized
+    }
+
+    fn to_json(&self) -> Value {
+        Value::String(base64(&self.0))
+    }
+}
+
+// Standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// An IPv4 or IPv6 address stored inline as a tag byte (4 or 6) followed by
+// 16 bytes of address, with IPv4 addresses zero-padded
+#[derive(Debug, PartialEq, Eq)]
+pub struct DBIpAddr(pub IpAddr);
+
+impl DBIpAddr {
+    pub fn new() -> Self {
+        DBIpAddr(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    }
+}
+
+impl Default for DBIpAddr {
+    fn default() -> Self {
+        DBIpAddr::new()
+    }
+}
+
+impl DbValue for DBIpAddr {
+    fn size(&self) -> usize {
+        17
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        if buf.len() < 17 {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        self.0 = match buf[0] {
+            4 => {
+                let mut octets = [0u8; 4];
+                octets.copy_from_slice(&buf[1..5]);
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            6 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&buf[1..17]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            tag => return Err(format!("Invalid address tag: {}", tag)),
+        };
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
+        if buf.len() < 17 {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        for b in buf[1..17].iter_mut() {
+            *b = 0;
+        }
+        match self.0 {
+            IpAddr::V4(addr) => {
+                buf[0] = 4;
+                buf[1..5].copy_from_slice(&addr.octets());
+            }
+            IpAddr::V6(addr) => {
+                buf[0] = 6;
+                buf[1..17].copy_from_slice(&addr.octets());
+            }
+        }
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::IpAddr
+    }
+}
+
+impl FromStr for DBIpAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<IpAddr>()
+            .map(DBIpAddr)
+            .map_err(|_| format!("Invalid IP address: {}", s))
+    }
+}
+
+impl fmt::Display for DBIpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Deref for DBIpAddr {
+    type Target = IpAddr;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// A single character, stored inline as its Unicode scalar value
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBChar(pub char);
+
+impl DBChar {
+    pub fn new() -> Self {
+        DBChar('\0')
+    }
+}
+
+impl DbValue for DBChar {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        if buf.len() < 4 {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        let scalar = LittleEndian::read_u32(buf);
+        self.0 = std::char::from_u32(scalar)
+            .ok_or_else(|| format!("Invalid Unicode scalar value: {:#x}", scalar))?;
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
+        if buf.len() < 4 {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        LittleEndian::write_u32(buf, self.0 as u32);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Char
+    }
+}
+
+impl Deref for DBChar {
+    type Target = char;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// An amount of money in minor units, scaled by `10^scale`, with a
+// three-letter currency code. Stored inline as the i64 amount followed by
+// the code's 3 bytes. Written as e.g. "USD 12.34".
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DBMoney {
+    pub amount: i64,
+    pub currency: [u8; 3],
+    pub scale: u8,
+}
+
+impl DBMoney {
+    pub fn new() -> Self {
+        DBMoney::default()
+    }
+
+    // An empty value for a column with the given scale
+    pub(crate) fn with_type_scale(scale: u8) -> Self {
+        DBMoney { scale, ..DBMoney::default() }
+    }
+
+    // The same amount expressed with a different number of decimal places.
+    // Fails if that would lose digits or overflow.
+    pub fn with_scale(&self, scale: u8) -> Result<DBMoney, String> {
+        let amount = if scale >= self.scale {
+            10i64.checked_pow(u32::from(scale - self.scale))
+                .and_then(|factor| self.amount.checked_mul(factor))
+        } else {
+            10i64.checked_pow(u32::from(self.scale - scale))
+                .filter(|factor| self.amount % factor == 0)
+                .map(|factor| self.amount / factor)
+        };
+        let amount = amount.ok_or_else(|| format!("{} cannot be written with {} decimal places", self, scale))?;
+        Ok(DBMoney { amount, currency: self.currency, scale })
+    }
+}
+
+fn is_currency_code(code: &[u8]) -> bool {
+    code.len() == 3 && code.iter().all(|b| b.is_ascii_uppercase())
+}
+
+impl DbValue for DBMoney {
+    fn size(&self) -> usize {
+        11
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], _heap: &dyn HeapSource) -> Result<(), String> {
+        if buf.len() < 11 {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        self.amount = LittleEndian::read_i64(buf);
+        self.currency.copy_from_slice(&buf[8..11]);
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], _heap: &mut DbHeap) -> Result<(), String> {
+        if buf.len() < 11 {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        if !is_currency_code(&self.currency) {
+            return Err(format!("Invalid currency code: {}", String::from_utf8_lossy(&self.currency)));
+        }
+        LittleEndian::write_i64(buf, self.amount);
+        buf[8..11].copy_from_slice(&self.currency);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        self.to_string()
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Money { scale: self.scale }
+    }
+}
+
+// The scale is the number of digits after the decimal point, if any
+impl FromStr for DBMoney {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid money value: {}", s);
+        let mut parts = s.splitn(2, ' ');
+        let code = parts.next().unwrap_or("").to_ascii_uppercase();
+        let amount = parts.next().ok_or_else(invalid)?;
+        if !is_currency_code(code.as_bytes()) {
+            return Err(format!("Invalid currency code: {}", code));
+        }
+
+        let (negative, digits) = match amount.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, amount),
+        };
+        let (whole, fraction) = match digits.find('.') {
+            Some(point) => (&digits[..point], &digits[point + 1..]),
+            None => (digits, ""),
+        };
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) || (digits.contains('.') && fraction.is_empty()) {
+            return Err(invalid());
+        }
+        let minor: i64 = format!("{}{}{}", if negative { "-" } else { "" }, whole, fraction)
+            .parse()
+            .map_err(|_| invalid())?;
+
+        let mut currency = [0u8; 3];
+        currency.copy_from_slice(code.as_bytes());
+        Ok(DBMoney {
+            amount: minor,
+            currency,
+            scale: fraction.len() as u8,
+        })
+    }
+}
+
+impl fmt::Display for DBMoney {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = format!("{:0width$}", self.amount.unsigned_abs(), width = usize::from(self.scale) + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - usize::from(self.scale));
+        write!(f, "{} {}{}", String::from_utf8_lossy(&self.currency), if self.amount < 0 { "-" } else { "" }, whole)?;
+        if !fraction.is_empty() {
+            write!(f, ".{}", fraction)?;
+        }
+        Ok(())
+    }
+}
+
+// A list of values of one fixed-width type, stored on the heap as a
+// length prefix followed by each element's bytes in turn. The row holds the
+// heap offset.
+#[derive(Debug)]
+pub struct DBArray {
+    pub element_type: DbType,
+    // The number of elements every array in the column has, if fixed
+    pub fixed_len: Option<usize>,
+    pub values: Vec<Box<dyn DbValue>>,
+}
+
+impl DBArray {
+    pub fn new(element_type: DbType, values: Vec<Box<dyn DbValue>>) -> Self {
+        DBArray {
+            element_type,
+            fixed_len: None,
+            values,
+        }
+    }
+
+    pub fn with_fixed_len(mut self, len: usize) -> Self {
+        self.fixed_len = Some(len);
+        self
+    }
+}
+
+// Whether an array may hold elements of this type. Only types that are
+// always stored inline at a fixed, nonzero width are supported.
+pub fn is_array_element(db_type: &DbType) -> bool {
+    !db_type.is_string() && !db_type.is_external() && db_type.new_value().is_some()
+        && !matches!(*db_type, DbType::Array(..)) && db_type.size() > 0
+}
+
+impl DbValue for DBArray {
+    fn size(&self) -> usize {
+        POINTER_SIZE + self.values.len() * self.element_type.size()
+    }
+
+    fn read_from_buffer(&mut self, buf: &[u8], heap: &dyn HeapSource) -> Result<(), String> {
+        if !is_array_element(&self.element_type) {
+            return Err(format!("Arrays of {:?} are not supported", self.element_type));
+        }
+        if buf.len() < POINTER_SIZE {
+            return Err(format!("Invalid buffer length: {}", buf.len()));
+        }
+        let data = heap.checked_prefixed_data(LittleEndian::read_uint(buf, POINTER_SIZE) as usize)?;
+        let element_size = self.element_type.size();
+        if data.len() % element_size != 0 {
+            return Err(format!("Array data of {} bytes is not a whole number of {:?} elements", data.len(), self.element_type));
+        }
+        if let Some(len) = self.fixed_len {
+            if data.len() / element_size != len {
+                return Err(format!("Expected an array of {} elements, got {}", len, data.len() / element_size));
+            }
+        }
+        let mut values = vec![];
+        for chunk in data.chunks(element_size) {
+            let mut value = self.element_type.new_value().unwrap();
+            value.read_from_buffer(chunk, heap)?;
+            values.push(value);
+        }
+        self.values = values;
+        Ok(())
+    }
+
+    fn write_to_buffer(&self, buf: &mut [u8], heap: &mut DbHeap) -> Result<(), String> {
+        if !is_array_element(&self.element_type) {
+            return Err(format!("Arrays of {:?} are not supported", self.element_type));
+        }
+        if let Some(len) = self.fixed_len {
+            if self.values.len() != len {
+                return Err(format!("Expected an array of {} elements, got {}", len, self.values.len()));
+            }
+        }
+
+        let element_size = self.element_type.size();
+        let mut entry = vec![0u8; POINTER_SIZE + self.values.len() * element_size];
+        LittleEndian::write_uint(&mut entry, (self.values.len() * element_size) as u64, POINTER_SIZE);
+        for (value, chunk) in self.values.iter().zip(entry[POINTER_SIZE..].chunks_mut(element_size)) {
+            if value.db_type() != self.element_type {
+                return Err(format!("Array of {:?} cannot hold a {:?}", self.element_type, value.db_type()));
+            }
+            value.write_to_buffer(chunk, heap)?;
+        }
+
+        let offset = heap.append_data(&mut entry).map_err(|err| err.to_string())?;
+        LittleEndian::write_uint(buf, offset as u64, POINTER_SIZE);
+        Ok(())
+    }
+
+    fn to_display_string(&self) -> String {
+        let values: Vec<String> = self.values.iter().map(|value| value.to_display_string()).collect();
+        format!("[{}]", values.join(", "))
+    }
+
+    fn db_type(&self) -> DbType {
+        DbType::Array(Box::new(self.element_type.clone()), self.fixed_len)
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Array(self.values.iter().map(|value| value.to_json()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_convert_to_json() {
+        assert_eq!(Value::from(u64::MAX), DBUInt64(u64::MAX).to_json());
+        assert_eq!(Value::Bool(true), DBBoolean(true).to_json());
+        assert_eq!(Value::from("USD 12.34"), "USD 12.34".parse::<DBMoney>().unwrap().to_json());
+        assert_eq!(Value::Null, NullableValue::null().to_json());
+
+        for &(bytes, encoded) in &[(&b""[..], ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"),
+                                   (b"\xff\xfe\xfd\xfc", "//79/A==")] {
+            assert_eq!(Value::from(encoded), DBBytes(bytes.to_vec()).to_json());
+        }
+
+        let array = DBArray::new(DbType::UInt32, vec![Box::new(DBUInt32(1)), Box::new(DBUInt32(2))]);
+        assert_eq!(Value::from(vec![1, 2]), array.to_json());
+    }
+
+    #[test]
+    fn money_roundtrips_through_text_and_buffer() {
+        for &(text, scale) in &[("USD 12.34", 2), ("JPY 100", 0), ("EUR -0.05", 2)] {
+            let money: DBMoney = text.parse().unwrap();
+            assert_eq!(DbType::Money { scale }, money.db_type());
+            assert_eq!(text, money.to_display_string());
+
+            let mut buf = [0u8; 11];
+            money.write_to_buffer(&mut buf, &mut DbHeap::new()).unwrap();
+            let mut read = DBMoney::with_type_scale(scale);
+            read.read_from_buffer(&buf, &DbHeap::new()).unwrap();
+            assert_eq!(money, read);
+        }
+        assert_eq!(1234, "USD 12.34".parse::<DBMoney>().unwrap().amount);
+        assert_eq!("USD 12.340", "USD 12.34".parse::<DBMoney>().unwrap().with_scale(3).unwrap().to_string());
+        assert!("USD 12.34".parse::<DBMoney>().unwrap().with_scale(1).is_err());
+    }
+
+    #[test]
+    fn money_rejects_invalid_currency() {
+        assert_eq!(Err("Invalid currency code: US1".to_string()), "US1 5.00".parse::<DBMoney>());
+        assert!("USD 5.".parse::<DBMoney>().is_err());
+        assert!("USD".parse::<DBMoney>().is_err());
+
+        let money = DBMoney { amount: 500, currency: *b"US1", scale: 2 };
+        assert!(money.write_to_buffer(&mut [0u8; 11], &mut DbHeap::new()).is_err());
+    }
+
+    fn roundtrip_long_string(len: usize) -> DBLongString {
+        let text: String = (0..len).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let mut buf = vec![0xffu8; DbType::LongVarchar(len).size()];
+        DBLongString(text.clone()).write_to_buffer(&mut buf, &mut DbHeap::new()).unwrap();
+        assert_eq!(len, LittleEndian::read_u16(&buf) as usize);
+
+        let mut read = DBLongString::new();
+        read.read_from_buffer(&buf, &DbHeap::new()).unwrap();
+        assert_eq!(text, read.0);
+        read
+    }
+
+    #[test]
+    fn long_strings_roundtrip_with_two_byte_length() {
+        assert_eq!(DbType::LongVarchar(300), roundtrip_long_string(300).db_type());
+        assert_eq!(DbType::LongVarchar(60000), roundtrip_long_string(60000).db_type());
+
+        let too_long = DBLongString("x".repeat(70000));
+        assert!(too_long.write_to_buffer(&mut vec![0u8; 70002], &mut DbHeap::new()).is_err());
+        assert!(DBLongString("abc".to_string()).write_to_buffer(&mut [0u8; 4], &mut DbHeap::new()).is_err());
+    }
+
+    #[test]
+    fn eq_dyn_compares_across_types() {
+        assert!(DBUInt32(5).eq_dyn(&DBUInt32(5)));
+        assert!(!DBUInt32(5).eq_dyn(&DBUInt32(6)));
+        assert!(!DBUInt32(5).eq_dyn(&DBUInt64(5)));
+        assert!(DBInlineString("abc".to_string()).eq_dyn(&DBExternalString("abc".to_string())));
+        assert!(!DBInlineString("5".to_string()).eq_dyn(&DBUInt32(5)));
+    }
+
+    #[test]
+    fn integers_convert_between_widths() {
+        let widened = DBUInt32(5).try_widen(&DbType::UInt64).unwrap();
+        assert_eq!(DbType::UInt64, widened.db_type());
+        assert_eq!("5", widened.to_display_string());
+
+        let narrowed = DBUInt64(7).try_narrow(&DbType::UInt32).unwrap();
+        assert_eq!(DbType::UInt32, narrowed.db_type());
+        assert_eq!("7", narrowed.to_display_string());
+
+        let overflow = DBUInt64(1 << 40).try_narrow(&DbType::UInt32).unwrap_err();
+        assert_eq!(format!("{} overflows UInt32", 1u64 << 40), overflow);
+        assert!(DBUInt64(5).try_widen(&DbType::UInt32).is_err());
+        assert!(DBBoolean(true).try_widen(&DbType::UInt64).is_err());
+    }
+
+    fn roundtrip_array(array: &DBArray) -> Result<DBArray, String> {
+        let mut heap = DbHeap::new();
+        let mut buf = [0u8; POINTER_SIZE];
+        array.write_to_buffer(&mut buf, &mut heap)?;
+
+        let mut read = DBArray::new(array.element_type.clone(), vec![]);
+        read.read_from_buffer(&buf, &heap)?;
+        Ok(read)
+    }
+
+    #[test]
+    fn arrays_roundtrip() {
+        let empty = roundtrip_array(&DBArray::new(DbType::UInt32, vec![])).unwrap();
+        assert!(empty.values.is_empty());
+        assert_eq!("[]", empty.to_display_string());
+
+        let values: Vec<Box<dyn DbValue>> = vec![Box::new(DBUInt32(1)), Box::new(DBUInt32(20)), Box::new(DBUInt32(300))];
+        let read = roundtrip_array(&DBArray::new(DbType::UInt32, values)).unwrap();
+        assert_eq!(3, read.values.len());
+        assert_eq!("[1, 20, 300]", read.to_display_string());
+        assert_eq!(DbType::UInt32, read.values[2].db_type());
+    }
+
+    #[test]
+    fn arrays_reject_wrong_elements() {
+        let mixed = DBArray::new(DbType::UInt32, vec![Box::new(DBUInt32(1)), Box::new(DBUInt64(2))]);
+        assert_eq!(Err("Array of UInt32 cannot hold a UInt64".to_string()), roundtrip_array(&mixed).map(|_| ()));
+
+        let strings = DBArray::new(DbType::Varchar(10), vec![Box::new(DBInlineString("a".to_string()))]);
+        assert!(roundtrip_array(&strings).is_err());
+
+        let short = DBArray::new(DbType::Boolean, vec![Box::new(DBBoolean(true))]).with_fixed_len(2);
+        assert!(roundtrip_array(&short).is_err());
+
+        // Elements without bytes can't be told apart in the heap entry
+        let empty_bytes = DBArray::new(DbType::Bytes(0), vec![Box::new(DBBytes(vec![]))]);
+        assert_eq!(Err("Arrays of Bytes(0) are not supported".to_string()), roundtrip_array(&empty_bytes).map(|_| ()));
+        let mut read = DBArray::new(DbType::Bytes(0), vec![]);
+        assert!(read.read_from_buffer(&[0; POINTER_SIZE], &DbHeap::new()).is_err());
+    }
+
+    #[test]
+    fn arrays_reject_corrupt_heap_entries() {
+        let mut heap = DbHeap::new();
+        let mut buf = [0u8; POINTER_SIZE];
+        let values: Vec<Box<dyn DbValue>> = vec![Box::new(DBUInt32(1)), Box::new(DBUInt32(2))];
+        DBArray::new(DbType::UInt32, values).write_to_buffer(&mut buf, &mut heap).unwrap();
+        let mut read = DBArray::new(DbType::UInt32, vec![]);
+
+        assert!(read.read_from_buffer(&buf[..3], &heap).is_err());
+        // Two elements where the column holds three
+        let mut fixed = DBArray::new(DbType::UInt32, vec![]).with_fixed_len(3);
+        assert_eq!(Err("Expected an array of 3 elements, got 2".to_string()), fixed.read_from_buffer(&buf, &heap));
+        // Data that isn't a whole number of elements
+        let mut partial = vec![0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut partial, 6, POINTER_SIZE);
+        partial.extend_from_slice(&[0; 6]);
+        let offset = heap.append_data(&mut partial).unwrap();
+        LittleEndian::write_uint(&mut buf, offset as u64, POINTER_SIZE);
+        assert!(read.read_from_buffer(&buf, &heap).is_err());
+        LittleEndian::write_uint(&mut buf, heap.len() as u64 + 1, POINTER_SIZE);
+        assert!(read.read_from_buffer(&buf, &heap).is_err());
+    }
+
+    #[test]
+    fn heap_entry_streams_into_writer() {
+        let blob: Vec<u8> = (0..3 * READ_CHUNK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        let mut entry = vec![0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut entry, blob.len() as u64, POINTER_SIZE);
+        entry.extend_from_slice(&blob);
+
+        let mut heap = DbHeap::new();
+        heap.append_data(&mut vec![1, 2, 3]).unwrap();
+        let offset = heap.append_data(&mut entry).unwrap();
+
+        let mut out = vec![];
+        assert_eq!(blob.len(), heap.read_into(offset, &mut out).unwrap());
+        assert_eq!(blob, out);
+    }
+
+    #[test]
+    fn varchar_stores_short_values_inline() {
+        let mut heap = DbHeap::new();
+        let short = DBVarchar("abc".to_string());
+        let long = DBVarchar("x".repeat(40));
+
+        let mut short_buf = [0u8; 20];
+        let mut long_buf = [0u8; 20];
+        short.write_to_buffer(&mut short_buf, &mut heap).unwrap();
+        assert!(heap.is_empty());
+        long.write_to_buffer(&mut long_buf, &mut heap).unwrap();
+        assert_eq!(POINTER_SIZE + 40, heap.len());
+
+        assert_eq!(VARCHAR_INLINE, short_buf[0]);
+        assert_eq!(VARCHAR_SPILLED, long_buf[0]);
+        let mut read = DBVarchar::new();
+        read.read_from_buffer(&short_buf, &heap).unwrap();
+        assert_eq!(short, read);
+        read.read_from_buffer(&long_buf, &heap).unwrap();
+        assert_eq!(long, read);
+    }
+
+    #[test]
+    fn spilled_varchar_past_the_heap_is_an_error() {
+        let mut heap = DbHeap::new();
+        let mut buf = [0u8; 20];
+        DBVarchar("x".repeat(40)).write_to_buffer(&mut buf, &mut heap).unwrap();
+        let mut read = DBVarchar::new();
+
+        LittleEndian::write_uint(&mut buf[1..], heap.len() as u64, POINTER_SIZE);
+        assert!(read.read_from_buffer(&buf, &heap).is_err());
+        LittleEndian::write_uint(&mut buf[1..], u64::MAX, POINTER_SIZE);
+        assert!(read.read_from_buffer(&buf, &heap).is_err());
+        // An entry whose length prefix overruns the heap
+        LittleEndian::write_uint(&mut buf[1..], 0, POINTER_SIZE);
+        heap.truncate(POINTER_SIZE + 39);
+        assert!(read.read_from_buffer(&buf, &heap).is_err());
+    }
+
+    #[test]
+    fn values_report_their_db_type() {
+        assert_eq!(DbType::UInt64, DBUInt64::new().db_type());
+        assert_eq!(DbType::Varchar(5), DBInlineString("hello".to_string()).db_type());
+        assert!(!DBInlineString("hello".to_string()).db_type().is_external());
+        assert!(DBExternalString("hello".to_string()).db_type().is_external());
+        assert_eq!(DbType::Bytes(3), DBBytes(vec![1, 2, 3]).db_type());
+    }
+
+    #[test]
+    fn uint64_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        let test_cases: Vec<u64> = vec![0, u64::MAX, 4538756723];
+        for x in test_cases {
+            let val = DBUInt64(x);
+            let mut new_val = DBUInt64::new();
+            let mut buf = [0u8; 8];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+            assert_eq!(val, new_val);
+        }
+    }
+
+    #[test]
+    fn uint32_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        let test_cases: Vec<u32> = vec![0, u32::MAX, 4538756];
+        for x in test_cases {
+            let val = DBUInt32(x);
+            let mut new_val = DBUInt32::new();
+            let mut buf = [0u8; 4];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+            assert_eq!(val, new_val);
+        }
+    }
+
+    #[test]
+    fn aligned_heap_pads_appends() {
+        let mut heap = DbHeap::new().with_alignment(8);
+        assert_eq!(0, heap.append_data(&mut vec![1, 2, 3]).unwrap());
+        assert_eq!(8, heap.append_data(&mut vec![4; 5]).unwrap());
+        assert_eq!(16, heap.append_data(&mut vec![5]).unwrap());
+        assert_eq!(17, heap.len());
+        assert_eq!(&[4; 5], heap.get_slice(8, 5));
+        assert_eq!(&[0; 3], heap.get_slice(13, 3));
+
+        let mut unaligned = DbHeap::new();
+        assert_eq!(0, unaligned.append_data(&mut vec![1, 2, 3]).unwrap());
+        assert_eq!(3, unaligned.append_data(&mut vec![4]).unwrap());
+    }
+
+    #[test]
+    fn null_equals_nothing() {
+        let null = NullableValue::null();
+        assert!(!null.eq_dyn(&NullableValue::null()));
+        assert!(!null.eq_dyn(&NullableValue::from(DBUInt32(0))));
+        assert!(!NullableValue::from(DBUInt32(0)).eq_dyn(&null));
+        assert!(NullableValue::from(DBUInt32(7)).eq_dyn(&NullableValue::from(DBUInt32(7))));
+    }
+
+    #[test]
+    fn float64_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        for &x in &[0.0, -1.5, f64::MAX, f64::MIN_POSITIVE, f64::INFINITY] {
+            let val = DBFloat64(x);
+            let mut new_val = DBFloat64::new();
+            let mut buf = [0u8; 8];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+            assert_eq!(val, new_val);
+        }
+    }
+
+    #[test]
+    fn boolean_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        let test_cases: Vec<bool> = vec![true, false];
+        for p in test_cases {
+            let val = DBBoolean(p);
+            let mut new_val = DBBoolean::new();
+            let mut buf = [0u8; 1];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+            assert_eq!(val, new_val);
+        }
+    }
+
+    #[test]
+    fn inline_string_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        let test_cases: Vec<String> = vec![
+            "".to_string(),
+            " ".to_string(),
+            "abc123!@#\n\t".to_string(),
+            "Infinite Taco -> ∞ 🌮".to_string(),
+        ];
+        for s in test_cases {
+            let val = DBInlineString(s);
+            let mut new_val = DBInlineString::new();
+            let mut buf = [0u8; 32];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+            assert_eq!(val, new_val);
+        }
+    }
+
+    #[test]
     fn external_string_serialize() {
         let mut heap = DbHeap::new();
 
@@ -329,10 +1789,192 @@ mod tests {
             let mut new_val = DBExternalString::new();
             let mut buf = [0u8; 8];
 
-            val.write_to_buffer(&mut buf, &mut heap);
-            new_val.read_from_buffer(&buf, &heap);
+            val.write_to_buffer(&mut buf, &mut heap).unwrap();
+            new_val.read_from_buffer(&buf, &heap).unwrap();
+
+            assert_eq!(val, new_val);
+        }
+    }
+
+    #[test]
+    fn empty_strings_use_only_their_length() {
+        let mut heap = DbHeap::new();
+        heap.append_data(&mut vec![7; 3]).unwrap();
+
+        // Inline, the length byte is the whole field
+        let mut buf = [0xffu8; 1];
+        DBInlineString(String::new()).write_to_buffer(&mut buf, &mut heap).unwrap();
+        assert_eq!([0], buf);
+        let mut inline = DBInlineString("stale".to_string());
+        inline.read_from_buffer(&buf, &heap).unwrap();
+        assert_eq!("", inline.0);
+
+        // On the heap, the entry is a zero length prefix and nothing else
+        let mut buf = [0u8; POINTER_SIZE];
+        DBExternalString(String::new()).write_to_buffer(&mut buf, &mut heap).unwrap();
+        assert_eq!(3, LittleEndian::read_uint(&buf, POINTER_SIZE));
+        assert_eq!(3 + POINTER_SIZE, heap.len());
+        assert_eq!(&[0; POINTER_SIZE], heap.get_slice(3, POINTER_SIZE));
+        let mut external = DBExternalString("stale".to_string());
+        external.read_from_buffer(&buf, &heap).unwrap();
+        assert_eq!("", external.0);
+    }
+
+    #[test]
+    fn external_string_counts_pointer_and_heap_bytes_apart() {
+        let mut heap = DbHeap::new();
+        let mut buf = [0u8; POINTER_SIZE];
+
+        let written = DBExternalString("taco".to_string()).write_counted(&mut buf, &mut heap).unwrap();
+        assert_eq!(Written { fixed: POINTER_SIZE, heap: POINTER_SIZE + 4 }, written);
+        assert_eq!(written.heap, heap.len());
+
+        let mut buf = [0u8; 8];
+        let written = DBUInt64(9).write_counted(&mut buf, &mut heap).unwrap();
+        assert_eq!(Written { fixed: 8, heap: 0 }, written);
+
+        let mut buf = [0u8; 32];
+        let written = DBVarchar("taco".to_string()).write_counted(&mut buf, &mut heap).unwrap();
+        assert_eq!(Written { fixed: 6, heap: 0 }, written);
+        let long = "x".repeat(40);
+        let written = DBVarchar(long).write_counted(&mut buf, &mut heap).unwrap();
+        assert_eq!(Written { fixed: 1 + POINTER_SIZE, heap: POINTER_SIZE + 40 }, written);
+    }
+
+    #[test]
+    fn serialized_value_roundtrips_through_a_table() {
+        let mut table = crate::Table::new("things", std::rc::Rc::new(vec![
+            crate::FieldSpec::new("thing", crate::TypeSpec::new(DbType::Serialized, false, None)),
+        ]));
+        let thing = (7u32, "seven".to_string());
+        table.insert(&crate::Tuple::new().with(DBSerialized::from_value(&thing).unwrap())).unwrap();
+
+        let mut read = DBSerialized::new();
+        table.read_field_into(0, "thing", &mut read.0).unwrap();
+        assert_eq!(thing, read.to_value::<(u32, String)>().unwrap());
+        assert_eq!(read.to_display_string(), table.get_field(0, "thing").unwrap().to_display_string());
+        assert!(read.to_value::<(u64, u64, String)>().is_err());
+        assert!(read.read_from_buffer(&[0; 3], &DbHeap::new()).is_err());
+        assert!(read.read_from_buffer(&[0; POINTER_SIZE], &DbHeap::new()).is_err());
+    }
+
+    #[test]
+    fn external_string_reads_from_a_borrowed_slice() {
+        let mut heap = DbHeap::new();
+        let mut buf = [0u8; POINTER_SIZE];
+        DBExternalString("mapped".to_string()).write_to_buffer(&mut buf, &mut heap).unwrap();
+        let mapped = heap.as_slice().to_vec();
+        drop(heap);
+
+        let mut external = DBExternalString::new();
+        external.read_from_buffer(&buf, &SliceHeap(&mapped)).unwrap();
+        assert_eq!("mapped", external.0);
+        assert!(external.read_from_buffer(&buf, &SliceHeap(&mapped[..POINTER_SIZE + 2])).is_err());
+    }
+
+    #[test]
+    fn strings_past_their_buffer_are_errors() {
+        let mut heap = DbHeap::new();
+        let mut inline = DBInlineString::new();
+        assert!(inline.read_from_buffer(&[5, b'a', b'b'], &heap).is_err());
+        assert!(inline.read_from_buffer(&[], &heap).is_err());
+
+        let mut buf = [0u8; POINTER_SIZE];
+        DBExternalString("abc".to_string()).write_to_buffer(&mut buf, &mut heap).unwrap();
+        heap.truncate(POINTER_SIZE + 2);
+        let mut external = DBExternalString::new();
+        assert!(external.read_from_buffer(&buf, &heap).is_err());
+        LittleEndian::write_uint(&mut buf, 100, POINTER_SIZE);
+        assert!(external.read_from_buffer(&buf, &heap).is_err());
+    }
+
+    #[test]
+    fn bytes_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        let hash: Vec<u8> = (0..32).collect();
+        let val = DBBytes(hash);
+        let mut new_val = DBBytes::new();
+        let mut buf = [0u8; 32];
+
+        val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+        new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+        assert_eq!(val, new_val);
+        assert_eq!(32, new_val.len());
+    }
+
+    #[test]
+    fn bytes_wrong_length() {
+        let mut heap_unused = DbHeap::new();
+
+        let val = DBBytes(vec![0xab; 31]);
+        let mut buf = [0u8; 32];
+
+        assert!(val.write_to_buffer(&mut buf, &mut heap_unused).is_err());
+    }
+
+    #[test]
+    fn char_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        for &c in &['A', '\u{20ac}', '😀'] {
+            let val = DBChar(c);
+            let mut new_val = DBChar::new();
+            let mut buf = [0u8; 4];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
+
+            assert_eq!(val, new_val);
+            assert_eq!(c as u32, LittleEndian::read_u32(&buf));
+        }
+    }
+
+    #[test]
+    fn char_rejects_invalid_scalars() {
+        let heap_unused = DbHeap::new();
+        let mut val = DBChar::new();
+        for &scalar in &[0xd800u32, 0x11_0000] {
+            let mut buf = [0u8; 4];
+            LittleEndian::write_u32(&mut buf, scalar);
+            assert!(val.read_from_buffer(&buf, &heap_unused).is_err());
+        }
+    }
+
+    #[test]
+    fn ip_addr_serialize() {
+        let mut heap_unused = DbHeap::new();
+
+        let test_cases = vec!["127.0.0.1", "::1", "2001:db8:85a3::8a2e:370:7334"];
+        for s in test_cases {
+            let val: DBIpAddr = s.parse().unwrap();
+            let mut new_val = DBIpAddr::new();
+            let mut buf = [0u8; 17];
+
+            val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+            new_val.read_from_buffer(&buf, &heap_unused).unwrap();
 
             assert_eq!(val, new_val);
+            assert_eq!(s, new_val.to_string());
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ip_addr_v4_layout() {
+        let mut heap_unused = DbHeap::new();
+        let mut buf = [0xffu8; 17];
+
+        let val: DBIpAddr = "10.0.0.1".parse().unwrap();
+        val.write_to_buffer(&mut buf, &mut heap_unused).unwrap();
+
+        assert_eq!([4, 10, 0, 0, 1], buf[..5]);
+        assert!(buf[5..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn ip_addr_rejects_malformed() {
+        assert!("127.0.0.256".parse::<DBIpAddr>().is_err());
+        assert!("not an address".parse::<DBIpAddr>().is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{Table, TableError};
+
+// Longer values are cut short and end in "..."
+const MAX_CELL_WIDTH: usize = 32;
+// Bytes shown on each line of a hex dump
+const HEXDUMP_WIDTH: usize = 16;
+
+impl Table {
+    // Renders the live rows as an ASCII table for debugging, with the field
+    // names as a header. A value that can't be read is shown as its error.
+    pub fn format_table(&self) -> String {
+        let header: Vec<String> = self.schema.iter().map(|field_spec| truncate_cell(&field_spec.name)).collect();
+        let rows: Vec<Vec<String>> = (0..self.row_count())
+            .filter(|&index| !self.tombstones[index])
+            .map(|index| self.schema.iter().map(|field_spec| {
+                let cell = self.get_field(index, &field_spec.name)
+                    .map(|value| value.to_display_string())
+                    .unwrap_or_else(|err| format!("<{}>", err));
+                truncate_cell(&cell)
+            }).collect())
+            .collect();
+
+        let widths: Vec<usize> = (0..header.len())
+            .map(|column| rows.iter()
+                .map(|row| row[column].chars().count())
+                .fold(header[column].chars().count(), usize::max))
+            .collect();
+
+        let border = widths.iter()
+            .fold(String::from("+"), |line, width| line + &"-".repeat(width + 2) + "+");
+        let mut out = String::new();
+        out.push_str(&border);
+        out.push('\n');
+        push_line(&mut out, &header, &widths);
+        out.push_str(&border);
+        out.push('\n');
+        for row in &rows {
+            push_line(&mut out, row, &widths);
+        }
+        out.push_str(&border);
+        out.push('\n');
+        out
+    }
+
+    // Renders a row's fixed bytes for debugging, 16 to a line: each line's
+    // offset in the row, the bytes in hex and then as ASCII, with `.` for
+    // anything unprintable, and then where each field that starts on that
+    // line begins. Heap data isn't followed. A row that doesn't exist is
+    // shown as its error.
+    pub fn hexdump_row(&self, index: usize) -> String {
+        if index >= self.row_count() {
+            return format!("<{}>\n", TableError::RowOutOfBounds(index));
+        }
+        let layout = self.row_layout();
+        let mut starts = vec![];
+        if layout.null_bitmap_len() > 0 {
+            starts.push((0, "<nulls>"));
+        }
+        for (field_index, field_spec) in self.schema.iter().enumerate() {
+            starts.push((layout.field_offset(field_index), field_spec.name.as_str()));
+        }
+
+        let mut out = String::new();
+        for (line, bytes) in self.row(index).chunks(HEXDUMP_WIDTH).enumerate() {
+            let offset = line * HEXDUMP_WIDTH;
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let ascii: String = bytes.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            let fields: Vec<String> = starts.iter()
+                .filter(|&&(start, _)| start >= offset && start < offset + bytes.len())
+                .map(|&(start, name)| format!("{}@{}", name, start))
+                .collect();
+            let line = format!("{:08x}  {:width$}  |{}|  {}", offset, hex.join(" "), ascii, fields.join(", "),
+                width = HEXDUMP_WIDTH * 3 - 1);
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn push_line(out: &mut String, cells: &[String], widths: &[usize]) {
+    out.push('|');
+    for (cell, &width) in cells.iter().zip(widths) {
+        out.push_str(&format!(" {:width$} |", cell, width = width));
+    }
+    out.push('\n');
+}
+
+fn truncate_cell(value: &str) -> String {
+    if value.chars().count() <= MAX_CELL_WIDTH {
+        return value.to_string();
+    }
+    let mut cell: String = value.chars().take(MAX_CELL_WIDTH - 3).collect();
+    cell.push_str("...");
+    cell
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_value::{DBInlineString, DBUInt32, DBUInt64};
+    use crate::{DbType, FieldSpec, Table, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    #[test]
+    fn formats_rows_as_box_table() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(60), true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(1)).with(DBInlineString("alice".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(2)).with_null()).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(3)).with(DBInlineString("deleted".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(40)).with(DBInlineString("x".repeat(50)))).unwrap();
+        table.delete(2).unwrap();
+
+        let expected = "\
++----+----------------------------------+
+| id | name                             |
++----+----------------------------------+
+| 1  | alice                            |
+| 2  | NULL                             |
+| 40 | xxxxxxxxxxxxxxxxxxxxxxxxxxxxx... |
++----+----------------------------------+
+";
+        assert_eq!(expected, table.format_table());
+    }
+
+    #[test]
+    fn hexdump_shows_little_endian_fields() {
+        let mut table = Table::new("counts", Rc::new(vec![
+            FieldSpec::new("count", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(0x4142_4344))).unwrap();
+
+        let blank = " ".repeat(12 * 3);
+        assert_eq!(format!("00000000  44 43 42 41{}  |DCBA|  count@0\n", blank), table.hexdump_row(0));
+        assert_eq!("<Row 1 does not exist>\n", table.hexdump_row(1));
+    }
+
+    #[test]
+    fn hexdump_marks_where_each_field_starts() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt64(1)).with(DBInlineString("Ann".to_string()))).unwrap();
+
+        let expected = "\
+00000000  00 01 00 00 00 00 00 00 00 03 41 6e 6e 00 00 00  |..........Ann...|  <nulls>@0, id@1, name@9
+00000010  00 00 00 00 00 00 00 00 00 00 00 00 00 00        |..............|
+";
+        assert_eq!(expected, table.hexdump_row(0));
+    }
+}
@@ -0,0 +1,194 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+
+use crate::{Table, POINTER_SIZE};
+
+// Something structurally wrong with a table, found by `validate_integrity`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntegrityProblem {
+    // The fixed data doesn't divide into whole rows
+    PartialRow { fixed_len: usize, row_length: usize },
+    // The per-row bookkeeping doesn't have an entry for every row
+    RowCountMismatch { rows: usize, tombstones: usize, versions: usize, checksums: usize },
+    // A field's heap entry, or its length prefix, runs past the end of the heap
+    HeapOutOfBounds { row: usize, field: String, offset: usize },
+    // Two live fields' heap entries overlap without being the same entry
+    HeapOverlap { first: (usize, String), second: (usize, String) },
+    // The null bitmap has bits set past the last nullable field
+    StrayNullBits { row: usize },
+}
+
+impl fmt::Display for IntegrityProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntegrityProblem::PartialRow { fixed_len, row_length } =>
+                write!(f, "{} bytes of rows is not a multiple of the row length {}", fixed_len, row_length),
+            IntegrityProblem::RowCountMismatch { rows, tombstones, versions, checksums } =>
+                write!(f, "{} rows but {} tombstones, {} versions and {} checksums",
+                    rows, tombstones, versions, checksums),
+            IntegrityProblem::HeapOutOfBounds { row, ref field, offset } =>
+                write!(f, "Row {} field {} refers to heap offset {} past the end of the heap", row, field, offset),
+            IntegrityProblem::HeapOverlap { ref first, ref second } =>
+                write!(f, "Row {} field {} overlaps row {} field {} on the heap", first.0, first.1, second.0, second.1),
+            IntegrityProblem::StrayNullBits { row } => write!(f, "Row {} has null bits for no field", row),
+        }
+    }
+}
+
+impl Table {
+    // Checks the table's structure, rather than its values' contents, and
+    // reports every problem found. Only live rows' heap entries are checked,
+    // as deleted rows' may already have been reused. Useful after loading
+    // data from elsewhere, and in tests.
+    pub fn validate_integrity(&self) -> Result<(), Vec<IntegrityProblem>> {
+        let mut problems = vec![];
+        let row_length = self.row_length();
+        let fixed_len = self.fixed_data.len();
+        if row_length != 0 && !fixed_len.is_multiple_of(row_length) {
+            problems.push(IntegrityProblem::PartialRow { fixed_len, row_length });
+        }
+        let rows = self.row_count();
+        if self.tombstones.len() != rows || self.row_versions.len() != rows || self.checksums.len() != rows {
+            problems.push(IntegrityProblem::RowCountMismatch {
+                rows,
+                tombstones: self.tombstones.len(),
+                versions: self.row_versions.len(),
+                checksums: self.checksums.len(),
+            });
+            // Nothing past here can be trusted to index the bookkeeping
+            return Err(problems);
+        }
+
+        let nullable_fields = self.row_layout().nullable_fields();
+        let heap = self.variable_data.as_slice();
+        // (start, end, row index, field index) of every live heap entry
+        let mut entries = vec![];
+        for index in (0..rows).filter(|&index| !self.tombstones[index]) {
+            let row = self.row(index);
+            let stray = (nullable_fields..(self.null_bitmap_len() * 8))
+                .any(|bit| row[bit / 8] & (1 << (bit % 8)) != 0);
+            if stray {
+                problems.push(IntegrityProblem::StrayNullBits { row: index });
+            }
+
+            for field_index in 0..self.schema.len() {
+                let pointer = match self.heap_pointer(row, field_index) {
+                    Some(pointer) => pointer,
+                    None => continue,
+                };
+                let offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+                let end = offset.checked_add(POINTER_SIZE)
+                    .filter(|&end| end <= heap.len())
+                    .and_then(|data| {
+                        let len = LittleEndian::read_uint(&heap[offset..], POINTER_SIZE) as usize;
+                        data.checked_add(len)
+                    })
+                    .filter(|&end| end <= heap.len());
+                match end {
+                    Some(end) => entries.push((offset, end, index, field_index)),
+                    None => problems.push(IntegrityProblem::HeapOutOfBounds {
+                        row: index,
+                        field: self.schema[field_index].name.clone(),
+                        offset,
+                    }),
+                }
+            }
+        }
+
+        entries.sort();
+        for pair in entries.windows(2) {
+            let (first, second) = (pair[0], pair[1]);
+            let same_entry = first.0 == second.0 && first.1 == second.1;
+            if second.0 < first.1 && !same_entry {
+                problems.push(IntegrityProblem::HeapOverlap {
+                    first: (first.2, self.schema[first.3].name.clone()),
+                    second: (second.2, self.schema[second.3].name.clone()),
+                });
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBUInt32};
+    use crate::{DbType, FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn people() -> Table {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        for (age, notes) in &[(31, "first"), (42, "second"), (53, "third")] {
+            table.insert(&Tuple::new().with(DBUInt32(*age)).with(DBExternalString(notes.to_string()))).unwrap();
+        }
+        table.update_field(0, "notes", DBExternalString("changed".to_string())).unwrap();
+        table.delete(1).unwrap();
+        table
+    }
+
+    // Where row `index`'s notes offset is stored
+    fn notes_pointer(table: &Table, index: usize) -> usize {
+        index * table.row_length() + table.field_offset(1)
+    }
+
+    #[test]
+    fn built_table_passes() {
+        assert_eq!(Ok(()), people().validate_integrity());
+        let mut compacted = people();
+        compacted.compact_tombstones().unwrap();
+        compacted.shrink_to_fit().unwrap();
+        assert_eq!(Ok(()), compacted.validate_integrity());
+    }
+
+    #[test]
+    fn corrupt_offset_and_bits_are_reported() {
+        let mut table = people();
+        let mut offset = [0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut offset, 10_000, POINTER_SIZE);
+        table.fixed_data.write_at(notes_pointer(&table, 2), &offset).unwrap();
+        // One byte into row 0's entry, where its length prefix isn't
+        let row_0 = LittleEndian::read_uint(table.fixed_data.read_at(notes_pointer(&table, 0), POINTER_SIZE),
+            POINTER_SIZE);
+        LittleEndian::write_uint(&mut offset, row_0 + 1, POINTER_SIZE);
+        table.fixed_data.write_at(notes_pointer(&table, 0), &offset).unwrap();
+        table.fixed_data.write_at(2 * table.row_length(), &[0b10]).unwrap();
+
+        let problems = table.validate_integrity().unwrap_err();
+        assert_eq!(vec![
+            IntegrityProblem::HeapOutOfBounds { row: 0, field: "notes".to_string(), offset: row_0 as usize + 1 },
+            IntegrityProblem::StrayNullBits { row: 2 },
+            IntegrityProblem::HeapOutOfBounds { row: 2, field: "notes".to_string(), offset: 10_000 },
+        ], problems);
+    }
+
+    #[test]
+    fn overlapping_entries_are_reported() {
+        let mut table = people();
+        let third = LittleEndian::read_uint(table.fixed_data.read_at(notes_pointer(&table, 2), POINTER_SIZE),
+            POINTER_SIZE) as usize;
+        // Row 0 sharing row 2's entry exactly is allowed
+        let mut offset = [0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut offset, third as u64, POINTER_SIZE);
+        table.fixed_data.write_at(notes_pointer(&table, 0), &offset).unwrap();
+        assert_eq!(Ok(()), table.validate_integrity());
+
+        // An empty entry written inside row 2's is not
+        let inner = third + 5;
+        table.variable_data.write_at(inner, &[0u8; POINTER_SIZE]).unwrap();
+        LittleEndian::write_uint(&mut offset, inner as u64, POINTER_SIZE);
+        table.fixed_data.write_at(notes_pointer(&table, 0), &offset).unwrap();
+        assert_eq!(Err(vec![IntegrityProblem::HeapOverlap {
+            first: (2, "notes".to_string()),
+            second: (0, "notes".to_string()),
+        }]), table.validate_integrity());
+    }
+}
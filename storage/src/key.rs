@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{Collation, Table, TableError, Tuple, POINTER_SIZE};
+
+// A unique index over a single field, mapping the key of every live row to
+// that row's index. NULL keys are not indexed, so any number of rows may
+// have one.
+#[derive(Debug)]
+pub(crate) struct PrimaryKey {
+    field_index: usize,
+    rows: HashMap<Vec<u8>, usize>,
+}
+
+// The first byte of every `key_encode` result, telling NULL apart from any
+// value, whatever its bytes
+pub const KEY_NULL: u8 = 0;
+pub const KEY_VALUE: u8 = 1;
+
+// What `Table::upsert` did, and to which row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpsertOutcome {
+    Inserted(usize),
+    Updated(usize),
+}
+
+impl Table {
+    // Makes a field the table's primary key. Fails, leaving the table
+    // without a key, if two live rows already share a value.
+    pub fn set_primary_key(&mut self, field_name: &str) -> Result<(), TableError> {
+        let field_index = self.field_index(field_name)?;
+        self.primary_key = Some(PrimaryKey {
+            field_index,
+            rows: HashMap::new(),
+        });
+
+        if let Err(err) = self.rebuild_primary_key() {
+            self.primary_key = None;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn primary_key(&self) -> Option<&str> {
+        self.primary_key.as_ref()
+            .map(|key| self.schema[key.field_index].name.as_str())
+    }
+
+    // Replaces the live row whose primary key matches the tuple's, as
+    // `replace_row` does, or inserts the tuple if there is none. A NULL key
+    // matches nothing, so is always inserted.
+    pub fn upsert(&mut self, tuple: &Tuple) -> Result<UpsertOutcome, TableError> {
+        let field_index = match self.primary_key {
+            Some(ref key) => key.field_index,
+            None => return Err(TableError::NoPrimaryKey(self.name.clone())),
+        };
+        if tuple.len() != self.schema.len() {
+            return Err(TableError::ArityMismatch {
+                expected: self.schema.len(),
+                actual: tuple.len(),
+            });
+        }
+
+        // Only the key is encoded, to look it up; whatever it put on the
+        // heap is dropped again
+        let heap_len = self.variable_data.len();
+        let mut row = vec![0u8; self.row_length()];
+        let existing = self.write_field(&mut row, field_index, &tuple.values()[field_index])
+            .and_then(|_| self.row_key_bytes(&row, field_index))
+            .map(|key| key.and_then(|key| self.primary_key.as_ref().unwrap().rows.get(&key).cloned()));
+        self.variable_data.truncate(heap_len);
+
+        match existing? {
+            Some(index) => {
+                self.replace_row(index, tuple)?;
+                Ok(UpsertOutcome::Updated(index))
+            }
+            None => self.insert(tuple).map(UpsertOutcome::Inserted),
+        }
+    }
+
+    // A field's value as bytes that are equal exactly when two fields are
+    // the same key, as the primary key compares them. Unlike `eq_dyn`, which
+    // follows SQL in never finding NULL equal to anything, every NULL
+    // encodes the same, as `KEY_NULL` alone, so indexes over nullable fields
+    // can group and find them. Other values are `KEY_VALUE` followed by
+    // their key bytes.
+    pub fn key_encode(&self, index: usize, field_name: &str) -> Result<Vec<u8>, TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let field_index = self.field_index(field_name)?;
+        Ok(match self.key_bytes(index, field_index)? {
+            Some(key) => {
+                let mut encoded = Vec::with_capacity(1 + key.len());
+                encoded.push(KEY_VALUE);
+                encoded.extend_from_slice(&key);
+                encoded
+            }
+            None => vec![KEY_NULL],
+        })
+    }
+
+    // Re-indexes every live row, e.g. after rows have been renumbered
+    pub(crate) fn rebuild_primary_key(&mut self) -> Result<(), TableError> {
+        let field_index = match self.primary_key {
+            Some(ref key) => key.field_index,
+            None => return Ok(()),
+        };
+
+        let mut rows = HashMap::new();
+        for index in (0..self.row_count()).filter(|&index| !self.tombstones[index]) {
+            if let Some(key) = self.key_bytes(index, field_index)? {
+                if rows.insert(key, index).is_some() {
+                    return Err(TableError::DuplicateKey(self.schema[field_index].name.clone()));
+                }
+            }
+        }
+
+        self.primary_key = Some(PrimaryKey { field_index, rows });
+        Ok(())
+    }
+
+    // Adds a live row to the primary key, failing if another row already
+    // has the same key
+    pub(crate) fn index_key(&mut self, index: usize) -> Result<(), TableError> {
+        let field_index = match self.primary_key {
+            Some(ref key) if !self.tombstones[index] => key.field_index,
+            _ => return Ok(()),
+        };
+        let key = match self.key_bytes(index, field_index)? {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let rows = &mut self.primary_key.as_mut().unwrap().rows;
+        match rows.get(&key) {
+            Some(&existing) if existing != index =>
+                Err(TableError::DuplicateKey(self.schema[field_index].name.clone())),
+            _ => {
+                rows.insert(key, index);
+                Ok(())
+            }
+        }
+    }
+
+    // Removes a row from the primary key. Must be called before the row's
+    // bytes change.
+    pub(crate) fn unindex_key(&mut self, index: usize) {
+        let field_index = match self.primary_key {
+            Some(ref key) => key.field_index,
+            None => return,
+        };
+        // A row whose key can't be read was never indexed
+        let key = match self.key_bytes(index, field_index) {
+            Ok(Some(key)) => key,
+            _ => return,
+        };
+
+        let rows = &mut self.primary_key.as_mut().unwrap().rows;
+        if rows.get(&key) == Some(&index) {
+            rows.remove(&key);
+        }
+    }
+
+    // The bytes two rows' keys are compared by, or None for a NULL key.
+    // Heap-backed values are compared by their contents, and strings with a
+    // case-insensitive collation by their lowercased text.
+    fn key_bytes(&self, index: usize, field_index: usize) -> Result<Option<Vec<u8>>, TableError> {
+        self.row_key_bytes(self.row(index), field_index)
+    }
+
+    // Like `key_bytes`, for a row that may not be in the table yet
+    fn row_key_bytes(&self, row: &[u8], field_index: usize) -> Result<Option<Vec<u8>>, TableError> {
+        if self.field_is_null(row, field_index) {
+            return Ok(None);
+        }
+
+        let type_spec = &self.schema[field_index].type_spec;
+        let field = &*self.field_bytes(row, field_index);
+        if type_spec.collation == Collation::CaseInsensitive && type_spec.db_type.is_string() {
+            let mut value = type_spec.db_type.new_value()
+                .ok_or_else(|| TableError::UnsupportedType(format!("{:?}", type_spec.db_type)))?;
+            value.read_from_buffer(field, &self.variable_data)?;
+            return Ok(Some(value.to_display_string().to_lowercase().into_bytes()));
+        }
+
+        Ok(Some(match self.heap_pointer(row, field_index) {
+            Some(pointer) => {
+                let heap_offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+                self.variable_data.get_prefixed_slice(heap_offset).to_vec()
+            }
+            None => field.to_vec(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBInlineString, DBUInt32};
+    use crate::{DbType, FieldSpec, TypeSpec};
+    use std::rc::Rc;
+
+    fn users(collation: Collation) -> Table {
+        let mut table = Table::new("users", Rc::new(vec![
+            FieldSpec::new("username", TypeSpec::new(DbType::Varchar(30), false, None)
+                .with_collation(collation)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        table.set_primary_key("username").unwrap();
+        table
+    }
+
+    fn user(name: &str, age: u32) -> Tuple {
+        Tuple::new().with(DBInlineString(name.to_string())).with(DBUInt32(age))
+    }
+
+    #[test]
+    fn case_insensitive_key_rejects_other_casing() {
+        let mut table = users(Collation::CaseInsensitive);
+        table.insert(&user("Bob", 30)).unwrap();
+
+        let result = table.insert(&user("bob", 31));
+        assert_eq!(Err(TableError::DuplicateKey("username".to_string())), result);
+        assert_eq!(1, table.row_count());
+        assert_eq!("Bob", table.get_field(0, "username").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn binary_key_allows_other_casing() {
+        let mut table = users(Collation::Binary);
+        table.insert(&user("Bob", 30)).unwrap();
+        table.insert(&user("bob", 31)).unwrap();
+        assert_eq!(Err(TableError::DuplicateKey("username".to_string())), table.insert(&user("bob", 32)));
+    }
+
+    #[test]
+    fn deleted_and_replaced_keys_are_released() {
+        let mut table = users(Collation::Binary);
+        table.insert(&user("alice", 30)).unwrap();
+        table.insert(&user("bob", 31)).unwrap();
+
+        assert!(table.replace_row(1, &user("alice", 32)).is_err());
+        assert_eq!("bob", table.get_field(1, "username").unwrap().to_display_string());
+
+        table.replace_row(1, &user("carol", 32)).unwrap();
+        table.insert(&user("bob", 33)).unwrap();
+
+        table.delete(0).unwrap();
+        table.insert(&user("alice", 34)).unwrap();
+    }
+
+    #[test]
+    fn compaction_renumbers_keys() {
+        let mut table = users(Collation::Binary);
+        for (age, name) in ["alice", "bob", "carol", "dave"].iter().enumerate() {
+            table.insert(&user(name, age as u32)).unwrap();
+        }
+        table.delete(0).unwrap();
+        table.delete(2).unwrap();
+        table.compact_tombstones().unwrap();
+
+        assert_eq!(Err(TableError::DuplicateKey("username".to_string())), table.insert(&user("dave", 5)));
+        table.replace_row(1, &user("dave", 6)).unwrap();
+        assert_eq!("6", table.get_field(1, "age").unwrap().to_display_string());
+        table.insert(&user("carol", 7)).unwrap();
+    }
+
+    #[test]
+    fn upsert_inserts_then_updates() {
+        let mut table = users(Collation::Binary);
+        table.insert(&user("alice", 30)).unwrap();
+
+        assert_eq!(Ok(UpsertOutcome::Inserted(1)), table.upsert(&user("bob", 31)));
+        let heap_len = table.variable_data.len();
+        assert_eq!(Ok(UpsertOutcome::Updated(1)), table.upsert(&user("bob", 41)));
+        assert_eq!(2, table.row_count());
+        assert_eq!(heap_len, table.variable_data.len());
+        assert_eq!("41", table.get_field(1, "age").unwrap().to_display_string());
+        assert_eq!("30", table.get_field(0, "age").unwrap().to_display_string());
+
+        table.primary_key = None;
+        assert_eq!(Err(TableError::NoPrimaryKey("users".to_string())), table.upsert(&user("bob", 51)));
+    }
+
+    #[test]
+    fn upsert_matches_heap_backed_keys() {
+        let mut table = Table::new("notes", Rc::new(vec![
+            FieldSpec::new("title", TypeSpec::new(DbType::Varchar(500), false, None)),
+            FieldSpec::new("views", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        table.set_primary_key("title").unwrap();
+        let note = |views| Tuple::new().with(DBExternalString("on heaps".to_string())).with(DBUInt32(views));
+
+        assert_eq!(Ok(UpsertOutcome::Inserted(0)), table.upsert(&note(1)));
+        assert_eq!(Ok(UpsertOutcome::Updated(0)), table.upsert(&note(2)));
+        assert_eq!(1, table.row_count());
+        assert_eq!("2", table.get_field(0, "views").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn existing_duplicates_prevent_setting_key() {
+        let mut table = users(Collation::Binary);
+        table.insert(&user("alice", 30)).unwrap();
+        table.primary_key = None;
+        table.insert(&user("alice", 31)).unwrap();
+
+        assert!(table.set_primary_key("username").is_err());
+        assert_eq!(None, table.primary_key());
+    }
+
+    #[test]
+    fn nulls_share_a_key_distinct_from_values() {
+        let mut table = Table::new("readings", Rc::new(vec![
+            FieldSpec::new("value", TypeSpec::new(DbType::UInt32, true, None)),
+        ]));
+        table.insert(&Tuple::new().with_null()).unwrap();
+        table.insert(&Tuple::new().with_null()).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(0))).unwrap();
+
+        let null = table.key_encode(0, "value").unwrap();
+        assert_eq!(vec![KEY_NULL], null);
+        assert_eq!(null, table.key_encode(1, "value").unwrap());
+        assert_eq!(vec![KEY_VALUE, 0, 0, 0, 0], table.key_encode(2, "value").unwrap());
+        assert!(!table.get_field(0, "value").unwrap().eq_dyn(&table.get_field(1, "value").unwrap()));
+        assert_eq!(Err(TableError::RowOutOfBounds(3)), table.key_encode(3, "value"));
+    }
+}
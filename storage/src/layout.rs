@@ -0,0 +1,169 @@
+use crate::{DbType, FieldSpec, Schema, TableError};
+
+// Where everything sits in a schema's fixed rows: a null bitmap with a bit
+// per nullable field, in field order, and then each field's bytes, in field
+// order. Schemas without nullable fields have no bitmap. Deleted rows are
+// tracked outside the rows, so rows have no tombstone byte.
+//
+// With packed booleans, every boolean field is a bit in a region between
+// the bitmap and the other fields, in field order, rather than a byte
+// among them. A single boolean still takes a byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RowLayout {
+    null_bitmap_len: usize,
+    // Each field's bit in the null bitmap, if it is nullable
+    null_bits: Vec<Option<usize>>,
+    packed_len: usize,
+    // Each packed boolean's bit, counted from the start of the row
+    packed_bits: Vec<Option<usize>>,
+    // Each field's first byte; for a packed boolean, the byte holding its bit
+    offsets: Vec<usize>,
+    // The bytes each field has to itself, which for a packed boolean is none
+    sizes: Vec<usize>,
+    row_length: usize,
+}
+
+impl RowLayout {
+    // Fails if the fields add up to more bytes than a usize can count
+    pub fn new(schema: &Schema) -> Result<RowLayout, TableError> {
+        RowLayout::build(schema, false)
+    }
+
+    // Like `new`, with the boolean fields bit-packed
+    pub fn with_packed_booleans(schema: &Schema) -> Result<RowLayout, TableError> {
+        RowLayout::build(schema, true)
+    }
+
+    fn build(schema: &Schema, pack_booleans: bool) -> Result<RowLayout, TableError> {
+        let null_bits = count_off(schema, |field_spec| field_spec.type_spec.is_nullable);
+        let nullable_fields = null_bits.iter().filter(|bit| bit.is_some()).count();
+        let null_bitmap_len = nullable_fields.div_ceil(8);
+
+        let packed = count_off(schema, |field_spec| pack_booleans && field_spec.type_spec.db_type == DbType::Boolean);
+        let packed_fields = packed.iter().filter(|bit| bit.is_some()).count();
+        let packed_len = packed_fields.div_ceil(8);
+        let packed_bits: Vec<Option<usize>> = packed.into_iter()
+            .map(|bit| bit.map(|bit| null_bitmap_len * 8 + bit))
+            .collect();
+
+        let mut offsets = Vec::with_capacity(schema.len());
+        let mut sizes = Vec::with_capacity(schema.len());
+        let mut row_length = null_bitmap_len + packed_len;
+        for (field_spec, packed_bit) in schema.iter().zip(&packed_bits) {
+            if let Some(bit) = *packed_bit {
+                offsets.push(bit / 8);
+                sizes.push(0);
+                continue;
+            }
+            offsets.push(row_length);
+            sizes.push(field_spec.size());
+            row_length = row_length.checked_add(field_spec.size()).ok_or(TableError::RowTooLarge)?;
+        }
+        Ok(RowLayout { null_bitmap_len, null_bits, packed_len, packed_bits, offsets, sizes, row_length })
+    }
+
+    pub fn row_length(&self) -> usize {
+        self.row_length
+    }
+
+    pub fn null_bitmap_len(&self) -> usize {
+        self.null_bitmap_len
+    }
+
+    // How many of the bitmap's bits belong to a field
+    pub fn nullable_fields(&self) -> usize {
+        self.null_bits.iter().filter(|bit| bit.is_some()).count()
+    }
+
+    // The position of a field's bit in the null bitmap, if it has one
+    pub fn null_bit(&self, field_index: usize) -> Option<usize> {
+        self.null_bits[field_index]
+    }
+
+    // The length of the region packed booleans share, after the bitmap
+    pub fn packed_len(&self) -> usize {
+        self.packed_len
+    }
+
+    // The position of a packed boolean's bit, counted from the start of the
+    // row. None for fields that have bytes of their own.
+    pub fn packed_bit(&self, field_index: usize) -> Option<usize> {
+        self.packed_bits[field_index]
+    }
+
+    pub fn field_offset(&self, field_index: usize) -> usize {
+        self.offsets[field_index]
+    }
+
+    // The bytes a field has to itself, which for a packed boolean is none
+    pub fn field_size(&self, field_index: usize) -> usize {
+        self.sizes[field_index]
+    }
+}
+
+// Numbers the fields `include` picks, in field order
+fn count_off<F>(schema: &Schema, include: F) -> Vec<Option<usize>> where F: Fn(&FieldSpec) -> bool {
+    let mut count: usize = 0;
+    schema.iter()
+        .map(|field_spec| {
+            if !include(field_spec) {
+                return None;
+            }
+            count += 1;
+            Some(count - 1)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TypeSpec;
+
+    #[test]
+    fn nullable_fields_share_one_bitmap_byte() {
+        let layout = RowLayout::new(&vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), true, None)),
+        ]).unwrap();
+
+        assert_eq!(1, layout.null_bitmap_len());
+        assert_eq!(2, layout.nullable_fields());
+        assert_eq!(vec![None, Some(0), Some(1)], (0..3).map(|i| layout.null_bit(i)).collect::<Vec<_>>());
+        assert_eq!(vec![1, 9, 13], (0..3).map(|i| layout.field_offset(i)).collect::<Vec<_>>());
+        assert_eq!(21, layout.field_size(2));
+        assert_eq!(34, layout.row_length());
+    }
+
+    #[test]
+    fn schema_without_nullable_fields_has_no_bitmap() {
+        let layout = RowLayout::new(&vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+        ]).unwrap();
+        assert_eq!(0, layout.null_bitmap_len());
+        assert_eq!(0, layout.field_offset(0));
+        assert_eq!(8, layout.row_length());
+        assert_eq!(0, RowLayout::new(&vec![]).unwrap().row_length());
+    }
+
+    #[test]
+    fn packed_booleans_share_bits_after_the_bitmap() {
+        let mut schema = vec![FieldSpec::new("id", TypeSpec::new(DbType::UInt32, true, None))];
+        for flag in 0..10 {
+            schema.push(FieldSpec::new(format!("flag{}", flag), TypeSpec::new(DbType::Boolean, false, None)));
+        }
+        schema.push(FieldSpec::new("count", TypeSpec::new(DbType::UInt32, false, None)));
+        let layout = RowLayout::with_packed_booleans(&schema).unwrap();
+
+        assert_eq!(2, layout.packed_len());
+        assert_eq!(None, layout.packed_bit(0));
+        assert_eq!(Some(8), layout.packed_bit(1));
+        assert_eq!(Some(17), layout.packed_bit(10));
+        assert_eq!(2, layout.field_offset(10));
+        assert_eq!(0, layout.field_size(10));
+        assert_eq!(vec![3, 7], vec![layout.field_offset(0), layout.field_offset(11)]);
+        assert_eq!(1 + 2 + 4 + 4, layout.row_length());
+        assert_eq!(1 + 10 + 4 + 4, RowLayout::new(&schema).unwrap().row_length());
+    }
+}
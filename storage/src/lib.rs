@@ -1,9 +1,57 @@
 extern crate byteorder;
 
+use byteorder::{ByteOrder, LittleEndian};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
 use std::mem;
 use std::rc::Rc;
 
-mod db_value;
+mod aggregate;
+mod alter;
+mod backend;
+mod blob;
+mod checksum;
+mod database;
+mod delta;
+mod format;
+mod integrity;
+pub mod db_value;
+mod key;
+mod layout;
+mod persist;
+mod query;
+mod raw_row;
+mod reserve;
+mod row;
+mod row_lock;
+mod schema_text;
+mod stats;
+mod vacuum;
+#[cfg(test)]
+mod value_props;
+
+use crate::db_value::{
+    DbHeap, DbValue, HeapStr, DBArray, DBBoolean, DBBytes, DBChar, DBExternalString, DBFloat64, DBInlineString, DBIpAddr, DBLongString, DBMoney,
+    DBSerialized, DBUInt32, DBUInt64, DBVarchar, NullableValue, VARCHAR_INLINE,
+    VARCHAR_SPILLED,
+};
+use crate::key::PrimaryKey;
+pub use crate::aggregate::AggKind;
+pub use crate::backend::Backend;
+pub use crate::database::Database;
+pub use crate::delta::import_column_delta;
+pub use crate::integrity::IntegrityProblem;
+pub use crate::key::{UpsertOutcome, KEY_NULL, KEY_VALUE};
+pub use crate::layout::RowLayout;
+pub use crate::persist::{Durability, SaveStats};
+pub use crate::query::Cursor;
+pub use crate::row::Row;
+pub use crate::row_lock::SharedRows;
+pub use crate::schema_text::{describe_schema, parse_schema};
+pub use crate::stats::{ColumnStats, TableStats};
 
 #[cfg(target_pointer_width = "64")]
 const POINTER_SIZE: usize = 8;
@@ -11,178 +59,1234 @@ const POINTER_SIZE: usize = 8;
 const POINTER_SIZE: usize = 4;
 
 #[derive(Debug)]
-struct Table {
+pub struct Table {
     name: String,
     schema: Rc<Schema>,
-    fixed_data: Vec<u8>,
-    variable_data: Vec<u8>,
+    // Computed whenever the schema changes. None if the schema's rows are
+    // too long to address.
+    layout: Option<RowLayout>,
+    // Whether the layout bit-packs boolean fields
+    packed_booleans: bool,
+    fixed_data: Box<dyn Backend>,
+    variable_data: DbHeap,
+    // Soft-deleted rows, indexed by row number
+    tombstones: Vec<bool>,
+    // Bumped each time a row is changed, indexed by row number
+    row_versions: Vec<u64>,
+    // Each row's checksum as last written, for `verify_all`
+    checksums: Vec<u32>,
+    // Set by `analyze` and cleared whenever the rows change
+    stats: Option<TableStats>,
+    primary_key: Option<PrimaryKey>,
+    // Rows from `reserve_row` that haven't been finalized, with the names
+    // of the fields set on them so far
+    reserved: HashMap<usize, HashSet<String>>,
 }
 
 impl Table {
 
-    fn new<S>(name: S, schema: Rc<Schema>) -> Self where S: Into<String> {
-        Table {
+    pub fn new<S>(name: S, schema: Rc<Schema>) -> Self where S: Into<String> {
+        Table::with_backends(name, schema, Box::new(Vec::new()), DbHeap::new())
+    }
+
+    // Creates a table over existing storage. Any rows already in
+    // `fixed_data` are treated as live.
+    pub fn with_backends<S>(name: S, schema: Rc<Schema>, fixed_data: Box<dyn Backend>, variable_data: DbHeap) -> Self
+        where S: Into<String>
+    {
+        let mut table = Table {
             name: name.into(),
+            layout: RowLayout::new(&schema).ok(),
+            packed_booleans: false,
             schema,
-            fixed_data: Vec::new(),
-            variable_data: Vec::new(),
+            fixed_data,
+            variable_data,
+            tombstones: Vec::new(),
+            row_versions: Vec::new(),
+            checksums: Vec::new(),
+            stats: None,
+            primary_key: None,
+            reserved: HashMap::new(),
+        };
+        table.adopt_existing_rows();
+        table
+    }
+
+    // Stores every boolean field as a single bit rather than a byte, as
+    // described on `RowLayout`. Any rows already in the table's storage are
+    // taken to be in the packed layout, and taken to be live.
+    pub fn with_packed_booleans(mut self) -> Self {
+        self.packed_booleans = true;
+        self.layout = RowLayout::with_packed_booleans(&self.schema).ok();
+        self.adopt_existing_rows();
+        self
+    }
+
+    pub fn packs_booleans(&self) -> bool {
+        self.packed_booleans
+    }
+
+    // Treats every row in `fixed_data` as live and unchanged
+    fn adopt_existing_rows(&mut self) {
+        let existing_rows = self.checked_row_length().ok()
+            .and_then(|row_length| self.fixed_data.len().checked_div(row_length))
+            .unwrap_or(0);
+        self.tombstones = vec![false; existing_rows];
+        self.row_versions = vec![0; existing_rows];
+        self.record_all_checksums();
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn schema(&self) -> Rc<Schema> {
+        self.schema.clone()
+    }
+
+    // The names of the fields, in schema order
+    pub fn field_names(&self) -> Vec<&str> {
+        self.schema.iter().map(|field_spec| field_spec.name.as_str()).collect()
+    }
+
+    // Panics if the schema's fields add up to more bytes than a usize can
+    // count. Tables are checked for that when created through a `Database`
+    // or loaded, and `checked_row_length` checks any other.
+    pub fn row_length(&self) -> usize {
+        self.row_layout().row_length()
+    }
+
+    pub fn checked_row_length(&self) -> Result<usize, TableError> {
+        self.layout.as_ref().map(RowLayout::row_length).ok_or(TableError::RowTooLarge)
+    }
+
+    // Where fields and the null bitmap sit in each row. Panics where
+    // `row_length` does.
+    pub fn row_layout(&self) -> &RowLayout {
+        self.layout.as_ref().expect("row length overflows usize")
+    }
+
+    // The space a row holding `sample` would take: the fixed row plus the
+    // heap entries its values would add, length prefixes included. The
+    // table itself is left alone. A value that can't be encoded for its
+    // field adds nothing.
+    pub fn estimate_row_size(&self, sample: &Tuple) -> usize {
+        let mut heap = DbHeap::new();
+        for (field_spec, value) in self.schema.iter().zip(sample.values()) {
+            if let Some(value) = value.value() {
+                let mut buf = vec![0u8; field_spec.size()];
+                let heap_len = heap.len();
+                if value.write_to_buffer(&mut buf, &mut heap).is_err() {
+                    heap.truncate(heap_len);
+                }
+            }
+        }
+        self.row_length() + heap.len()
+    }
+
+    // Number of rows stored, including deleted ones
+    pub fn row_count(&self) -> usize {
+        self.tombstones.len()
+    }
+
+    // Number of rows that have not been deleted
+    pub fn live_row_count(&self) -> usize {
+        self.tombstones.iter().filter(|&&deleted| !deleted).count()
+    }
+
+    // Appends a row and returns its index. The tuple must supply one value
+    // per field, in schema order.
+    pub fn insert(&mut self, tuple: &Tuple) -> Result<usize, TableError> {
+        let heap_len = self.variable_data.len();
+        let row = self.encode_row(tuple)?;
+        self.append_row(&row, heap_len)
+    }
+
+    // Like `insert`, but first checks every value against its field's type,
+    // so a value of the wrong type is rejected instead of being written as
+    // whatever its bytes happen to decode to. Nothing is written if any
+    // field fails.
+    pub fn insert_checked(&mut self, tuple: &Tuple) -> Result<usize, TableError> {
+        if tuple.len() != self.schema.len() {
+            return Err(TableError::ArityMismatch {
+                expected: self.schema.len(),
+                actual: tuple.len(),
+            });
+        }
+        for (field_spec, value) in self.schema.iter().zip(tuple.values()) {
+            let value = match value.value() {
+                Some(value) => value,
+                None if field_spec.type_spec.is_nullable => continue,
+                None => return Err(TableError::NotNullable(field_spec.name.clone())),
+            };
+            let (expected, actual) = (&field_spec.type_spec.db_type, value.db_type());
+            if !expected.accepts(&actual) {
+                return Err(TableError::TypeMismatch {
+                    field: field_spec.name.clone(),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+
+        self.insert(tuple)
+    }
+
+    // Appends a row with every field set to its default and returns its
+    // index, so the row can be filled in afterwards with `update_field`.
+    // Heap-backed fields get their type's empty value, since a default's
+    // bytes can't stand in for a heap offset.
+    pub fn allocate_row(&mut self) -> Result<usize, TableError> {
+        let heap_len = self.variable_data.len();
+        let mut row = vec![0u8; self.row_length()];
+        for (field_index, field_spec) in self.schema.iter().enumerate() {
+            let mut buf = vec![0u8; field_spec.size()];
+            let db_type = &field_spec.type_spec.db_type;
+            if !db_type.is_external() {
+                field_spec.write_default(&mut buf);
+                self.put_field_bytes(&mut row, field_index, &buf);
+                continue;
+            }
+
+            let written = match db_type.new_value() {
+                Some(empty) => empty.write_to_buffer(&mut buf, &mut self.variable_data)
+                    .map(|_| self.put_field_bytes(&mut row, field_index, &buf))
+                    .map_err(TableError::from),
+                None => Err(TableError::UnsupportedType(format!("{:?}", db_type))),
+            };
+            if let Err(err) = written {
+                self.variable_data.truncate(heap_len);
+                return Err(err);
+            }
         }
+
+        self.append_row(&row, heap_len)
     }
 
-    fn row_length(&self) -> usize {
-        self.schema.iter().fold(0, |acc, field_spec| acc + field_spec.size())
+    // Overwrites every field of an existing row. Heap data the old row
+    // referenced is freed. If any value fails to write, or the new row's
+    // primary key is taken, the row and the heap are left as they were.
+    // They are also left as they were, row version included, when the
+    // values are the ones the row already holds.
+    pub fn replace_row(&mut self, index: usize, tuple: &Tuple) -> Result<(), TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let old_spans: Vec<(usize, usize)> = (0..self.schema.len())
+            .filter_map(|field_index| self.heap_span(index, field_index))
+            .collect();
+
+        let heap_len = self.variable_data.len();
+        let row = self.encode_row(tuple)?;
+        if self.resolve(&row) == self.resolved_row(index) {
+            self.variable_data.truncate(heap_len);
+            return Ok(());
+        }
+        self.overwrite_row(index, &row, heap_len, old_spans)
+    }
+
+    // Overwrites a single field of an existing row, with the same
+    // guarantees as `replace_row`
+    pub fn update_field<V>(&mut self, index: usize, field_name: &str, value: V) -> Result<(), TableError>
+        where V: Into<NullableValue>
+    {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let field_index = self.field_index(field_name)?;
+        let old_spans: Vec<(usize, usize)> = self.heap_span(index, field_index).into_iter().collect();
+
+        let heap_len = self.variable_data.len();
+        let mut row = self.row(index).to_vec();
+        if let Some(bit) = self.null_bit(field_index) {
+            row[bit / 8] &= !(1 << (bit % 8));
+        }
+        // Clear out the old value so no stale bytes are left past the new one
+        self.put_field_bytes(&mut row, field_index, &vec![0; self.schema[field_index].size()]);
+        if let Err(err) = self.write_field(&mut row, field_index, &value.into()) {
+            self.variable_data.truncate(heap_len);
+            return Err(err);
+        }
+        self.overwrite_row(index, &row, heap_len, old_spans)
+    }
+
+    // Sets a nullable field to NULL, freeing any heap data it referred to
+    pub fn set_null(&mut self, index: usize, field_name: &str) -> Result<(), TableError> {
+        self.update_field(index, field_name, NullableValue::null())
+    }
+
+    // Makes a NULL field non-null again, holding its type's empty value: 0,
+    // an empty string and so on. Its old value isn't kept while it is NULL,
+    // so it can't be restored. Fields that aren't NULL are left alone.
+    pub fn clear_null(&mut self, index: usize, field_name: &str) -> Result<(), TableError> {
+        let field_index = self.field_index(field_name)?;
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        if !self.field_is_null(self.row(index), field_index) {
+            return Ok(());
+        }
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        let value = db_type.new_value()
+            .ok_or_else(|| TableError::UnsupportedType(format!("{:?}", db_type)))?;
+        self.update_field(index, field_name, NullableValue::new(value))
+    }
+
+    // Whether a field is NULL. False for rows or fields that don't exist.
+    pub fn is_null(&self, index: usize, field_name: &str) -> bool {
+        match self.field_index(field_name) {
+            Ok(field_index) if index < self.row_count() => self.field_is_null(self.row(index), field_index),
+            _ => false,
+        }
+    }
+
+    // Updates a single field only if the row is still at `expected_version`,
+    // so a caller can read a row, compute a change and apply it without
+    // overwriting someone else's update made in between. Returns the row's
+    // new version.
+    pub fn update_if_version<V>(&mut self, index: usize, field_name: &str, value: V, expected_version: u64)
+        -> Result<u64, TableError>
+        where V: Into<NullableValue>
+    {
+        let actual = self.row_version(index)?;
+        if actual != expected_version {
+            return Err(TableError::VersionMismatch { expected: expected_version, actual });
+        }
+        self.update_field(index, field_name, value)?;
+        self.row_version(index)
+    }
+
+    // How many times a row has been changed since it was inserted. Versions
+    // are not persisted, so every row starts again from 0 when a table is
+    // loaded.
+    pub fn row_version(&self, index: usize) -> Result<u64, TableError> {
+        self.row_versions.get(index).cloned().ok_or(TableError::RowOutOfBounds(index))
+    }
+
+    // Marks a row as deleted. Its space is not reclaimed.
+    pub fn delete(&mut self, index: usize) -> Result<(), TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        self.unindex_key(index);
+        self.tombstones[index] = true;
+        self.reserved.remove(&index);
+        self.stats = None;
+        Ok(())
+    }
+
+    // Marks every live row whose fixed bytes match `pred` as deleted,
+    // returning how many were. As with `delete`, no space is reclaimed.
+    pub fn delete_where<F>(&mut self, pred: F) -> usize where F: Fn(&[u8]) -> bool {
+        let matching: Vec<usize> = (0..self.row_count())
+            .filter(|&index| !self.tombstones[index] && pred(self.row(index)))
+            .collect();
+        for &index in &matching {
+            self.delete(index).expect("matching rows are in bounds");
+        }
+        matching.len()
+    }
+
+    pub fn is_deleted(&self, index: usize) -> bool {
+        self.tombstones.get(index).cloned().unwrap_or(false)
+    }
+
+    // Physically removes deleted rows, moving the live rows down so they
+    // stay in their original order. Heap data the deleted rows referenced
+    // is freed.
+    //
+    // This renumbers rows: a live row's new index is the number of live
+    // rows before it. The primary key is updated, but any row indices held
+    // outside the table must be rebuilt afterwards.
+    pub fn compact_tombstones(&mut self) -> Result<(), TableError> {
+        let row_length = self.row_length();
+        let mut reserved = mem::take(&mut self.reserved);
+        let mut live_rows = 0;
+        for index in 0..self.row_count() {
+            if let Some(fields) = reserved.remove(&index).filter(|_| !self.tombstones[index]) {
+                self.reserved.insert(live_rows, fields);
+            }
+            if self.tombstones[index] {
+                let spans: Vec<(usize, usize)> = (0..self.schema.len())
+                    .filter_map(|field_index| self.heap_span(index, field_index))
+                    .collect();
+                for (offset, len) in spans {
+                    self.variable_data.free(offset, len);
+                }
+                continue;
+            }
+            if live_rows != index {
+                let row = self.row(index).to_vec();
+                self.fixed_data.write_at(live_rows * row_length, &row)?;
+                self.row_versions[live_rows] = self.row_versions[index];
+                self.checksums[live_rows] = self.checksums[index];
+            }
+            live_rows += 1;
+        }
+
+        self.fixed_data.truncate(live_rows * row_length);
+        self.tombstones = vec![false; live_rows];
+        self.row_versions.truncate(live_rows);
+        self.checksums.truncate(live_rows);
+        self.stats = None;
+        self.rebuild_primary_key()
+    }
+
+    // Physically removes a row by moving the last row into its place, so it
+    // takes the same time however many rows there are. Heap data the
+    // removed row referenced is freed.
+    //
+    // This reorders rows: the last row takes the removed row's index. The
+    // primary key is updated, but any row indices held outside the table
+    // must be rebuilt afterwards.
+    pub fn swap_remove_row(&mut self, index: usize) -> Result<(), TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let last = self.row_count() - 1;
+        let row_length = self.row_length();
+        let spans: Vec<(usize, usize)> = (0..self.schema.len())
+            .filter_map(|field_index| self.heap_span(index, field_index))
+            .collect();
+
+        self.unindex_key(index);
+        self.unindex_key(last);
+        if index != last {
+            let row = self.row(last).to_vec();
+            self.fixed_data.write_at(index * row_length, &row)?;
+        }
+        for (offset, len) in spans {
+            self.variable_data.free(offset, len);
+        }
+        self.fixed_data.truncate(last * row_length);
+        self.tombstones.swap_remove(index);
+        self.row_versions.swap_remove(index);
+        self.checksums.swap_remove(index);
+        self.reserved.remove(&index);
+        if let Some(fields) = self.reserved.remove(&last) {
+            self.reserved.insert(index, fields);
+        }
+        self.stats = None;
+
+        if index == last {
+            return Ok(());
+        }
+        // The moved row's key was unique where it was, so it still is
+        self.index_key(index)
+    }
+
+    // Deletes every row that is an exact duplicate of an earlier live row.
+    // Variable length fields are compared by their heap contents rather
+    // than their offsets.
+    pub fn dedup_rows(&mut self) {
+        let mut seen = HashSet::new();
+        for index in 0..self.row_count() {
+            if self.tombstones[index] {
+                continue;
+            }
+            if !seen.insert(self.resolved_row(index)) {
+                self.unindex_key(index);
+                self.tombstones[index] = true;
+                self.reserved.remove(&index);
+                self.stats = None;
+            }
+        }
+    }
+
+    // Picks up to `n` distinct live rows at random and returns their
+    // indices. The same seed always picks the same rows, so samples can be
+    // reproduced. Asking for more rows than are live returns all of them.
+    pub fn sample(&self, n: usize, seed: u64) -> Vec<usize> {
+        let mut live: Vec<usize> = (0..self.row_count())
+            .filter(|&index| !self.tombstones[index])
+            .collect();
+        let n = n.min(live.len());
+
+        // A partial Fisher-Yates shuffle, stopped once `n` rows are chosen
+        let mut state = seed;
+        for i in 0..n {
+            let j = i + (splitmix64(&mut state) % (live.len() - i) as u64) as usize;
+            live.swap(i, j);
+        }
+        live.truncate(n);
+        live
+    }
+
+    // The fixed bytes of the live rows with indices in `[start, end)`, in
+    // order. `end` is clamped to the row count.
+    pub fn rows_range<'a>(&'a self, start: usize, end: usize) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let end = end.min(self.row_count());
+        (start.min(end)..end)
+            .filter(move |&index| !self.tombstones[index])
+            .map(move |index| self.row(index))
+    }
+
+    // Each live row's index with its fixed bytes, in order
+    pub fn rows_enumerated<'a>(&'a self) -> impl Iterator<Item = (usize, &'a [u8])> + 'a {
+        (0..self.row_count())
+            .filter(move |&index| !self.tombstones[index])
+            .map(move |index| (index, self.row(index)))
+    }
+
+    // Reads a single field, returning a null value if the field is NULL
+    pub fn get_field(&self, index: usize, field_name: &str) -> Result<NullableValue, TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let field_index = self.field_index(field_name)?;
+        let row = self.row(index);
+        if self.field_is_null(row, field_index) {
+            return Ok(NullableValue::null());
+        }
+
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        let mut value = db_type.new_value()
+            .ok_or_else(|| TableError::UnsupportedType(format!("{:?}", db_type)))?;
+        value.read_from_buffer(&self.field_bytes(row, field_index), &self.variable_data)?;
+
+        Ok(NullableValue::new(value))
+    }
+
+    // A row as a JSON object with a member per field, NULLs included
+    pub fn row_to_json(&self, index: usize) -> Result<serde_json::Value, TableError> {
+        let mut object = serde_json::Map::new();
+        for field_spec in self.schema.iter() {
+            object.insert(field_spec.name.clone(), self.get_field(index, &field_spec.name)?.to_json());
+        }
+        Ok(serde_json::Value::Object(object))
+    }
+
+    // Borrows a string field's text from the heap instead of copying it, for
+    // strings stored there: external Varchars, and adaptive Varchars that
+    // spilled. Returns None for NULL. The borrow holds the table, so it
+    // can't be changed until the string is dropped.
+    pub fn get_heap_str(&self, index: usize, field_name: &str) -> Result<Option<HeapStr<'_>>, TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let field_index = self.field_index(field_name)?;
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        if !db_type.is_string() {
+            return Err(TableError::UnsupportedType(format!("{:?}", db_type)));
+        }
+        if self.field_is_null(self.row(index), field_index) {
+            return Ok(None);
+        }
+
+        match self.heap_span(index, field_index) {
+            Some((heap_offset, _)) => Ok(Some(self.variable_data.get_str(heap_offset)?)),
+            None => Err(TableError::Value(format!("Field {} of row {} is not stored on the heap", field_name, index))),
+        }
+    }
+
+    // Streams a string or blob field's data to a writer without decoding it,
+    // returning the number of bytes written. NULL fields write nothing.
+    pub fn read_field_into<W: Write>(&self, index: usize, field_name: &str, writer: &mut W) -> Result<usize, TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let field_index = self.field_index(field_name)?;
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        let is_adaptive = matches!(*db_type, DbType::AdaptiveVarchar { .. });
+        if !db_type.is_external() && !is_adaptive {
+            return Err(TableError::UnsupportedType(format!("{:?}", db_type)));
+        }
+
+        let row = self.row(index);
+        match self.heap_span(index, field_index) {
+            Some((heap_offset, _)) => Ok(self.variable_data.read_into(heap_offset, writer)?),
+            None if is_adaptive && !self.field_is_null(row, field_index) => {
+                // Stored inline after the tag and length bytes
+                let offset = self.field_offset(field_index);
+                let len = row[offset + 1] as usize;
+                writer.write_all(&row[(offset+2)..(offset+2+len)])?;
+                Ok(len)
+            }
+            None => Ok(0),
+        }
+    }
+
+    // Where a field's length-prefixed heap data starts, for fields that
+    // currently keep their value on the heap. None for inline and NULL
+    // values, unknown fields and rows that don't exist. The offset stays
+    // valid until the value is replaced or the heap is shrunk.
+    pub fn heap_offset_of(&self, index: usize, field_name: &str) -> Option<usize> {
+        if index >= self.row_count() {
+            return None;
+        }
+        let field_index = self.field_index(field_name).ok()?;
+        self.heap_span(index, field_index).map(|(offset, _)| offset)
+    }
+
+    fn field_index(&self, field_name: &str) -> Result<usize, TableError> {
+        self.schema.iter()
+            .position(|field_spec| field_spec.name == field_name)
+            .ok_or_else(|| TableError::UnknownField(field_name.to_string()))
+    }
+
+    // Each nullable field has a bit at the start of the row that is set
+    // when the field is NULL. Schemas without nullable fields have no bitmap.
+    fn null_bitmap_len(&self) -> usize {
+        self.row_layout().null_bitmap_len()
+    }
+
+    // The position of a field's bit in the null bitmap, if it has one
+    fn null_bit(&self, field_index: usize) -> Option<usize> {
+        self.row_layout().null_bit(field_index)
+    }
+
+    fn field_is_null(&self, row: &[u8], field_index: usize) -> bool {
+        match self.null_bit(field_index) {
+            Some(bit) => row[bit / 8] & (1 << (bit % 8)) != 0,
+            None => false,
+        }
+    }
+
+    fn field_offset(&self, field_index: usize) -> usize {
+        self.row_layout().field_offset(field_index)
+    }
+
+    // A field's bytes in a row. A packed boolean's bit is read out into a
+    // byte of its own, as an unpacked boolean would be stored.
+    fn field_bytes<'a>(&self, row: &'a [u8], field_index: usize) -> Cow<'a, [u8]> {
+        match self.row_layout().packed_bit(field_index) {
+            Some(bit) => Cow::Owned(vec![(row[bit / 8] >> (bit % 8)) & 1]),
+            None => {
+                let offset = self.field_offset(field_index);
+                Cow::Borrowed(&row[offset..(offset+self.schema[field_index].size())])
+            }
+        }
+    }
+
+    // Writes a field's bytes into a row, as `field_bytes` reads them
+    fn put_field_bytes(&self, row: &mut [u8], field_index: usize, bytes: &[u8]) {
+        match self.row_layout().packed_bit(field_index) {
+            Some(bit) if bytes[0] != 0 => row[bit / 8] |= 1 << (bit % 8),
+            Some(bit) => row[bit / 8] &= !(1 << (bit % 8)),
+            None => {
+                let offset = self.field_offset(field_index);
+                row[offset..(offset+bytes.len())].copy_from_slice(bytes);
+            }
+        }
+    }
+
+    // Replaces the schema, and the layout that goes with it
+    fn set_schema(&mut self, schema: Rc<Schema>) {
+        self.layout = self.layout_for(&schema);
+        self.schema = schema;
+    }
+
+    // The layout this table would give rows of `schema`
+    fn layout_for(&self, schema: &Schema) -> Option<RowLayout> {
+        if self.packed_booleans {
+            RowLayout::with_packed_booleans(schema).ok()
+        } else {
+            RowLayout::new(schema).ok()
+        }
+    }
+
+    // Writes new bytes over an existing row, keeping the primary key in
+    // step. If that fails the old row is restored and the heap is cut back
+    // to `heap_len`; otherwise the row's version is bumped and the heap data
+    // in `old_spans`, which the old row referenced, is freed.
+    fn overwrite_row(&mut self, index: usize, row: &[u8], heap_len: usize, old_spans: Vec<(usize, usize)>)
+        -> Result<(), TableError>
+    {
+        let old_row = self.row(index).to_vec();
+        let start = index * self.row_length();
+
+        self.unindex_key(index);
+        let written = self.fixed_data.write_at(start, row)
+            .map_err(TableError::from)
+            .and_then(|_| self.index_key(index));
+        if let Err(err) = written {
+            self.fixed_data.write_at(start, &old_row)?;
+            self.variable_data.truncate(heap_len);
+            // The old key was unique before, so it still is
+            self.index_key(index)?;
+            return Err(err);
+        }
+
+        for (offset, len) in old_spans {
+            self.variable_data.free(offset, len);
+        }
+        self.row_versions[index] += 1;
+        self.record_checksum(index);
+        self.stats = None;
+        Ok(())
+    }
+
+    // Adds an encoded row whose heap data has already been appended. On
+    // failure the row is not added and the heap is cut back to `heap_len`.
+    fn append_row(&mut self, row: &[u8], heap_len: usize) -> Result<usize, TableError> {
+        if let Err(err) = self.fixed_data.append(row) {
+            self.variable_data.truncate(heap_len);
+            return Err(err.into());
+        }
+        self.tombstones.push(false);
+        self.row_versions.push(0);
+        self.stats = None;
+
+        let index = self.tombstones.len() - 1;
+        if let Err(err) = self.index_key(index) {
+            self.tombstones.pop();
+            self.row_versions.pop();
+            self.fixed_data.truncate(index * self.row_length());
+            self.variable_data.truncate(heap_len);
+            return Err(err);
+        }
+        self.record_checksum(index);
+        Ok(index)
+    }
+
+    // Serializes a tuple into a new fixed row, appending its heap-backed
+    // values to the heap. On failure the heap is rolled back.
+    fn encode_row(&mut self, tuple: &Tuple) -> Result<Vec<u8>, TableError> {
+        if tuple.len() != self.schema.len() {
+            return Err(TableError::ArityMismatch {
+                expected: self.schema.len(),
+                actual: tuple.len(),
+            });
+        }
+
+        let heap_len = self.variable_data.len();
+        let mut row = vec![0u8; self.row_length()];
+        for (field_index, value) in tuple.values().iter().enumerate() {
+            if let Err(err) = self.write_field(&mut row, field_index, value) {
+                self.variable_data.truncate(heap_len);
+                return Err(err);
+            }
+        }
+
+        Ok(row)
+    }
+
+    fn write_field(&mut self, row: &mut [u8], field_index: usize, value: &NullableValue) -> Result<(), TableError> {
+        let field_spec = &self.schema[field_index];
+        // The stored amount doesn't carry its scale, so an amount with a
+        // different scale than the column's would read back as another amount
+        if let (Some(value), &DbType::Money { scale }) = (value.value(), &field_spec.type_spec.db_type) {
+            let actual = value.db_type();
+            if matches!(actual, DbType::Money { scale: value_scale } if value_scale != scale) {
+                return Err(TableError::TypeMismatch {
+                    field: field_spec.name.clone(),
+                    expected: field_spec.type_spec.db_type.clone(),
+                    actual,
+                });
+            }
+        }
+        match value.value() {
+            Some(value) if self.row_layout().packed_bit(field_index).is_some() => {
+                let mut buf = [0u8; 1];
+                value.write_to_buffer(&mut buf, &mut self.variable_data)?;
+                self.put_field_bytes(row, field_index, &buf);
+            }
+            Some(value) => {
+                let offset = self.field_offset(field_index);
+                let buf = &mut row[offset..(offset+field_spec.size())];
+                value.write_to_buffer(buf, &mut self.variable_data)?;
+            }
+            None => match self.null_bit(field_index) {
+                Some(bit) => row[bit / 8] |= 1 << (bit % 8),
+                None => return Err(TableError::NotNullable(field_spec.name.clone())),
+            },
+        }
+        Ok(())
+    }
+
+    // The (offset, len) of the heap data a field refers to, including its
+    // length prefix. None for inline and NULL fields.
+    fn heap_span(&self, index: usize, field_index: usize) -> Option<(usize, usize)> {
+        let row = self.row(index);
+        let pointer = self.heap_pointer(row, field_index)?;
+        let heap_offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+        Some((heap_offset, self.variable_data.get_prefixed_slice(heap_offset).len()))
+    }
+
+    // Where in a row a field's heap offset is stored, if the field
+    // currently refers to heap data
+    fn heap_pointer(&self, row: &[u8], field_index: usize) -> Option<usize> {
+        if self.field_is_null(row, field_index) {
+            return None;
+        }
+        let offset = self.field_offset(field_index);
+        match self.schema[field_index].type_spec.db_type {
+            ref db_type if db_type.is_external() => Some(offset),
+            DbType::AdaptiveVarchar { .. } if row[offset] == VARCHAR_SPILLED => Some(offset + 1),
+            _ => None,
+        }
+    }
+
+    fn row(&self, index: usize) -> &[u8] {
+        let row_length = self.row_length();
+        let start = index * row_length;
+        self.fixed_data.read_at(start, row_length)
+    }
+
+    // The row's fixed bytes with each heap reference replaced by the
+    // length-prefixed data it points at
+    fn resolved_row(&self, index: usize) -> Vec<u8> {
+        self.resolve(self.row(index))
+    }
+
+    // Resolves an encoded row as `resolved_row` does, whether or not it is
+    // in the table yet
+    fn resolve(&self, row: &[u8]) -> Vec<u8> {
+        let layout = self.row_layout();
+        let mut resolved = Vec::with_capacity(row.len());
+        // The bitmap and any packed booleans
+        resolved.extend_from_slice(&row[..(layout.null_bitmap_len() + layout.packed_len())]);
+        for field_index in 0..self.schema.len() {
+            let offset = layout.field_offset(field_index);
+            match self.heap_pointer(row, field_index) {
+                Some(pointer) => {
+                    let heap_offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+                    resolved.extend_from_slice(self.variable_data.get_prefixed_slice(heap_offset));
+                }
+                None => resolved.extend_from_slice(&row[offset..(offset+layout.field_size(field_index))]),
+            }
+        }
+
+        resolved
     }
 }
 
-#[derive(Debug)]
-struct FieldSpec {
-    name: String,
-    type_spec: TypeSpec,
+// The values for a single row, in schema order
+#[derive(Debug, Default)]
+pub struct Tuple {
+    values: Vec<NullableValue>,
+}
+
+impl Tuple {
+    pub fn new() -> Self {
+        Tuple {
+            values: Vec::new(),
+        }
+    }
+
+    pub fn with<V>(mut self, value: V) -> Self where V: DbValue + 'static {
+        self.push(value);
+        self
+    }
+
+    pub fn with_null(mut self) -> Self {
+        self.push_null();
+        self
+    }
+
+    pub fn push<V>(&mut self, value: V) where V: DbValue + 'static {
+        self.values.push(NullableValue::new(Box::new(value)));
+    }
+
+    pub fn push_null(&mut self) {
+        self.values.push(NullableValue::null());
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn values(&self) -> &[NullableValue] {
+        &self.values
+    }
 }
 
-type Schema = Vec<FieldSpec>;
+#[derive(Debug, PartialEq)]
+pub enum TableError {
+    ArityMismatch { expected: usize, actual: usize },
+    RowOutOfBounds(usize),
+    InvalidRowLength { expected: usize, actual: usize },
+    UnknownField(String),
+    // A field with this name is already in the schema
+    FieldExists(String),
+    // A value's type doesn't fit the named field's column type
+    TypeMismatch { field: String, expected: DbType, actual: DbType },
+    // NULL was given for a field that does not allow it
+    NotNullable(String),
+    // A reserved row was finalized without a value for this required field
+    MissingField(String),
+    // Another live row already has this value in the named key field
+    DuplicateKey(String),
+    // A conditional update found the row at a different version
+    VersionMismatch { expected: u64, actual: u64 },
+    // The column type has no value implementation yet
+    UnsupportedType(String),
+    // A value failed to serialize or deserialize
+    Value(String),
+    // Aggregating the named field gave a result too large for its type
+    Overflow(String),
+    // The named field has no non-NULL values to aggregate
+    NoValues(String),
+    Io(String),
+    // The named field is the primary key, which must stay in the schema
+    PrimaryKeyField(String),
+    // The named table needs a primary key for this
+    NoPrimaryKey(String),
+    // A field's default can't be stored in a field of its type
+    InvalidDefault(String),
+    // A schema description could not be parsed
+    InvalidSchema(String),
+    // A schema's fields add up to more bytes than a row can have
+    RowTooLarge,
+    // A database has no table with this name
+    UnknownTable(String),
+    // A table with this name already exists
+    TableExists(String),
+    // Persisted table data could not be decoded
+    Corrupt(String),
+    // The table file was written by a newer format version
+    UnsupportedVersion(u8),
+    // The table file stores its integers big-endian, and this build only
+    // reads little-endian ones
+    WrongByteOrder,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TableError::ArityMismatch { expected, actual } =>
+                write!(f, "Expected {} values, got {}", expected, actual),
+            TableError::RowOutOfBounds(index) => write!(f, "Row {} does not exist", index),
+            TableError::InvalidRowLength { expected, actual } =>
+                write!(f, "Expected a row of {} bytes, got {}", expected, actual),
+            TableError::UnknownField(ref name) => write!(f, "No field named {}", name),
+            TableError::FieldExists(ref name) => write!(f, "Field {} already exists", name),
+            TableError::TypeMismatch { ref field, ref expected, ref actual } =>
+                write!(f, "Field {} has type {}, got a value of type {}", field, expected, actual),
+            TableError::NotNullable(ref name) => write!(f, "Field {} cannot be NULL", name),
+            TableError::MissingField(ref name) => write!(f, "Field {} was never set", name),
+            TableError::DuplicateKey(ref name) => write!(f, "Duplicate value for key field {}", name),
+            TableError::VersionMismatch { expected, actual } =>
+                write!(f, "Expected row version {}, found {}", expected, actual),
+            TableError::UnsupportedType(ref db_type) => write!(f, "Type {} is not supported", db_type),
+            TableError::Value(ref msg) => write!(f, "{}", msg),
+            TableError::Overflow(ref name) => write!(f, "Aggregate of field {} overflowed", name),
+            TableError::NoValues(ref name) => write!(f, "Field {} has no values to aggregate", name),
+            TableError::Io(ref msg) => write!(f, "I/O error: {}", msg),
+            TableError::PrimaryKeyField(ref name) => write!(f, "Field {} is the primary key", name),
+            TableError::NoPrimaryKey(ref name) => write!(f, "Table {} has no primary key", name),
+            TableError::InvalidDefault(ref msg) => write!(f, "Invalid default for {}", msg),
+            TableError::InvalidSchema(ref msg) => write!(f, "Invalid schema: {}", msg),
+            TableError::RowTooLarge => write!(f, "Rows of this schema are too large to address"),
+            TableError::UnknownTable(ref name) => write!(f, "No table named {}", name),
+            TableError::TableExists(ref name) => write!(f, "Table {} already exists", name),
+            TableError::Corrupt(ref msg) => write!(f, "Corrupt table data: {}", msg),
+            TableError::UnsupportedVersion(version) =>
+                write!(f, "Table format version {} is newer than this build supports", version),
+            TableError::WrongByteOrder =>
+                write!(f, "Table file is big-endian; only little-endian table files can be loaded"),
+        }
+    }
+}
+
+impl Error for TableError {}
+
+impl From<String> for TableError {
+    fn from(msg: String) -> Self {
+        TableError::Value(msg)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub name: String,
+    pub type_spec: TypeSpec,
+}
+
+pub type Schema = Vec<FieldSpec>;
 
 impl FieldSpec {
-    fn new<S>(name: S, type_spec: TypeSpec) -> Self where S: Into<String>  {
+    pub fn new<S>(name: S, type_spec: TypeSpec) -> Self where S: Into<String>  {
         FieldSpec {
             name: name.into(),
             type_spec,
         }
     }
 
-    fn size(&self) -> usize {
+    pub fn size(&self) -> usize {
         self.type_spec.size()
     }
+
+    // Fills a field's slice with its default bytes, or with zeros if it has
+    // no default. A default longer than the field is cut short.
+    pub fn write_default(&self, buf: &mut [u8]) {
+        for b in buf.iter_mut() {
+            *b = 0;
+        }
+        if let Some(ref default) = self.type_spec.default {
+            let len = default.len().min(buf.len());
+            buf[..len].copy_from_slice(&default[..len]);
+        }
+    }
 }
 
-#[derive(Debug)]
-struct TypeSpec {
-    db_type: DbType,
-    is_nullable: bool,
-    default: Option<Vec<u8>>,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeSpec {
+    pub db_type: DbType,
+    pub is_nullable: bool,
+    pub default: Option<Vec<u8>>,
+    pub collation: Collation,
 }
 
 impl TypeSpec {
-    fn new(db_type: DbType, is_nullable: bool, default: Option<Vec<u8>>) -> Self {
+    pub fn new(db_type: DbType, is_nullable: bool, default: Option<Vec<u8>>) -> Self {
         TypeSpec {
             db_type,
             is_nullable,
             default,
+            collation: Collation::Binary,
         }
     }
 
-    fn size(&self) -> usize {
+    pub fn with_collation(mut self, collation: Collation) -> Self {
+        self.collation = collation;
+        self
+    }
+
+    pub fn size(&self) -> usize {
         self.db_type.size()
     }
+
+    // Checks that the default, if any, is bytes a field of this type could
+    // hold. Fixed-width types need exactly their size; strings need their
+    // length prefix (and tag, for adaptive Varchars) followed by that much
+    // UTF-8, and may be shorter than the field. Heap-backed types can't have
+    // a default, since its bytes would stand in for a heap offset.
+    pub fn validate(&self) -> Result<(), TableError> {
+        let default = match self.default {
+            Some(ref default) => default,
+            None => return Ok(()),
+        };
+        let invalid = |msg: String| Err(TableError::InvalidDefault(format!("{:?}: {}", self.db_type, msg)));
+        if self.db_type.is_external() {
+            return invalid("heap-backed types can't have a default".to_string());
+        }
+
+        // How many bytes come before the text, and the most text there can be
+        let (prefix_len, max_len) = match self.db_type {
+            DbType::Varchar(len) => (1, len),
+            DbType::LongVarchar(len) => (2, len),
+            DbType::AdaptiveVarchar { inline_len, .. } => (2, inline_len),
+            _ => {
+                if default.len() != self.size() {
+                    return invalid(format!("default must be {} bytes, got {}", self.size(), default.len()));
+                }
+                return match self.db_type.new_value() {
+                    Some(mut value) => value.read_from_buffer(default, &DbHeap::new())
+                        .or_else(invalid),
+                    None => Ok(()),
+                };
+            }
+        };
+
+        if default.len() < prefix_len || default.len() > self.size() {
+            return invalid(format!("default must be between {} and {} bytes, got {}",
+                prefix_len, self.size(), default.len()));
+        }
+        let len = match self.db_type {
+            DbType::LongVarchar(_) => LittleEndian::read_u16(default) as usize,
+            DbType::AdaptiveVarchar { .. } if default[0] != VARCHAR_INLINE =>
+                return invalid("default must be stored inline".to_string()),
+            DbType::AdaptiveVarchar { .. } => default[1] as usize,
+            _ => default[0] as usize,
+        };
+        let data = &default[prefix_len..];
+        if len != data.len() || len > max_len {
+            return invalid(format!("length prefix {} doesn't match {} bytes of data", len, data.len()));
+        }
+        if std::str::from_utf8(data).is_err() {
+            return invalid("default is not valid UTF-8".to_string());
+        }
+        Ok(())
+    }
+}
+
+// How string values are compared by keys. Values are always stored as
+// given; this only affects which values count as equal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Collation {
+    // Byte for byte
+    #[default]
+    Binary,
+    // Ignoring case, so "Alice" and "alice" are the same key
+    CaseInsensitive,
 }
 
 // Idea: Rename to InternalDBType and create a DbType trait that defines
 // (initially) read/write methods
-#[derive(Debug)]
-enum DbType {
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DbType {
     Boolean,
     Int32,
     UInt32,
     Int64,
     UInt64,
+    // An IEEE 754 double
+    Float64,
     Varchar(usize),
+    // A string of up to `len` bytes (at most 65535) kept in the row behind a
+    // two-byte length, for strings too long for an inline Varchar that
+    // should still stay off the heap
+    LongVarchar(usize),
     Blob,
+    // Exactly `len` bytes of binary data stored inline
+    Bytes(usize),
+    // An IPv4 or IPv6 address
+    IpAddr,
+    // A single Unicode character
+    Char,
+    // A string of up to `max_len` bytes that is kept in the row when it is
+    // at most `inline_len` bytes (which must be under 256) and on the heap
+    // otherwise, so short values avoid the heap without limiting long ones
+    AdaptiveVarchar { max_len: usize, inline_len: usize },
+    // An amount with `scale` decimal places and a currency code
+    Money { scale: u8 },
+    // A list of elements of a fixed-width type, kept on the heap, with an
+    // optional fixed number of elements
+    Array(Box<DbType>, Option<usize>),
+    // Any serde value as bincode bytes, kept on the heap
+    Serialized,
 }
 
 impl DbType {
-    fn size(&self) -> usize {
+    pub fn size(&self) -> usize {
         match *self {
             DbType::Boolean => 1,
             DbType::Int32 => 4,
             DbType::UInt32 => 4,
             DbType::Int64 => 8,
             DbType::UInt64 => 8,
+            DbType::Float64 => 8,
             DbType::Varchar(len) if len < 256 => 1 + len,
-            DbType::Varchar(len)              => 2 + POINTER_SIZE,
+            DbType::Varchar(_)                => 2 + POINTER_SIZE,
+            DbType::LongVarchar(len) => 2 + len,
             DbType::Blob => 2 + POINTER_SIZE,
+            DbType::Bytes(len) => len,
+            DbType::IpAddr => 17,
+            DbType::Char => 4,
+            // A tag byte, then either a length byte and the data or a heap offset
+            DbType::AdaptiveVarchar { inline_len, .. } => 1 + (1 + inline_len).max(POINTER_SIZE),
+            DbType::Money { .. } => 11,
+            DbType::Array(..) | DbType::Serialized => POINTER_SIZE,
+        }
+    }
+
+    // Creates an empty value that fields of this type can be read into
+    pub fn new_value(&self) -> Option<Box<dyn DbValue>> {
+        match *self {
+            DbType::Boolean => Some(Box::new(DBBoolean::new())),
+            DbType::UInt32 => Some(Box::new(DBUInt32::new())),
+            DbType::UInt64 => Some(Box::new(DBUInt64::new())),
+            DbType::Float64 => Some(Box::new(DBFloat64::new())),
+            DbType::Varchar(len) if len < 256 => Some(Box::new(DBInlineString::new())),
+            DbType::Varchar(_) => Some(Box::new(DBExternalString::new())),
+            DbType::LongVarchar(_) => Some(Box::new(DBLongString::new())),
+            DbType::Bytes(_) => Some(Box::new(DBBytes::new())),
+            DbType::IpAddr => Some(Box::new(DBIpAddr::new())),
+            DbType::Char => Some(Box::new(DBChar::new())),
+            DbType::AdaptiveVarchar { .. } => Some(Box::new(DBVarchar::new())),
+            DbType::Money { scale } => Some(Box::new(DBMoney::with_type_scale(scale))),
+            DbType::Array(ref element_type, fixed_len) => {
+                let array = DBArray::new((**element_type).clone(), vec![]);
+                Some(Box::new(match fixed_len {
+                    Some(len) => array.with_fixed_len(len),
+                    None => array,
+                }))
+            }
+            DbType::Serialized => Some(Box::new(DBSerialized::new())),
+            DbType::Int32 | DbType::Int64 | DbType::Blob => None,
+        }
+    }
+
+    // Whether a value reporting `value_type` can be stored in a column of
+    // this type. Strings and arrays fit when they are no longer than the
+    // column allows and are stored the same way (inline or on the heap);
+    // everything else must match exactly.
+    pub fn accepts(&self, value_type: &DbType) -> bool {
+        match (self, value_type) {
+            (&DbType::Varchar(len), &DbType::Varchar(value_len)) =>
+                value_len <= len && self.is_external() == value_type.is_external(),
+            (&DbType::LongVarchar(len), &DbType::LongVarchar(value_len)) => value_len <= len,
+            (&DbType::AdaptiveVarchar { max_len, .. }, &DbType::AdaptiveVarchar { max_len: value_len, .. }) =>
+                value_len <= max_len,
+            (&DbType::Array(ref element_type, fixed_len), &DbType::Array(ref value_element_type, value_len)) =>
+                element_type == value_element_type && (fixed_len.is_none() || fixed_len == value_len),
+            _ => self == value_type,
+        }
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(*self, DbType::Varchar(_) | DbType::LongVarchar(_) | DbType::AdaptiveVarchar { .. })
+    }
+
+    // Whether values of this type always live in the heap, with the fixed
+    // row only holding their offset. Adaptive Varchars decide per value.
+    pub fn is_external(&self) -> bool {
+        match *self {
+            DbType::Varchar(len) => len >= 256,
+            DbType::Blob | DbType::Array(..) | DbType::Serialized => true,
+            _ => false,
         }
     }
 }
 
-fn read_value<T: Clone>(buf: &Vec<u8>, offset: usize) -> T {
-    let size = mem::size_of::<T>();
-    let src = &buf[offset..(offset+size)];
-    let src_ptr: *const u8 = src.as_ptr();
-    let out_ptr: *const T = src_ptr as *const _;
-    let out: &T = unsafe { &*out_ptr };
-    out.clone()
+// A small seeded generator for sampling. It does not need to be
+// cryptographically strong, only cheap and reproducible.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
 }
 
-fn write_value<T: Clone>(buf: &mut Vec<u8>, offset: usize, val: T) {
-    let size = mem::size_of::<T>();
-    let src_ptr = &val as *const T as *const u8;
-    let dest = &mut buf[offset..(offset+size)];
-    let dest_ptr: *mut u8 = dest.as_mut_ptr();
-    unsafe {
-        std::ptr::copy_nonoverlapping(src_ptr, dest_ptr, size);
-    };
+// An integer that can be packed into a buffer. Values are always stored
+// little-endian so data written on one host reads back the same on another.
+pub trait LittleEndianValue: Sized {
+    const SIZE: usize;
+
+    fn from_le_slice(src: &[u8]) -> Self;
+    fn write_le_slice(self, dest: &mut [u8]);
 }
 
-trait DbValue {
-    fn size(&self) -> usize;
-    fn read_from_buffer(&mut self, buf: &[u8]) -> Result<(), String>;
-    fn write_to_buffer(&self, buf: &mut [u8]) -> Result<(), String>;
+macro_rules! impl_little_endian_value {
+    ($($t:ty),*) => {
+        $(
+            impl LittleEndianValue for $t {
+                const SIZE: usize = mem::size_of::<$t>();
+
+                fn from_le_slice(src: &[u8]) -> Self {
+                    let mut bytes = [0u8; mem::size_of::<$t>()];
+                    bytes.copy_from_slice(src);
+                    <$t>::from_le_bytes(bytes)
+                }
+
+                fn write_le_slice(self, dest: &mut [u8]) {
+                    dest.copy_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
 }
 
-#[derive(Debug, PartialEq, Eq)]
-struct DBUInt64(u64);
+impl_little_endian_value!(u8, u16, u32, u64, i8, i16, i32, i64);
 
-impl DBUInt64 {
-    fn new() -> Self {
-        DBUInt64(0)
-    }
+pub fn read_value<T: LittleEndianValue>(buf: &[u8], offset: usize) -> T {
+    T::from_le_slice(&buf[offset..(offset+T::SIZE)])
 }
 
-impl DbValue for DBUInt64 {
-    fn size(&self) -> usize {
-        8
-    }
-
-    #[cfg(target_endian = "little")]
-    fn read_from_buffer(&mut self, buf: &[u8]) -> Result<(), String> {
-        if let [b0, b1, b2, b3, b4, b5, b6, b7] = buf {
-            self.0 =
-            (*b0 as u64)
-            | ((*b1 as u64) << 8)
-            | ((*b2 as u64) << 16)
-            | ((*b3 as u64) << 24)
-            | ((*b4 as u64) << 32)
-            | ((*b5 as u64) << 40)
-            | ((*b6 as u64) << 48)
-            | ((*b7 as u64) << 56);
-
-            Ok(())
-        } else {
-            Err(format!("Invalid buffer length: {}", buf.len()))
-        }
-    }
-
-    #[cfg(target_endian = "little")]
-    fn write_to_buffer(&self, buf: &mut [u8]) -> Result<(), String> {
-        if let [b0, b1, b2, b3, b4, b5, b6, b7] = buf {
-            *b0 = (self.0 & 0xff) as u8;
-            *b1 = ((self.0 >> 8) & 0xff) as u8;
-            *b2 = ((self.0 >> 16) & 0xff) as u8;
-            *b3 = ((self.0 >> 24) & 0xff) as u8;
-            *b4 = ((self.0 >> 32) & 0xff) as u8;
-            *b5 = ((self.0 >> 40) & 0xff) as u8;
-            *b6 = ((self.0 >> 48) & 0xff) as u8;
-            *b7 = ((self.0 >> 56) & 0xff) as u8;
-            Ok(())
-        } else {
-            Err(format!("Invalid buffer length: {}", buf.len()))
-        }
-    }
+pub fn write_value<T: LittleEndianValue>(buf: &mut [u8], offset: usize, val: T) {
+    val.write_le_slice(&mut buf[offset..(offset+T::SIZE)]);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::db_value::{DBExternalString, DBInlineString, DBUInt32, DBUInt64};
 
     #[test]
     fn read_data_from_buffer() {
@@ -220,6 +1324,14 @@ mod tests {
         assert_eq!(128, read_value::<u8>(&bytes, 15));
     }
 
+    #[test]
+    fn written_values_are_little_endian() {
+        let mut bytes = vec![0; 8];
+        write_value(&mut bytes, 0, 0x0102_0304_0506_0708u64);
+        assert_eq!(vec![8, 7, 6, 5, 4, 3, 2, 1], bytes);
+        assert_eq!(0x0102_0304_0506_0708u64, LittleEndian::read_u64(&bytes));
+    }
+
     #[test]
     fn fixed_row_length() {
         let schema1 = Rc::new(vec![
@@ -239,6 +1351,29 @@ mod tests {
         assert_eq!(17 + 2*POINTER_SIZE, table2.row_length());
     }
 
+    #[test]
+    fn schema_accessor_shares_schema() {
+        let schema = Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+        ]);
+        let table = Table::new("people", schema.clone());
+
+        assert_eq!("people", table.name());
+        assert_eq!(2, table.schema().len());
+        assert!(Rc::ptr_eq(&schema, &table.schema()));
+    }
+
+    #[test]
+    fn field_names_are_in_schema_order() {
+        let table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+        ]));
+        assert_eq!(vec!["name", "age", "id"], table.field_names());
+    }
+
     #[test]
     fn variable_row_length() {
         let table1 = Table::new("test 1", Rc::new(vec![
@@ -253,6 +1388,689 @@ mod tests {
         assert_eq!(287, table2.row_length());
     }
 
+    #[test]
+    fn oversized_row_length_is_an_error() {
+        let half = usize::MAX / 2 + 1;
+        let schema = Rc::new(vec![
+            FieldSpec::new("first", TypeSpec::new(DbType::Bytes(half), false, None)),
+            FieldSpec::new("second", TypeSpec::new(DbType::Bytes(half), false, None)),
+        ]);
+        let table = Table::new("huge", schema.clone());
+        assert_eq!(Err(TableError::RowTooLarge), table.checked_row_length());
+        assert_eq!(Err(TableError::RowTooLarge), RowLayout::new(&schema));
+
+        let mut database = Database::new();
+        assert_eq!(Err(TableError::RowTooLarge), database.create_table("huge", schema).map(|_| ()));
+        assert!(database.get_table("huge").is_none());
+    }
+
+    #[test]
+    fn bytes_row_length() {
+        let table = Table::new("hashes", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("sha256", TypeSpec::new(DbType::Bytes(32), false, None)),
+        ]));
+        assert_eq!(40, table.row_length());
+    }
+
+    #[test]
+    fn insert_rejects_wrong_arity() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+
+        let result = table.insert(&Tuple::new().with(DBUInt64(1)));
+        assert_eq!(Err(TableError::ArityMismatch { expected: 2, actual: 1 }), result);
+        assert_eq!(0, table.row_count());
+    }
+
+    #[test]
+    fn dedup_keeps_earliest_duplicate() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+
+        let rows = vec![(30, "likes tacos"), (30, "likes burritos"), (30, "likes tacos")];
+        for (age, notes) in rows {
+            table.insert(&Tuple::new()
+                .with(DBUInt32(age))
+                .with(DBExternalString(notes.to_string()))).unwrap();
+        }
+        assert_eq!(3, table.live_row_count());
+
+        table.dedup_rows();
+
+        assert_eq!(2, table.live_row_count());
+        assert!(!table.is_deleted(0));
+        assert!(!table.is_deleted(1));
+        assert!(table.is_deleted(2));
+    }
+
+    #[test]
+    fn sample_is_reproducible_and_distinct() {
+        let mut table = Table::new("counters", Rc::new(vec![
+            FieldSpec::new("count", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        for count in 0..20 {
+            table.insert(&Tuple::new().with(DBUInt32(count))).unwrap();
+        }
+        table.delete(3).unwrap();
+
+        let sample = table.sample(5, 42);
+        assert_eq!(sample, table.sample(5, 42));
+        assert_eq!(5, sample.iter().collect::<HashSet<_>>().len());
+        assert!(sample.iter().all(|&index| !table.is_deleted(index)));
+
+        let mut all = table.sample(100, 7);
+        all.sort();
+        assert_eq!((0..20).filter(|&index| index != 3).collect::<Vec<_>>(), all);
+    }
+
+    #[test]
+    fn compact_removes_deleted_rows() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        for (age, notes) in &[(31, "first"), (42, "second"), (53, "third"), (64, "fourth")] {
+            table.insert(&Tuple::new()
+                .with(DBUInt32(*age))
+                .with(DBExternalString(notes.to_string()))).unwrap();
+        }
+        table.delete(0).unwrap();
+        table.delete(2).unwrap();
+
+        table.compact_tombstones().unwrap();
+
+        assert_eq!(2, table.row_count());
+        assert_eq!(2, table.live_row_count());
+        assert_eq!(2 * table.row_length(), table.fixed_data.len());
+        assert_eq!("42", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!("second", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("64", table.get_field(1, "age").unwrap().to_display_string());
+        assert_eq!("fourth", table.get_field(1, "notes").unwrap().to_display_string());
+        assert_eq!(2 * POINTER_SIZE + "first".len() + "third".len(), table.variable_data.free_bytes());
+    }
+
+    #[test]
+    fn external_field_streams_into_writer() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(30)).with(DBExternalString("streamed".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(31)).with_null()).unwrap();
+
+        let mut out = vec![];
+        assert_eq!(8, table.read_field_into(0, "notes", &mut out).unwrap());
+        assert_eq!(b"streamed".to_vec(), out);
+        assert_eq!(0, table.read_field_into(1, "notes", &mut out).unwrap());
+        assert!(table.read_field_into(0, "age", &mut out).is_err());
+    }
+
+    #[test]
+    fn stale_version_update_is_rejected() {
+        let mut table = Table::new("counters", Rc::new(vec![
+            FieldSpec::new("count", TypeSpec::new(DbType::UInt32, true, None)),
+        ]));
+        table.insert(&Tuple::new().with_null()).unwrap();
+        assert_eq!(0, table.row_version(0).unwrap());
+
+        assert_eq!(1, table.update_if_version(0, "count", DBUInt32(5), 0).unwrap());
+        assert_eq!(
+            Err(TableError::VersionMismatch { expected: 0, actual: 1 }),
+            table.update_if_version(0, "count", DBUInt32(6), 0));
+        assert_eq!("5", table.get_field(0, "count").unwrap().to_display_string());
+
+        assert_eq!(2, table.update_if_version(0, "count", NullableValue::null(), 1).unwrap());
+        assert!(table.get_field(0, "count").unwrap().is_null());
+    }
+
+    #[test]
+    fn delete_where_deletes_matching_rows() {
+        let mut table = Table::new("users", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("is_active", TypeSpec::new(DbType::Boolean, false, None)),
+        ]));
+        for id in 0..10 {
+            table.insert(&Tuple::new().with(DBUInt32(id)).with(DBBoolean(id % 3 == 0))).unwrap();
+        }
+        table.delete(3).unwrap();
+
+        let offset = table.field_offset(1);
+        assert_eq!(6, table.delete_where(|row| row[offset] == 0));
+        let remaining: Vec<String> = (0..table.row_count())
+            .filter(|&index| !table.is_deleted(index))
+            .map(|index| table.get_field(index, "id").unwrap().to_display_string())
+            .collect();
+        assert_eq!(vec!["0", "6", "9"], remaining);
+        assert_eq!(0, table.delete_where(|row| row[offset] == 0));
+    }
+
+    #[test]
+    fn update_field_frees_old_heap_data() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(30)).with(DBExternalString("old".to_string()))).unwrap();
+
+        table.update_field(0, "notes", DBExternalString("new".to_string())).unwrap();
+        assert_eq!("30", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!("new", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!(POINTER_SIZE + 3, table.variable_data.free_bytes());
+        assert_eq!(1, table.row_version(0).unwrap());
+    }
+
+    #[test]
+    fn packed_booleans_take_a_bit_each() {
+        let mut fields = vec![FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None))];
+        for flag in 0..10 {
+            fields.push(FieldSpec::new(format!("flag{}", flag), TypeSpec::new(DbType::Boolean, flag == 9, None)));
+        }
+        let schema = Rc::new(fields);
+        let mut table = Table::new("flags", schema.clone()).with_packed_booleans();
+        assert_eq!(1 + 2 + 4, table.row_length());
+        assert_eq!(1 + 10 + 4, Table::new("flags", schema).row_length());
+
+        for id in 0..4u32 {
+            let mut tuple = Tuple::new().with(DBUInt32(id));
+            for flag in 0..9u32 {
+                tuple = tuple.with(DBBoolean((id + flag) % 3 == 0));
+            }
+            tuple = if id == 0 { tuple.with_null() } else { tuple.with(DBBoolean(true)) };
+            table.insert(&tuple).unwrap();
+        }
+        table.update_field(1, "flag0", DBBoolean(true)).unwrap();
+
+        let flag = |table: &Table, index: usize, flag: usize| {
+            table.get_field(index, &format!("flag{}", flag)).unwrap().to_display_string()
+        };
+        for id in 0..4 {
+            assert_eq!(id.to_string(), table.get_field(id, "id").unwrap().to_display_string());
+            for field in 1..9 {
+                assert_eq!(((id + field) % 3 == 0).to_string(), flag(&table, id, field));
+            }
+        }
+        assert_eq!(vec!["true", "true", "false", "true"], (0..4).map(|id| flag(&table, id, 0)).collect::<Vec<_>>());
+        assert!(table.is_null(0, "flag9"));
+        assert_eq!("true", flag(&table, 3, 9));
+        assert_eq!(vec![0, 1, 3], table.find_all("flag0", &DBBoolean(true)).unwrap());
+
+        table.drop_column("flag0").unwrap();
+        assert_eq!(1 + 2 + 4, table.row_length());
+        assert_eq!("true", flag(&table, 2, 1));
+    }
+
+    #[test]
+    fn set_null_and_clear_null_toggle_a_field() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(30)).with(DBExternalString("old".to_string()))).unwrap();
+        assert!(!table.is_null(0, "notes"));
+
+        table.set_null(0, "notes").unwrap();
+        assert!(table.is_null(0, "notes"));
+        assert!(table.get_field(0, "notes").unwrap().is_null());
+        assert_eq!(POINTER_SIZE + 3, table.variable_data.free_bytes());
+        assert_eq!("30", table.get_field(0, "age").unwrap().to_display_string());
+
+        table.clear_null(0, "notes").unwrap();
+        assert!(!table.is_null(0, "notes"));
+        assert_eq!("", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!(2, table.row_version(0).unwrap());
+        table.clear_null(0, "notes").unwrap();
+        assert_eq!(2, table.row_version(0).unwrap());
+    }
+
+    #[test]
+    fn set_null_needs_a_nullable_field() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(30))).unwrap();
+
+        assert_eq!(Err(TableError::NotNullable("age".to_string())), table.set_null(0, "age"));
+        assert!(!table.is_null(0, "age"));
+        assert_eq!("30", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!(Err(TableError::RowOutOfBounds(1)), table.set_null(1, "age"));
+        assert!(table.clear_null(0, "height").is_err());
+        assert_eq!(Err(TableError::RowOutOfBounds(1)), table.clear_null(1, "age"));
+        assert!(!table.is_null(1, "age"));
+    }
+
+    #[test]
+    fn rows_range_yields_live_window() {
+        let mut table = Table::new("counters", Rc::new(vec![
+            FieldSpec::new("count", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        for count in 0..10 {
+            table.insert(&Tuple::new().with(DBUInt32(count))).unwrap();
+        }
+        let counts = |table: &Table, start, end| -> Vec<u32> {
+            table.rows_range(start, end).map(LittleEndian::read_u32).collect()
+        };
+
+        assert_eq!(vec![3, 4, 5], counts(&table, 3, 6));
+        assert_eq!(vec![8, 9], counts(&table, 8, 100));
+        assert!(counts(&table, 12, 20).is_empty());
+        assert!(counts(&table, 6, 3).is_empty());
+
+        table.delete(4).unwrap();
+        assert_eq!(vec![3, 5], counts(&table, 3, 6));
+    }
+
+    #[test]
+    fn rows_enumerated_skips_deleted_rows() {
+        let mut table = Table::new("counters", Rc::new(vec![
+            FieldSpec::new("count", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        for count in &[10, 11, 12] {
+            table.insert(&Tuple::new().with(DBUInt32(*count))).unwrap();
+        }
+        table.delete(1).unwrap();
+
+        let rows: Vec<(usize, u32)> = table.rows_enumerated()
+            .map(|(index, row)| (index, LittleEndian::read_u32(row)))
+            .collect();
+        assert_eq!(vec![(0, 10), (2, 12)], rows);
+    }
+
+    #[test]
+    fn heap_offset_points_at_prefixed_value() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(1)).with(DBExternalString("first".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(2)).with(DBExternalString("second".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(3)).with_null()).unwrap();
+
+        let offset = table.heap_offset_of(1, "notes").unwrap();
+        let mut expected = vec![0u8; POINTER_SIZE];
+        LittleEndian::write_uint(&mut expected, 6, POINTER_SIZE);
+        expected.extend_from_slice(b"second");
+        assert_eq!(&expected[..], table.variable_data.get_slice(offset, POINTER_SIZE + 6));
+
+        assert_eq!(None, table.heap_offset_of(1, "age"));
+        assert_eq!(None, table.heap_offset_of(2, "notes"));
+        assert_eq!(None, table.heap_offset_of(3, "notes"));
+        assert_eq!(None, table.heap_offset_of(0, "missing"));
+    }
+
+    #[test]
+    fn array_column_lives_on_the_heap() {
+        let mut table = Table::new("tagged", Rc::new(vec![
+            FieldSpec::new("tags", TypeSpec::new(DbType::Array(Box::new(DbType::UInt32), None), false, None)),
+        ]));
+        let tags: Vec<Box<dyn DbValue>> = vec![Box::new(DBUInt32(7)), Box::new(DBUInt32(9))];
+        table.insert(&Tuple::new().with(DBArray::new(DbType::UInt32, tags))).unwrap();
+        assert_eq!("[7, 9]", table.get_field(0, "tags").unwrap().to_display_string());
+
+        table.update_field(0, "tags", DBArray::new(DbType::UInt32, vec![])).unwrap();
+        assert_eq!("[]", table.get_field(0, "tags").unwrap().to_display_string());
+        assert_eq!(POINTER_SIZE + 8, table.variable_data.free_bytes());
+    }
+
+    #[test]
+    fn money_column_reads_back_with_its_scale() {
+        let mut table = Table::new("prices", Rc::new(vec![
+            FieldSpec::new("price", TypeSpec::new(DbType::Money { scale: 2 }, false, None)),
+        ]));
+        assert_eq!(11, table.row_length());
+        table.insert(&Tuple::new().with("USD 12.34".parse::<DBMoney>().unwrap())).unwrap();
+        assert_eq!("USD 12.34", table.get_field(0, "price").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn money_with_another_scale_is_refused() {
+        let mut table = Table::new("prices", Rc::new(vec![
+            FieldSpec::new("price", TypeSpec::new(DbType::Money { scale: 2 }, false, None)),
+        ]));
+        assert_eq!(Err(TableError::TypeMismatch {
+            field: "price".to_string(),
+            expected: DbType::Money { scale: 2 },
+            actual: DbType::Money { scale: 0 },
+        }), table.insert(&Tuple::new().with("JPY 100".parse::<DBMoney>().unwrap())));
+        assert_eq!(0, table.row_count());
+
+        // Rescaled first, it goes in as the same amount
+        table.insert(&Tuple::new().with("JPY 100".parse::<DBMoney>().unwrap().with_scale(2).unwrap())).unwrap();
+        assert_eq!("JPY 100.00", table.get_field(0, "price").unwrap().to_display_string());
+        assert!(table.update_field(0, "price", "JPY 5.5".parse::<DBMoney>().unwrap()).is_err());
+    }
+
+    #[test]
+    fn adaptive_varchar_picks_storage_per_value() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("notes", TypeSpec::new(DbType::AdaptiveVarchar { max_len: 1000, inline_len: 16 }, false, None)),
+        ]));
+        assert_eq!(18, table.row_length());
+        let long_notes = "n".repeat(900);
+        table.insert(&Tuple::new().with(DBVarchar("abc".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBVarchar(long_notes.clone()))).unwrap();
+
+        assert_eq!(None, table.heap_span(0, 0));
+        assert_eq!(Some((0, POINTER_SIZE + 900)), table.heap_span(1, 0));
+        assert_eq!("abc", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!(long_notes, table.get_field(1, "notes").unwrap().to_display_string());
+
+        let mut out = vec![];
+        assert_eq!(3, table.read_field_into(0, "notes", &mut out).unwrap());
+        assert_eq!(b"abc".to_vec(), out);
+
+        table.update_field(1, "notes", DBVarchar("short now".to_string())).unwrap();
+        assert_eq!(None, table.heap_span(1, 0));
+        assert_eq!(POINTER_SIZE + 900, table.variable_data.free_bytes());
+        assert_eq!("short now", table.get_field(1, "notes").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn allocated_row_reads_back_defaults() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("is_active", TypeSpec::new(DbType::Boolean, false, Some(vec![1]))),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+
+        let index = table.allocate_row().unwrap();
+        assert_eq!("true", table.get_field(index, "is_active").unwrap().to_display_string());
+        assert_eq!("0", table.get_field(index, "age").unwrap().to_display_string());
+        assert_eq!("", table.get_field(index, "notes").unwrap().to_display_string());
+
+        table.update_field(index, "age", DBUInt32(30)).unwrap();
+        assert_eq!("30", table.get_field(index, "age").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn write_default_pads_with_zeros() {
+        let field = FieldSpec::new("port", TypeSpec::new(DbType::UInt32, false, Some(vec![0x90, 0x1f])));
+        let mut buf = [0xffu8; 4];
+        field.write_default(&mut buf);
+        assert_eq!([0x90, 0x1f, 0, 0], buf);
+    }
+
+    #[test]
+    fn nullable_fields_read_back_as_null() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+        ]));
+        assert_eq!(13, table.row_length());
+
+        table.insert(&Tuple::new().with(DBUInt64(1)).with_null()).unwrap();
+        table.insert(&Tuple::new().with(DBUInt64(2)).with(DBUInt32(42))).unwrap();
+
+        let null_age = table.get_field(0, "age").unwrap();
+        assert!(null_age.is_null());
+        assert_eq!("NULL", null_age.to_display_string());
+
+        let age = table.get_field(1, "age").unwrap();
+        assert!(!age.is_null());
+        assert_eq!("42", age.to_display_string());
+        assert_eq!("2", table.get_field(1, "id").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn replace_row_frees_old_heap_data() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        table.insert(&Tuple::new()
+            .with(DBUInt32(30))
+            .with(DBExternalString("short".to_string()))).unwrap();
+
+        let notes = "a considerably longer note".to_string();
+        table.replace_row(0, &Tuple::new()
+            .with(DBUInt32(31))
+            .with(DBExternalString(notes.clone()))).unwrap();
+
+        assert_eq!("31", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!(notes, table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!(POINTER_SIZE + "short".len(), table.variable_data.free_bytes());
+        let live_heap = table.variable_data.len() - table.variable_data.free_bytes();
+        assert_eq!(POINTER_SIZE + notes.len(), live_heap);
+    }
+
+    #[test]
+    fn failed_replace_leaves_row_unchanged() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(4), false, None)),
+        ]));
+        table.insert(&Tuple::new()
+            .with(DBExternalString("notes".to_string()))
+            .with(DBInlineString("bob".to_string()))).unwrap();
+        let heap_len = table.variable_data.len();
+
+        let result = table.replace_row(0, &Tuple::new()
+            .with(DBExternalString("new notes".to_string()))
+            .with(DBInlineString("too long".to_string())));
+
+        assert!(result.is_err());
+        assert_eq!(heap_len, table.variable_data.len());
+        assert_eq!(0, table.variable_data.free_bytes());
+        assert_eq!("notes", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("bob", table.get_field(0, "name").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn null_rejected_for_non_nullable_field() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+        ]));
+
+        let result = table.insert(&Tuple::new().with_null());
+        assert_eq!(Err(TableError::NotNullable("id".to_string())), result);
+    }
+
+    #[test]
+    fn defaults_are_validated_against_their_type() {
+        assert_eq!(Ok(()), TypeSpec::new(DbType::UInt32, false, Some(vec![7, 0, 0, 0])).validate());
+        assert_eq!(Ok(()), TypeSpec::new(DbType::UInt32, false, None).validate());
+        assert_eq!(
+            Err(TableError::InvalidDefault("UInt32: default must be 4 bytes, got 3".to_string())),
+            TypeSpec::new(DbType::UInt32, false, Some(vec![7, 0, 0])).validate());
+
+        assert_eq!(Ok(()), TypeSpec::new(DbType::Varchar(10), false, Some(b"\x03abc".to_vec())).validate());
+        assert_eq!(Ok(()), TypeSpec::new(DbType::LongVarchar(300), false, Some(b"\x02\x00hi".to_vec())).validate());
+        let adaptive = DbType::AdaptiveVarchar { max_len: 100, inline_len: 8 };
+        assert_eq!(Ok(()), TypeSpec::new(adaptive.clone(), false, Some(vec![VARCHAR_INLINE, 1, b'x'])).validate());
+
+        for bad in &[
+            TypeSpec::new(DbType::Varchar(10), false, Some(b"\x05abc".to_vec())),
+            TypeSpec::new(DbType::Varchar(2), false, Some(b"\x03abc".to_vec())),
+            TypeSpec::new(DbType::Varchar(10), false, Some(vec![2, 0xff, 0xfe])),
+            TypeSpec::new(DbType::Varchar(1000), false, Some(vec![0])),
+            TypeSpec::new(adaptive, false, Some(vec![VARCHAR_SPILLED, 0, 0, 0, 0, 0, 0, 0, 0])),
+            TypeSpec::new(DbType::IpAddr, false, Some(vec![9; 17])),
+        ] {
+            match bad.validate() {
+                Err(TableError::InvalidDefault(_)) => (),
+                other => panic!("Expected {:?} to be rejected, got {:?}", bad, other),
+            }
+        }
+    }
+
+    #[test]
+    fn row_converts_to_json() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), false, None)),
+            FieldSpec::new("active", TypeSpec::new(DbType::Boolean, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(20), true, None)),
+        ]));
+        table.insert(&Tuple::new()
+            .with(DBUInt64(7))
+            .with(DBInlineString("bob".to_string()))
+            .with(DBBoolean(true))
+            .with_null()).unwrap();
+
+        assert_eq!(serde_json::json!({
+            "id": 7,
+            "name": "bob",
+            "active": true,
+            "notes": null,
+        }), table.row_to_json(0).unwrap());
+        assert_eq!(Err(TableError::RowOutOfBounds(1)), table.row_to_json(1));
+    }
+
+    #[test]
+    fn heap_str_borrows_from_the_heap() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+        ]));
+        table.insert(&Tuple::new()
+            .with(DBInlineString("bob".to_string()))
+            .with(DBExternalString("kept on the heap".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBInlineString("alice".to_string())).with_null()).unwrap();
+
+        let notes = table.get_heap_str(0, "notes").unwrap().unwrap();
+        assert_eq!("kept on the heap", notes.as_str());
+        let heap = table.variable_data.as_slice().as_ptr_range();
+        let text = notes.as_bytes().as_ptr_range();
+        assert!(heap.start <= text.start && text.end <= heap.end);
+
+        assert_eq!(None, table.get_heap_str(1, "notes").unwrap());
+        assert!(table.get_heap_str(0, "name").is_err());
+    }
+
+    #[test]
+    fn swap_remove_moves_last_row_into_place() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        for name in &["alice", "bob", "carol"] {
+            table.insert(&Tuple::new()
+                .with(DBInlineString(name.to_string()))
+                .with(DBExternalString(format!("{} notes", name)))).unwrap();
+        }
+        table.set_primary_key("name").unwrap();
+        table.update_field(2, "notes", DBExternalString("carol's notes".to_string())).unwrap();
+
+        table.swap_remove_row(0).unwrap();
+        assert_eq!(2, table.row_count());
+        assert_eq!("carol", table.get_field(0, "name").unwrap().to_display_string());
+        assert_eq!("carol's notes", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!(1, table.row_version(0).unwrap());
+        assert_eq!("bob", table.get_field(1, "name").unwrap().to_display_string());
+        assert_eq!(Ok(()), table.verify_all());
+
+        // The removed key is free again and the moved one is still taken
+        table.insert(&Tuple::new()
+            .with(DBInlineString("alice".to_string()))
+            .with(DBExternalString(String::new()))).unwrap();
+        assert_eq!(Err(TableError::DuplicateKey("name".to_string())), table.insert(&Tuple::new()
+            .with(DBInlineString("carol".to_string()))
+            .with(DBExternalString(String::new()))));
+
+        table.swap_remove_row(2).unwrap();
+        assert_eq!(2, table.row_count());
+        assert_eq!(Err(TableError::RowOutOfBounds(2)), table.swap_remove_row(2));
+    }
+
+    #[test]
+    fn row_size_estimate_counts_heap_entries() {
+        let table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), false, None)),
+        ]));
+        let sample = Tuple::new()
+            .with(DBUInt32(30))
+            .with(DBExternalString("n".repeat(100)))
+            .with(DBInlineString("bob".to_string()));
+        assert_eq!(table.row_length() + 100 + POINTER_SIZE, table.estimate_row_size(&sample));
+
+        let sample = Tuple::new().with(DBUInt32(30)).with_null().with(DBInlineString("bob".to_string()));
+        assert_eq!(table.row_length(), table.estimate_row_size(&sample));
+        assert_eq!(0, table.row_count());
+    }
+
+    #[test]
+    fn long_varchar_stays_in_the_row() {
+        let mut table = Table::new("posts", Rc::new(vec![
+            FieldSpec::new("title", TypeSpec::new(DbType::LongVarchar(300), false, None)),
+            FieldSpec::new("body", TypeSpec::new(DbType::LongVarchar(60000), false, None)),
+        ]));
+        assert_eq!(2 + 300 + 2 + 60000, table.row_length());
+
+        let (title, body) = ("t".repeat(300), "b".repeat(60000));
+        table.insert(&Tuple::new().with(DBLongString(title.clone())).with(DBLongString(body.clone()))).unwrap();
+        table.insert(&Tuple::new().with(DBLongString("short".to_string())).with(DBLongString(String::new())))
+            .unwrap();
+        assert!(table.insert(&Tuple::new().with(DBLongString("t".repeat(301))).with(DBLongString(String::new())))
+            .is_err());
+
+        assert!(table.variable_data.is_empty());
+        assert_eq!(title, table.get_field(0, "title").unwrap().to_display_string());
+        assert_eq!(body, table.get_field(0, "body").unwrap().to_display_string());
+        assert_eq!("short", table.get_field(1, "title").unwrap().to_display_string());
+        assert_eq!("", table.get_field(1, "body").unwrap().to_display_string());
+    }
+
+    fn checked_table() -> Table {
+        Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(10), false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+        ]))
+    }
+
+    #[test]
+    fn checked_insert_accepts_matching_values() {
+        let mut table = checked_table();
+        table.insert_checked(&Tuple::new()
+            .with(DBInlineString("bob".to_string()))
+            .with(DBExternalString("stored on the heap".to_string()))
+            .with(DBUInt32(30))).unwrap();
+        table.insert_checked(&Tuple::new()
+            .with(DBInlineString("alice".to_string()))
+            .with_null()
+            .with(DBUInt32(31))).unwrap();
+
+        assert_eq!(2, table.row_count());
+        assert_eq!("stored on the heap", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("31", table.get_field(1, "age").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn checked_insert_rejects_mismatched_values() {
+        let mut table = checked_table();
+        let result = table.insert_checked(&Tuple::new()
+            .with(DBInlineString("bob".to_string()))
+            .with_null()
+            .with(DBBoolean(true)));
+        assert_eq!(Err(TableError::TypeMismatch {
+            field: "age".to_string(),
+            expected: DbType::UInt32,
+            actual: DbType::Boolean,
+        }), result);
+
+        // Too long for the column, and stored inline where the column wants
+        // the heap
+        let result = table.insert_checked(&Tuple::new()
+            .with(DBInlineString("much too long".to_string()))
+            .with(DBInlineString("inline".to_string()))
+            .with(DBUInt32(30)));
+        assert!(matches!(result, Err(TableError::TypeMismatch { ref field, .. }) if field == "name"));
+        let result = table.insert_checked(&Tuple::new()
+            .with(DBInlineString("bob".to_string()))
+            .with(DBInlineString("inline".to_string()))
+            .with(DBUInt32(30)));
+        assert!(matches!(result, Err(TableError::TypeMismatch { ref field, .. }) if field == "notes"));
+
+        assert_eq!(0, table.row_count());
+        assert!(table.variable_data.is_empty());
+    }
+
     // #[test]
     // fn write_tuple() {
     //     let schema = vec![
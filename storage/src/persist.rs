@@ -0,0 +1,520 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::checksum::crc32;
+use crate::db_value::DbHeap;
+use crate::{describe_schema, parse_schema, Schema, Table, TableError};
+
+// Every table file starts with these bytes
+const MAGIC: &[u8; 4] = b"RDBT";
+
+// The newest format version, which is always the one written
+const FORMAT_VERSION: u8 = 3;
+
+// Bits of the flags byte, each a choice made when the table was created
+const FLAG_PACKED_BOOLEANS: u8 = 1;
+// The file's integers, in its header and its rows, are big-endian. This
+// build always writes little-endian ones and refuses files with this set
+// rather than misreading them.
+const FLAG_BIG_ENDIAN: u8 = 2;
+
+// How much of a block is read at a time when loading
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+// Every self-describing table, from `to_bytes`, starts with these bytes
+const BUNDLE_MAGIC: &[u8; 4] = b"RDBB";
+
+// Whether saving waits for the file to reach the disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Durability {
+    // Returns once the OS has the data, which a crash or power loss can
+    // still lose. For tests and scratch files.
+    None,
+    // Returns once the OS reports the file's data and metadata are on disk
+    Fsync,
+}
+
+// How much smaller `compact_and_save` made a table's file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveStats {
+    // The size `save_to_path` would have written before compacting
+    pub bytes_before: u64,
+    pub bytes_written: u64,
+}
+
+impl SaveStats {
+    pub fn bytes_saved(&self) -> u64 {
+        self.bytes_before - self.bytes_written
+    }
+}
+
+// On-disk layout, all integers little-endian, as the flags record:
+//
+//   magic       4 bytes
+//   version     u8
+//   flags       u8, FLAG_* bits (version 3 onwards)
+//   name_len    u64, followed by the UTF-8 table name
+//   row_count   u64
+//   heap_len    u64
+//   fixed_data  row_count * row_length bytes
+//   tombstones  row_count bytes, 1 for a deleted row (version 2 onwards)
+//   heap        heap_len bytes
+//
+// Version 1 files have no tombstones, so every row in them is live, and
+// files before version 3 have no flags, so none of them are set.
+//
+// The schema is not stored, so it must be supplied when loading.
+impl Table {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), TableError> {
+        writer.write_all(MAGIC)?;
+        writer.write_u8(FORMAT_VERSION)?;
+        writer.write_u8(if self.packed_booleans { FLAG_PACKED_BOOLEANS } else { 0 })?;
+        writer.write_u64::<LittleEndian>(self.name.len() as u64)?;
+        writer.write_all(self.name.as_bytes())?;
+        writer.write_u64::<LittleEndian>(self.row_count() as u64)?;
+        writer.write_u64::<LittleEndian>(self.variable_data.len() as u64)?;
+        writer.write_all(self.fixed_data.read_at(0, self.fixed_data.len()))?;
+        let tombstones: Vec<u8> = self.tombstones.iter().map(|&deleted| deleted as u8).collect();
+        writer.write_all(&tombstones)?;
+        writer.write_all(self.variable_data.as_slice())?;
+
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R, schema: Rc<Schema>) -> Result<Table, TableError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(TableError::Corrupt("Not a table file".to_string()));
+        }
+
+        match reader.read_u8()? {
+            1 => read_body(reader, schema, false, 0),
+            2 => read_body(reader, schema, true, 0),
+            3 => {
+                let flags = reader.read_u8()?;
+                read_body(reader, schema, true, flags)
+            }
+            version => Err(TableError::UnsupportedVersion(version)),
+        }
+    }
+
+    // Encodes the table together with its schema, so it can be rebuilt
+    // without knowing the schema up front, e.g. after being sent to another
+    // node:
+    //
+    //   magic        4 bytes
+    //   schema_len   u64, followed by the schema as `describe_schema` text
+    //   schema_hash  u32, CRC-32 of the schema text
+    //   table        the table in the `write_to` format
+    //   checksum     u32, CRC-32 of everything before it
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let schema = describe_schema(&self.schema);
+        let mut bytes = BUNDLE_MAGIC.to_vec();
+        bytes.extend_from_slice(&(schema.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(schema.as_bytes());
+        bytes.extend_from_slice(&crc32(schema.as_bytes()).to_le_bytes());
+        self.write_to(&mut bytes).expect("writing to a Vec never fails");
+        let checksum = crc32(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    // Rebuilds a table from `to_bytes`. Fails if the bytes were altered, or
+    // if the schema doesn't describe itself the same way once parsed, which
+    // would mean rows are laid out differently than when they were written.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Table, TableError> {
+        if bytes.len() < BUNDLE_MAGIC.len() + 4 || &bytes[..BUNDLE_MAGIC.len()] != BUNDLE_MAGIC {
+            return Err(TableError::Corrupt("Not a table bundle".to_string()));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32(body) != (&checksum[..]).read_u32::<LittleEndian>()? {
+            return Err(TableError::Corrupt("Bundle checksum does not match".to_string()));
+        }
+
+        let mut reader = &body[BUNDLE_MAGIC.len()..];
+        let schema_len = reader.read_u64::<LittleEndian>()? as usize;
+        let text = String::from_utf8(read_block(&mut reader, schema_len)?)
+            .map_err(|_| TableError::Corrupt("Schema is not valid UTF-8".to_string()))?;
+        let schema = parse_schema(&text)?;
+        if crc32(describe_schema(&schema).as_bytes()) != reader.read_u32::<LittleEndian>()? {
+            return Err(TableError::Corrupt("Schema hash does not match".to_string()));
+        }
+
+        let table = Table::read_from(&mut reader, Rc::new(schema))?;
+        if !reader.is_empty() {
+            return Err(TableError::Corrupt(format!("{} bytes after the table", reader.len())));
+        }
+        Ok(table)
+    }
+
+    // Saves with `Durability::Fsync`: once this returns Ok, the file
+    // survives a crash
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> Result<(), TableError> {
+        self.save_to_path_with(path, Durability::Fsync)
+    }
+
+    pub fn save_to_path_with<P: AsRef<Path>>(&self, path: P, durability: Durability) -> Result<(), TableError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        if durability == Durability::Fsync {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // Drops deleted rows and the heap space nothing refers to any more, then
+    // saves what is left. Row indices change as for `compact_tombstones`;
+    // the primary key is rebuilt to match.
+    pub fn compact_and_save<P: AsRef<Path>>(&mut self, path: P) -> Result<SaveStats, TableError> {
+        let bytes_before = self.encoded_len();
+        self.compact_tombstones()?;
+        self.shrink_to_fit()?;
+        self.save_to_path(path)?;
+        Ok(SaveStats { bytes_before, bytes_written: self.encoded_len() })
+    }
+
+    // The number of bytes `write_to` writes
+    fn encoded_len(&self) -> u64 {
+        let header = MAGIC.len() + 1 + 1 + 8 + self.name.len() + 8 + 8;
+        (header + self.fixed_data.len() + self.row_count() + self.variable_data.len()) as u64
+    }
+
+    pub fn load_from_path<P: AsRef<Path>>(path: P, schema: Rc<Schema>) -> Result<Table, TableError> {
+        Table::load_from_reader(File::open(path)?, schema)
+    }
+
+    // Loads a table from any stream, such as a file or socket, buffering it.
+    // Blocks are read a chunk at a time and only grow as data arrives, so a
+    // stream that ends early, or a header claiming more than the stream
+    // holds, fails without first allocating the claimed size.
+    pub fn load_from_reader<R: Read>(reader: R, schema: Rc<Schema>) -> Result<Table, TableError> {
+        Table::read_from(&mut BufReader::new(reader), schema)
+    }
+
+    // Writes the same format as `save_to_path`, and syncs it as
+    // `Durability::Fsync` does, without blocking the reactor. The table is
+    // encoded up front so the returned future owns everything it needs.
+    #[cfg(feature = "async")]
+    pub fn save_to_path_async(&self, path: &Path)
+        -> impl tokio::prelude::Future<Item = (), Error = TableError>
+    {
+        use tokio::prelude::Future;
+
+        let mut bytes = vec![];
+        let encoded = self.write_to(&mut bytes);
+        let path = path.to_path_buf();
+
+        tokio::prelude::future::result(encoded)
+            .and_then(move |_| {
+                tokio::fs::File::create(path)
+                    .and_then(move |file| tokio::io::write_all(file, bytes))
+                    .and_then(|(file, _)| tokio::io::flush(file))
+                    .and_then(|mut file| tokio::prelude::future::poll_fn(move || file.poll_sync_all()))
+                    .map_err(TableError::from)
+            })
+    }
+}
+
+// Decodes everything after the version byte and flags. Versions 1 to 3
+// differ only in whether tombstones and flags are stored; a new version
+// that changes more than that should get its own decoder.
+fn read_body<R: Read>(reader: &mut R, schema: Rc<Schema>, has_tombstones: bool, flags: u8)
+    -> Result<Table, TableError>
+{
+    if flags & !(FLAG_PACKED_BOOLEANS | FLAG_BIG_ENDIAN) != 0 {
+        return Err(TableError::Corrupt(format!("Unknown table flags {:#04x}", flags)));
+    }
+    if flags & FLAG_BIG_ENDIAN != 0 {
+        return Err(TableError::WrongByteOrder);
+    }
+    let name_len = reader.read_u64::<LittleEndian>()? as usize;
+    let name = String::from_utf8(read_block(reader, name_len)?)
+        .map_err(|_| TableError::Corrupt("Table name is not valid UTF-8".to_string()))?;
+    let row_count = reader.read_u64::<LittleEndian>()? as usize;
+    let heap_len = reader.read_u64::<LittleEndian>()? as usize;
+
+    let mut table = Table::new(name, schema);
+    if flags & FLAG_PACKED_BOOLEANS != 0 {
+        table = table.with_packed_booleans();
+    }
+    let fixed_len = row_count.checked_mul(table.checked_row_length()?)
+        .ok_or_else(|| TableError::Corrupt(format!("Row count {} is too large", row_count)))?;
+    table.fixed_data = Box::new(read_block(reader, fixed_len)?);
+    table.tombstones = if has_tombstones {
+        read_block(reader, row_count)?.into_iter().map(|b| b == 1).collect()
+    } else {
+        vec![false; row_count]
+    };
+    table.row_versions = vec![0; row_count];
+    table.variable_data = DbHeap::from_vec(read_block(reader, heap_len)?);
+    table.record_all_checksums();
+
+    Ok(table)
+}
+
+// Reads exactly `len` bytes, a chunk at a time, without trusting `len` for
+// the allocation size
+fn read_block<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, TableError> {
+    let mut buf = Vec::with_capacity(len.min(READ_CHUNK_SIZE));
+    while buf.len() < len {
+        let chunk = (len - buf.len()).min(READ_CHUNK_SIZE);
+        let read = reader.take(chunk as u64).read_to_end(&mut buf)?;
+        if read < chunk {
+            return Err(TableError::Corrupt(format!("Expected {} bytes, found {}", len, buf.len())));
+        }
+    }
+    Ok(buf)
+}
+
+impl From<io::Error> for TableError {
+    fn from(err: io::Error) -> Self {
+        TableError::Io(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBBoolean, DBExternalString, DBUInt32};
+    use crate::{DbType, FieldSpec, Tuple, TypeSpec, POINTER_SIZE};
+    use std::env;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn test_schema() -> Rc<Schema> {
+        Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ])
+    }
+
+    fn test_table() -> Table {
+        let mut table = Table::new("people", test_schema());
+        for (age, notes) in &[(31, "first"), (42, "second"), (53, "third")] {
+            table.insert(&Tuple::new()
+                .with(DBUInt32(*age))
+                .with(DBExternalString(notes.to_string()))).unwrap();
+        }
+        table.delete(1).unwrap();
+        table
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ransomdb_{}_{}.tbl", name, process::id()))
+    }
+
+    fn assert_same_contents(expected: &Table, actual: &Table) {
+        assert_eq!(expected.name, actual.name);
+        assert_eq!(
+            expected.fixed_data.read_at(0, expected.fixed_data.len()),
+            actual.fixed_data.read_at(0, actual.fixed_data.len()));
+        assert_eq!(expected.tombstones, actual.tombstones);
+        assert_eq!(expected.variable_data.as_slice(), actual.variable_data.as_slice());
+    }
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let table = test_table();
+        let path = temp_path("roundtrip");
+
+        table.save_to_path(&path).unwrap();
+        let loaded = Table::load_from_path(&path, test_schema()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_same_contents(&table, &loaded);
+    }
+
+    #[test]
+    fn save_without_fsync_writes_the_table() {
+        let table = test_table();
+        let path = temp_path("no_fsync");
+
+        table.save_to_path_with(&path, Durability::None).unwrap();
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut expected = vec![];
+        table.write_to(&mut expected).unwrap();
+        assert_eq!(expected, saved);
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let mut bytes = vec![];
+        test_table().write_to(&mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+
+        match Table::read_from(&mut &bytes[..], test_schema()) {
+            Err(TableError::Corrupt(_)) => (),
+            other => panic!("Expected a corrupt table error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn version_1_file_loads_with_all_rows_live() {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.write_u8(1).unwrap();
+        bytes.write_u64::<LittleEndian>(6).unwrap();
+        bytes.extend_from_slice(b"people");
+        bytes.write_u64::<LittleEndian>(1).unwrap();
+        bytes.write_u64::<LittleEndian>((POINTER_SIZE + 5) as u64).unwrap();
+        // One row: age, then a heap slot pointing at offset 0
+        bytes.write_u32::<LittleEndian>(42).unwrap();
+        bytes.write_uint::<LittleEndian>(0, POINTER_SIZE).unwrap();
+        bytes.extend_from_slice(&[0, 0]);
+        // The heap: a length-prefixed string
+        bytes.write_uint::<LittleEndian>(5, POINTER_SIZE).unwrap();
+        bytes.extend_from_slice(b"hello");
+
+        let table = Table::read_from(&mut &bytes[..], test_schema()).unwrap();
+        assert_eq!("people", table.name);
+        assert_eq!(1, table.live_row_count());
+        assert_eq!("42", table.get_field(0, "age").unwrap().to_display_string());
+        assert_eq!("hello", table.get_field(0, "notes").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let mut bytes = vec![];
+        test_table().write_to(&mut bytes).unwrap();
+        bytes[MAGIC.len()] = FORMAT_VERSION + 1;
+
+        match Table::read_from(&mut &bytes[..], test_schema()) {
+            Err(TableError::UnsupportedVersion(version)) => assert_eq!(FORMAT_VERSION + 1, version),
+            other => panic!("Expected an unsupported version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn packed_booleans_are_kept_when_loading() {
+        let schema = Rc::new(vec![
+            FieldSpec::new("seen", TypeSpec::new(DbType::Boolean, false, None)),
+            FieldSpec::new("starred", TypeSpec::new(DbType::Boolean, false, None)),
+        ]);
+        let mut table = Table::new("flags", schema.clone()).with_packed_booleans();
+        table.insert(&Tuple::new().with(DBBoolean(false)).with(DBBoolean(true))).unwrap();
+        let mut bytes = vec![];
+        table.write_to(&mut bytes).unwrap();
+
+        let loaded = Table::read_from(&mut &bytes[..], schema.clone()).unwrap();
+        assert!(loaded.packs_booleans());
+        assert_same_contents(&table, &loaded);
+        assert_eq!("true", loaded.get_field(0, "starred").unwrap().to_display_string());
+
+        bytes[MAGIC.len() + 1] = 0x80;
+        match Table::read_from(&mut &bytes[..], schema) {
+            Err(TableError::Corrupt(_)) => (),
+            other => panic!("Expected a corrupt table error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn big_endian_files_are_refused() {
+        let mut table = test_table();
+        table.insert(&Tuple::new().with(DBUInt32(64)).with(DBExternalString("note".to_string()))).unwrap();
+        let mut bytes = vec![];
+        table.write_to(&mut bytes).unwrap();
+        assert_eq!(0, bytes[MAGIC.len() + 1] & FLAG_BIG_ENDIAN);
+
+        bytes[MAGIC.len() + 1] |= FLAG_BIG_ENDIAN;
+        let err = Table::read_from(&mut &bytes[..], table.schema.clone()).unwrap_err();
+        assert_eq!(TableError::WrongByteOrder, err);
+        assert_eq!("Table file is big-endian; only little-endian table files can be loaded", err.to_string());
+    }
+
+    #[test]
+    fn bytes_roundtrip_with_schema() {
+        let mut table = test_table();
+        table.insert(&Tuple::new()
+            .with(DBUInt32(64))
+            .with(DBExternalString("a longer note kept on the heap".to_string()))).unwrap();
+
+        let loaded = Table::from_bytes(&table.to_bytes()).unwrap();
+        assert_same_contents(&table, &loaded);
+        assert_eq!(*table.schema, *loaded.schema);
+        assert_eq!("a longer note kept on the heap", loaded.get_field(3, "notes").unwrap().to_display_string());
+        assert!(loaded.is_deleted(1));
+    }
+
+    #[test]
+    fn altered_bytes_are_rejected() {
+        let bytes = test_table().to_bytes();
+        for &offset in &[0, 12, bytes.len() / 2, bytes.len() - 1] {
+            let mut altered = bytes.clone();
+            altered[offset] ^= 0x01;
+            match Table::from_bytes(&altered) {
+                Err(TableError::Corrupt(_)) => (),
+                other => panic!("Expected a corrupt table error, got {:?}", other),
+            }
+        }
+        assert!(Table::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn compact_and_save_writes_only_live_data() {
+        let mut table = test_table();
+        table.update_field(0, "notes", DBExternalString("a replacement note".to_string())).unwrap();
+        let plain_path = temp_path("plain");
+        let compact_path = temp_path("compact");
+
+        table.save_to_path(&plain_path).unwrap();
+        let stats = table.compact_and_save(&compact_path).unwrap();
+        let plain_len = std::fs::metadata(&plain_path).unwrap().len();
+        let compact_len = std::fs::metadata(&compact_path).unwrap().len();
+        let loaded = Table::load_from_path(&compact_path, test_schema()).unwrap();
+        std::fs::remove_file(&plain_path).unwrap();
+        std::fs::remove_file(&compact_path).unwrap();
+
+        assert_eq!(SaveStats { bytes_before: plain_len, bytes_written: compact_len }, stats);
+        assert!(stats.bytes_saved() > 0);
+        assert_eq!(2, loaded.row_count());
+        assert_eq!("a replacement note", loaded.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("53", loaded.get_field(1, "age").unwrap().to_display_string());
+        assert_eq!("third", loaded.get_field(1, "notes").unwrap().to_display_string());
+        assert_eq!(Ok(()), loaded.verify_all());
+    }
+
+    #[test]
+    fn load_from_reader_streams_large_tables() {
+        let mut table = Table::new("people", test_schema());
+        for age in 0..20_000 {
+            table.insert(&Tuple::new()
+                .with(DBUInt32(age))
+                .with(DBExternalString(format!("note number {}", age)))).unwrap();
+        }
+        table.delete(7).unwrap();
+        let mut bytes = vec![];
+        table.write_to(&mut bytes).unwrap();
+        assert!(table.fixed_data.len() > READ_CHUNK_SIZE);
+
+        let loaded = Table::load_from_reader(Cursor::new(&bytes), test_schema()).unwrap();
+        assert_same_contents(&table, &loaded);
+        assert_eq!("note number 19999", loaded.get_field(19_999, "notes").unwrap().to_display_string());
+
+        match Table::load_from_reader(Cursor::new(&bytes[..bytes.len() - READ_CHUNK_SIZE]), test_schema()) {
+            Err(TableError::Corrupt(_)) => (),
+            other => panic!("Expected a corrupt table error, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_save_matches_sync_load() {
+        let table = test_table();
+        let path = temp_path("async");
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(table.save_to_path_async(&path)).unwrap();
+        let loaded = Table::load_from_path(&path, test_schema()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_same_contents(&table, &loaded);
+    }
+}
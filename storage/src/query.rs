@@ -0,0 +1,307 @@
+use crate::db_value::DbValue;
+use crate::{Row, Table, TableError};
+
+// The live rows of a scan, decoded one at a time as they are pulled. A row
+// is only decoded once the filter has picked it, so rows that are skipped
+// are never decoded at all, and only the current row is held.
+pub struct Cursor<'a> {
+    table: &'a Table,
+    next_index: usize,
+    // Whether the row at an index belongs in the scan
+    filter: Box<dyn Fn(usize) -> Result<bool, TableError> + 'a>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new<F>(table: &'a Table, filter: F) -> Self where F: Fn(usize) -> Result<bool, TableError> + 'a {
+        Cursor { table, next_index: 0, filter: Box::new(filter) }
+    }
+
+    // The index of the row `next` returned last
+    pub fn index(&self) -> Option<usize> {
+        self.next_index.checked_sub(1)
+    }
+}
+
+impl<'a> Iterator for Cursor<'a> {
+    type Item = Result<Row, TableError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next_index < self.table.row_count() {
+            let index = self.next_index;
+            self.next_index += 1;
+            if self.table.tombstones[index] {
+                continue;
+            }
+            match (self.filter)(index) {
+                Ok(true) => return Some(self.table.get_row(index)),
+                Ok(false) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
+impl Table {
+    // Every live row, decoded as the cursor reaches it
+    pub fn scan(&self) -> Cursor<'_> {
+        Cursor::new(self, |_| Ok(true))
+    }
+
+    // The live rows whose fixed bytes match `pred`, as `count_where` picks
+    // them, decoded as the cursor reaches them
+    pub fn scan_where<'a, F>(&'a self, pred: F) -> Cursor<'a> where F: Fn(&[u8]) -> bool + 'a {
+        Cursor::new(self, move |index| Ok(pred(self.row(index))))
+    }
+
+    // The live rows whose field equals `value`, as `find_all` matches them.
+    // Only that field is decoded to decide, and the rest of a row only once
+    // it matches.
+    pub fn find<'a>(&'a self, field_name: &str, value: &'a dyn DbValue) -> Result<Cursor<'a>, TableError> {
+        let field_name = self.schema[self.field_index(field_name)?].name.clone();
+        Ok(Cursor::new(self, move |index| {
+            Ok(self.get_field(index, &field_name)?.value().is_some_and(|field| field.eq_dyn(value)))
+        }))
+    }
+
+    // The indices of the live rows whose field equals `value`, found by
+    // reading every row. NULL fields never match.
+    pub fn find_all(&self, field_name: &str, value: &dyn DbValue) -> Result<Vec<usize>, TableError> {
+        self.field_index(field_name)?;
+        let mut matches = vec![];
+        for index in (0..self.row_count()).filter(|&index| !self.tombstones[index]) {
+            if let Some(field) = self.get_field(index, field_name)?.value() {
+                if field.eq_dyn(value) {
+                    matches.push(index);
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    // The number of live rows whose fixed bytes match `pred`
+    pub fn count_where<F>(&self, pred: F) -> usize where F: Fn(&[u8]) -> bool {
+        (0..self.row_count())
+            .filter(|&index| !self.tombstones[index] && pred(self.row(index)))
+            .count()
+    }
+
+    // Decodes one field from each live row, in row order, reading only that
+    // field's bytes (and its null bit) from the fixed rows. NULL values are
+    // skipped. Heap-backed values are read from the heap.
+    pub fn column_iter<'a>(&'a self, field_name: &str)
+        -> Result<impl Iterator<Item = Result<Box<dyn DbValue>, TableError>> + 'a, TableError>
+    {
+        let field_index = self.field_index(field_name)?;
+        let db_type = &self.schema[field_index].type_spec.db_type;
+        if db_type.new_value().is_none() {
+            return Err(TableError::UnsupportedType(format!("{:?}", db_type)));
+        }
+        let row_length = self.row_length();
+        let offset = self.field_offset(field_index);
+        let size = self.schema[field_index].size();
+        let null_bit = self.null_bit(field_index);
+        let packed_bit = self.row_layout().packed_bit(field_index);
+
+        Ok((0..self.row_count())
+            .filter(move |&index| !self.tombstones[index])
+            .filter_map(move |index| {
+                let start = index * row_length;
+                if let Some(bit) = null_bit {
+                    if self.fixed_data.read_at(start + bit / 8, 1)[0] & (1 << (bit % 8)) != 0 {
+                        return None;
+                    }
+                }
+                let mut value = db_type.new_value().unwrap();
+                let unpacked;
+                let field = match packed_bit {
+                    Some(bit) => {
+                        unpacked = [(self.fixed_data.read_at(start + bit / 8, 1)[0] >> (bit % 8)) & 1];
+                        &unpacked[..]
+                    }
+                    None => self.fixed_data.read_at(start + offset, size),
+                };
+                Some(value.read_from_buffer(field, &self.variable_data)
+                    .map(|_| value)
+                    .map_err(TableError::from))
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DbHeap, DBBoolean, DBExternalString, DBUInt32, DBUInt64};
+    use crate::{Backend, DbType, FieldSpec, Tuple, TypeSpec};
+    use std::cell::{Cell, RefCell};
+    use std::io;
+    use std::rc::Rc;
+
+    // A Vec backend that records the byte ranges read from it
+    #[derive(Debug)]
+    struct RecordingBackend {
+        bytes: Vec<u8>,
+        reads: Rc<RefCell<Vec<(usize, usize)>>>,
+    }
+
+    impl Backend for RecordingBackend {
+        fn len(&self) -> usize {
+            self.bytes.len()
+        }
+
+        fn read_at(&self, offset: usize, len: usize) -> &[u8] {
+            self.reads.borrow_mut().push((offset, len));
+            self.bytes.read_at(offset, len)
+        }
+
+        fn write_at(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+            self.bytes.write_at(offset, bytes)
+        }
+
+        fn append(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            Backend::append(&mut self.bytes, bytes)
+        }
+
+        fn truncate(&mut self, len: usize) {
+            self.bytes.truncate(len);
+        }
+    }
+
+    #[test]
+    fn column_iter_reads_only_its_column() {
+        let reads = Rc::new(RefCell::new(vec![]));
+        let backend = RecordingBackend { bytes: vec![], reads: reads.clone() };
+        let mut table = Table::with_backends("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]), Box::new(backend), DbHeap::new());
+        for (id, age) in [(1, 30), (2, 41), (3, 52)].iter() {
+            table.insert(&Tuple::new()
+                .with(DBUInt64(*id))
+                .with(DBUInt32(*age))
+                .with(DBExternalString(format!("person {}", id)))).unwrap();
+        }
+
+        reads.borrow_mut().clear();
+        let ages: Vec<String> = table.column_iter("age").unwrap()
+            .map(|age| age.unwrap().to_display_string())
+            .collect();
+        assert_eq!(vec!["30", "41", "52"], ages);
+
+        // 1 byte of null bitmap, 8 of id, then 4 of age
+        let row_length = table.row_length();
+        for &(offset, len) in reads.borrow().iter() {
+            let (row_offset, row) = (offset % row_length, offset / row_length);
+            assert!(row < 3);
+            assert!((row_offset, len) == (0, 1) || (row_offset, len) == (9, 4), "read {} bytes at {}", len, offset);
+        }
+        assert_eq!(6, reads.borrow().len());
+
+        let notes: Vec<String> = table.column_iter("notes").unwrap()
+            .map(|note| note.unwrap().to_display_string())
+            .collect();
+        assert_eq!(vec!["person 1", "person 2", "person 3"], notes);
+    }
+
+    #[test]
+    fn column_iter_skips_nulls_and_deleted_rows() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBUInt32(1))).unwrap();
+        table.insert(&Tuple::new().with_null()).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(3))).unwrap();
+        table.insert(&Tuple::new().with(DBUInt32(4))).unwrap();
+        table.delete(2).unwrap();
+
+        let ages: Vec<String> = table.column_iter("age").unwrap()
+            .map(|age| age.unwrap().to_display_string())
+            .collect();
+        assert_eq!(vec!["1", "4"], ages);
+        assert!(table.column_iter("missing").is_err());
+    }
+
+    #[test]
+    fn find_all_active_users() {
+        let mut table = Table::new("users", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("is_active", TypeSpec::new(DbType::Boolean, true, None)),
+        ]));
+        for (id, active) in [Some(true), Some(false), Some(true), None, Some(true)].iter().enumerate() {
+            let tuple = Tuple::new().with(DBUInt32(id as u32));
+            table.insert(&match *active {
+                Some(active) => tuple.with(DBBoolean(active)),
+                None => tuple.with_null(),
+            }).unwrap();
+        }
+        table.delete(4).unwrap();
+
+        assert_eq!(vec![0, 2], table.find_all("is_active", &DBBoolean(true)).unwrap());
+        assert_eq!(vec![1], table.find_all("is_active", &DBBoolean(false)).unwrap());
+        assert!(table.find_all("is_active", &DBUInt32(1)).unwrap().is_empty());
+        assert_eq!(Err(TableError::UnknownField("active".to_string())),
+            table.find_all("active", &DBBoolean(true)));
+    }
+
+    #[test]
+    fn count_where_counts_live_matches() {
+        let mut table = Table::new("users", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        for id in 0..25 {
+            table.insert(&Tuple::new().with(DBUInt32(id))).unwrap();
+        }
+        table.delete(4).unwrap();
+        table.delete(5).unwrap();
+
+        let offset = table.field_offset(0);
+        let is_even = |row: &[u8]| crate::read_value::<u32>(row, offset).is_multiple_of(2);
+        assert_eq!(12, table.count_where(is_even));
+        assert_eq!(23, table.count_where(|_| true));
+    }
+
+    #[test]
+    fn cursor_decodes_rows_as_they_are_pulled() {
+        let reads = Rc::new(RefCell::new(vec![]));
+        let heap = DbHeap::with_backend(Box::new(RecordingBackend { bytes: vec![], reads: reads.clone() }));
+        let mut table = Table::with_backends("people", Rc::new(vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]), Box::new(Vec::new()), heap);
+        for id in 0..6 {
+            table.insert(&Tuple::new().with(DBUInt32(id)).with(DBExternalString(format!("person {}", id)))).unwrap();
+        }
+        table.delete(3).unwrap();
+
+        let offset = table.field_offset(0);
+        let checked = Cell::new(0);
+        let is_odd = |row: &[u8]| {
+            checked.set(checked.get() + 1);
+            crate::read_value::<u32>(row, offset) % 2 == 1
+        };
+        reads.borrow_mut().clear();
+        let mut cursor = table.scan_where(is_odd);
+        assert_eq!((0, 0), (checked.get(), reads.borrow().len()));
+
+        // Only the row returned has its notes read from the heap
+        let first = cursor.next().unwrap().unwrap();
+        assert_eq!("person 1", first.get("notes").unwrap().to_display_string());
+        assert_eq!((2, Some(1)), (checked.get(), cursor.index()));
+        let heap_reads = reads.borrow().len();
+        assert!(heap_reads > 0);
+
+        // The deleted row 3 is skipped without being looked at
+        let ids: Vec<String> = cursor.map(|row| row.unwrap().get("id").unwrap().to_display_string()).collect();
+        assert_eq!(vec!["5"], ids);
+        assert_eq!(5, checked.get());
+        assert_eq!(2 * heap_reads, reads.borrow().len());
+
+        let found: Vec<String> = table.find("notes", &DBExternalString("person 4".to_string())).unwrap()
+            .map(|row| row.unwrap().get("id").unwrap().to_display_string())
+            .collect();
+        assert_eq!(vec!["4"], found);
+        assert_eq!(5, table.scan().count());
+        assert!(table.find("missing", &DBUInt32(1)).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{Table, TableError, POINTER_SIZE};
+
+// Rows can be copied between tables with the same schema as raw bytes, for
+// example to replicate an insert to another node. An exported row comes
+// with the heap entries it references, and its heap pointers are offsets
+// into those entries rather than into the source table's heap.
+impl Table {
+    // Returns a row's fixed bytes and the heap data they refer to
+    pub fn export_row(&self, index: usize) -> Result<(Vec<u8>, Vec<u8>), TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+
+        let mut row = self.row(index).to_vec();
+        let mut heap = vec![];
+        for field_index in 0..self.schema.len() {
+            if let Some((heap_offset, len)) = self.heap_span(index, field_index) {
+                let pointer = self.heap_pointer(&row, field_index).unwrap();
+                LittleEndian::write_uint(&mut row[pointer..], heap.len() as u64, POINTER_SIZE);
+                heap.extend_from_slice(self.variable_data.get_slice(heap_offset, len));
+            }
+        }
+
+        Ok((row, heap))
+    }
+
+    // Appends a row taken from `export_row` and returns its index. The row
+    // is checked against the primary key like any other insert.
+    pub fn import_row(&mut self, row: &[u8], heap: &[u8]) -> Result<usize, TableError> {
+        if row.len() != self.row_length() {
+            return Err(TableError::InvalidRowLength { expected: self.row_length(), actual: row.len() });
+        }
+
+        let heap_len = self.variable_data.len();
+        // Where the entries will be once appended, after any padding
+        let base = self.variable_data.aligned(heap_len);
+        let mut row = row.to_vec();
+        for field_index in 0..self.schema.len() {
+            let pointer = match self.heap_pointer(&row, field_index) {
+                Some(pointer) => pointer,
+                None => continue,
+            };
+            let heap_offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+            if !entry_in_bounds(heap, heap_offset) {
+                return Err(TableError::Corrupt(format!("Heap offset {} is out of bounds", heap_offset)));
+            }
+            LittleEndian::write_uint(&mut row[pointer..], (base + heap_offset) as u64, POINTER_SIZE);
+        }
+
+        self.variable_data.append_data(&mut heap.to_vec())?;
+        self.append_row(&row, heap_len)
+    }
+
+    // Appends a copy of a row and returns its index. The copy gets its own
+    // copies of the row's heap data, so changing one row never changes the
+    // other. The copy is live even if the original was deleted, and is
+    // checked against the primary key like any other insert.
+    pub fn copy_row(&mut self, src_index: usize) -> Result<usize, TableError> {
+        let (row, heap) = self.export_row(src_index)?;
+        self.import_row(&row, &heap)
+    }
+}
+
+// Whether a length-prefixed heap entry starting at `offset` fits in `heap`
+fn entry_in_bounds(heap: &[u8], offset: usize) -> bool {
+    if offset + POINTER_SIZE > heap.len() {
+        return false;
+    }
+    let len = LittleEndian::read_uint(&heap[offset..], POINTER_SIZE) as usize;
+    len <= heap.len() - offset - POINTER_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBUInt32, DBVarchar};
+    use crate::{DbType, FieldSpec, Schema, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn test_schema() -> Rc<Schema> {
+        Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+        ])
+    }
+
+    #[test]
+    fn exported_row_imports_into_other_table() {
+        let mut source = Table::new("people", test_schema());
+        source.insert(&Tuple::new().with(DBUInt32(1)).with(DBExternalString("padding".to_string()))).unwrap();
+        source.insert(&Tuple::new().with(DBUInt32(2)).with(DBExternalString("copied".to_string()))).unwrap();
+        source.insert(&Tuple::new().with(DBUInt32(3)).with_null()).unwrap();
+
+        let mut dest = Table::new("people", test_schema());
+        dest.insert(&Tuple::new().with(DBUInt32(9)).with(DBExternalString("existing".to_string()))).unwrap();
+        for index in 1..3 {
+            let (row, heap) = source.export_row(index).unwrap();
+            dest.import_row(&row, &heap).unwrap();
+        }
+
+        assert_eq!("existing", dest.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("2", dest.get_field(1, "age").unwrap().to_display_string());
+        assert_eq!("copied", dest.get_field(1, "notes").unwrap().to_display_string());
+        assert!(dest.get_field(2, "notes").unwrap().is_null());
+    }
+
+    #[test]
+    fn adaptive_varchar_rows_are_rebased() {
+        let schema = Rc::new(vec![
+            FieldSpec::new("notes", TypeSpec::new(DbType::AdaptiveVarchar { max_len: 1000, inline_len: 8 }, false, None)),
+        ]);
+        let mut source = Table::new("people", schema.clone());
+        source.insert(&Tuple::new().with(DBVarchar("spilled to the heap".to_string()))).unwrap();
+
+        let mut dest = Table::new("people", schema);
+        dest.insert(&Tuple::new().with(DBVarchar("already on the heap".to_string()))).unwrap();
+        let (row, heap) = source.export_row(0).unwrap();
+        dest.import_row(&row, &heap).unwrap();
+
+        assert_eq!("already on the heap", dest.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("spilled to the heap", dest.get_field(1, "notes").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn copied_row_has_its_own_heap_data() {
+        let mut table = Table::new("people", test_schema());
+        table.insert(&Tuple::new().with(DBUInt32(1)).with(DBExternalString("original".to_string()))).unwrap();
+
+        let copy = table.copy_row(0).unwrap();
+        assert_eq!(1, copy);
+        assert_ne!(table.heap_offset_of(0, "notes"), table.heap_offset_of(copy, "notes"));
+        table.update_field(copy, "notes", DBExternalString("changed".to_string())).unwrap();
+
+        assert_eq!("original", table.get_field(0, "notes").unwrap().to_display_string());
+        assert_eq!("changed", table.get_field(copy, "notes").unwrap().to_display_string());
+        assert_eq!("1", table.get_field(copy, "age").unwrap().to_display_string());
+        assert_eq!(Err(TableError::RowOutOfBounds(2)), table.copy_row(2));
+
+        let mut keyed = Table::new("people", test_schema());
+        keyed.set_primary_key("age").unwrap();
+        keyed.insert(&Tuple::new().with(DBUInt32(1)).with_null()).unwrap();
+        assert!(matches!(keyed.copy_row(0), Err(TableError::DuplicateKey(_))));
+        assert_eq!(1, keyed.row_count());
+    }
+
+    #[test]
+    fn import_rejects_dangling_heap_offset() {
+        let mut source = Table::new("people", test_schema());
+        source.insert(&Tuple::new().with(DBUInt32(1)).with(DBExternalString("notes".to_string()))).unwrap();
+        let (row, heap) = source.export_row(0).unwrap();
+
+        let mut dest = Table::new("people", test_schema());
+        match dest.import_row(&row, &heap[..heap.len() - 1]) {
+            Err(TableError::Corrupt(_)) => (),
+            other => panic!("Expected a corrupt row error, got {:?}", other),
+        }
+        assert_eq!(0, dest.row_count());
+        assert!(dest.variable_data.is_empty());
+    }
+}
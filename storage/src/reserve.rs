@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use crate::db_value::NullableValue;
+use crate::{Table, TableError};
+
+impl Table {
+    // Starts a row that is filled in over several steps: appends a row with
+    // every field at its default, as `allocate_row` does, and returns its
+    // index. Fields are then written with `set_field`, and `finalize_row`
+    // checks nothing required was left out. Until then the row is live like
+    // any other.
+    pub fn reserve_row(&mut self) -> Result<usize, TableError> {
+        let index = self.allocate_row()?;
+        self.reserved.insert(index, HashSet::new());
+        Ok(index)
+    }
+
+    // Writes one field of a row, as `update_field` does, and records it as
+    // set if the row is reserved
+    pub fn set_field<V>(&mut self, index: usize, field_name: &str, value: V) -> Result<(), TableError>
+        where V: Into<NullableValue>
+    {
+        self.update_field(index, field_name, value)?;
+        if let Some(fields) = self.reserved.get_mut(&index) {
+            fields.insert(field_name.to_string());
+        }
+        Ok(())
+    }
+
+    // Finishes a reserved row, failing if a field that is neither nullable
+    // nor has a default was never set. The row stays reserved on failure so
+    // the field can still be set. Rows that weren't reserved, or were
+    // already finalized, have nothing to check.
+    pub fn finalize_row(&mut self, index: usize) -> Result<(), TableError> {
+        if index >= self.row_count() {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        if let Some(fields) = self.reserved.get(&index) {
+            let missing = self.schema.iter().find(|field_spec| {
+                let type_spec = &field_spec.type_spec;
+                !type_spec.is_nullable && type_spec.default.is_none() && !fields.contains(&field_spec.name)
+            });
+            if let Some(field_spec) = missing {
+                return Err(TableError::MissingField(field_spec.name.clone()));
+            }
+        }
+
+        self.reserved.remove(&index);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBInlineString, DBUInt32};
+    use crate::{DbType, FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn people() -> Table {
+        Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(20), false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("height", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("team", TypeSpec::new(DbType::UInt32, false, Some(vec![7, 0, 0, 0]))),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(20), true, None)),
+        ]))
+    }
+
+    #[test]
+    fn finalize_requires_every_required_field() {
+        let mut table = people();
+        let index = table.reserve_row().unwrap();
+        table.set_field(index, "name", DBInlineString("bob".to_string())).unwrap();
+        table.set_field(index, "age", DBUInt32(30)).unwrap();
+
+        assert_eq!(Err(TableError::MissingField("height".to_string())), table.finalize_row(index));
+
+        table.set_field(index, "height", DBUInt32(180)).unwrap();
+        table.finalize_row(index).unwrap();
+        assert_eq!("bob", table.get_field(index, "name").unwrap().to_display_string());
+        assert_eq!("7", table.get_field(index, "team").unwrap().to_display_string());
+    }
+
+    #[test]
+    fn reservations_follow_compaction() {
+        let mut table = people();
+        table.insert(&Tuple::new()
+            .with(DBInlineString("alice".to_string()))
+            .with(DBUInt32(31))
+            .with(DBUInt32(170))
+            .with(DBUInt32(1))
+            .with_null()).unwrap();
+        let index = table.reserve_row().unwrap();
+        table.set_field(index, "name", DBInlineString("bob".to_string())).unwrap();
+        table.delete(0).unwrap();
+        table.compact_tombstones().unwrap();
+
+        assert_eq!(Err(TableError::MissingField("age".to_string())), table.finalize_row(0));
+        assert_eq!(Err(TableError::RowOutOfBounds(1)), table.finalize_row(1));
+    }
+}
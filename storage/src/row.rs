@@ -0,0 +1,174 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::db_value::{DbValue, NullableValue};
+use crate::{Schema, Table, TableError, Tuple};
+
+// A decoded row: one value per field of the schema, in schema order
+pub struct Row {
+    schema: Rc<Schema>,
+    values: Vec<NullableValue>,
+}
+
+impl Row {
+    // The value of the field named `field_name`, or None if there is no such
+    // field or it is NULL
+    pub fn get(&self, field_name: &str) -> Option<&dyn DbValue> {
+        self.schema.iter()
+            .position(|field_spec| field_spec.name == field_name)
+            .and_then(|field_index| self.values[field_index].value())
+    }
+
+    pub fn schema(&self) -> Rc<Schema> {
+        self.schema.clone()
+    }
+
+    pub fn values(&self) -> &[NullableValue] {
+        &self.values
+    }
+
+    fn fields(&self) -> impl Iterator<Item = (&str, &NullableValue)> {
+        self.schema.iter().map(|field_spec| field_spec.name.as_str()).zip(self.values.iter())
+    }
+}
+
+impl fmt::Display for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (name, value)) in self.fields().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", name, value.to_display_string())?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Debug for Row {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.fields()).finish()
+    }
+}
+
+impl Table {
+    // Decodes every field of a row
+    pub fn get_row(&self, index: usize) -> Result<Row, TableError> {
+        let values = self.schema.iter()
+            .map(|field_spec| self.get_field(index, &field_spec.name))
+            .collect::<Result<_, _>>()?;
+        Ok(Row { schema: self.schema.clone(), values })
+    }
+
+    // Decodes a row into the values `insert` and `replace_row` take, heap
+    // data included, so it can be written back or into another table
+    pub fn row_as_tuple(&self, index: usize) -> Result<Tuple, TableError> {
+        let values = self.schema.iter()
+            .map(|field_spec| self.get_field(index, &field_spec.name))
+            .collect::<Result<_, _>>()?;
+        Ok(Tuple { values })
+    }
+
+    // Decodes each live row, in row order
+    pub fn typed_rows<'a>(&'a self) -> impl Iterator<Item = Result<Row, TableError>> + 'a {
+        (0..self.row_count())
+            .filter(move |&index| !self.tombstones[index])
+            .map(move |index| self.get_row(index))
+    }
+
+    // The live row with the lowest index, decoded. None if there are no live
+    // rows, or if that row can't be decoded, which `get_row` explains.
+    pub fn first(&self) -> Option<Row> {
+        let index = (0..self.row_count()).find(|&index| !self.tombstones[index])?;
+        self.get_row(index).ok()
+    }
+
+    // The live row with the highest index, as `first` decodes it
+    pub fn last(&self) -> Option<Row> {
+        let index = (0..self.row_count()).rev().find(|&index| !self.tombstones[index])?;
+        self.get_row(index).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBInlineString, DBUInt32};
+    use crate::{DbType, FieldSpec, TypeSpec};
+
+    #[test]
+    fn fields_are_fetched_by_name() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(10), false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+        ]));
+        table.insert(&Tuple::new().with(DBInlineString("Ann".to_string())).with(DBUInt32(30))).unwrap();
+        table.insert(&Tuple::new().with(DBInlineString("Bob".to_string())).with_null()).unwrap();
+        table.delete(0).unwrap();
+
+        let rows: Vec<Row> = table.typed_rows().collect::<Result<_, _>>().unwrap();
+        assert_eq!(1, rows.len());
+        assert_eq!("Bob", rows[0].get("name").unwrap().to_display_string());
+        assert!(rows[0].get("age").is_none());
+        assert!(rows[0].get("height").is_none());
+        assert_eq!("{name: Bob, age: NULL}", rows[0].to_string());
+        assert_eq!("Ann", table.get_row(0).unwrap().get("name").unwrap().to_display_string());
+        assert!(format!("{:?}", rows[0]).starts_with("{\"name\": "));
+    }
+
+    #[test]
+    fn first_and_last_skip_deleted_rows() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(10), false, None)),
+        ]));
+        assert!(table.first().is_none());
+        for name in &["Ann", "Bob", "Cy"] {
+            table.insert(&Tuple::new().with(DBInlineString(name.to_string()))).unwrap();
+        }
+        assert_eq!("{name: Ann}", table.first().unwrap().to_string());
+        assert_eq!("{name: Cy}", table.last().unwrap().to_string());
+
+        table.delete(0).unwrap();
+        table.delete(2).unwrap();
+        assert_eq!("{name: Bob}", table.first().unwrap().to_string());
+        assert_eq!("{name: Bob}", table.last().unwrap().to_string());
+
+        table.delete(1).unwrap();
+        table.compact_tombstones().unwrap();
+        assert_eq!(0, table.row_count());
+        assert!(table.first().is_none());
+        assert!(table.last().is_none());
+    }
+
+    #[test]
+    fn replacing_a_row_with_its_own_tuple_changes_nothing() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(10), false, None)),
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), true, None)),
+        ]));
+        table.insert(&Tuple::new()
+            .with(DBInlineString("Ann".to_string()))
+            .with(DBUInt32(30))
+            .with(DBExternalString("likes tea".to_string()))).unwrap();
+        table.insert(&Tuple::new().with(DBInlineString("Bob".to_string())).with_null().with_null()).unwrap();
+        let fixed = table.fixed_data.read_at(0, table.fixed_data.len()).to_vec();
+        let heap = table.variable_data.as_slice().to_vec();
+
+        for index in 0..2 {
+            let tuple = table.row_as_tuple(index).unwrap();
+            assert_eq!(3, tuple.len());
+            table.replace_row(index, &tuple).unwrap();
+            assert_eq!(0, table.row_version(index).unwrap());
+        }
+        assert_eq!(fixed, table.fixed_data.read_at(0, table.fixed_data.len()));
+        assert_eq!(heap, table.variable_data.as_slice());
+        assert_eq!(0, table.variable_data.free_bytes());
+
+        // Into another table, the tuple is the same row
+        let mut copy = Table::new("copy", table.schema());
+        copy.insert(&table.row_as_tuple(0).unwrap()).unwrap();
+        assert_eq!("{name: Ann, age: 30, notes: likes tea}", copy.get_row(0).unwrap().to_string());
+        assert_eq!(Err(TableError::RowOutOfBounds(2)), table.row_as_tuple(2).map(|_| ()));
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::RwLock;
+
+use crate::{Table, TableError};
+
+// A `Table` holds its schema in an `Rc`, so it can never be shared between
+// threads itself. `SharedRows` is a thread-safe copy of a table's fixed rows
+// that can be put behind an `Arc` and updated concurrently, then merged back
+// into the table.
+//
+// Rows are striped across a fixed number of `RwLock`s by row index, so a
+// reader or writer only blocks others that touch the same stripe. More
+// stripes mean fewer unrelated rows contending for the same lock, at the
+// cost of one lock per stripe; with fewer stripes than threads, unrelated
+// updates will regularly wait on each other. A stripe count around the
+// number of threads expected to touch the rows is a reasonable default.
+//
+// Only fixed row bytes can be changed this way. Heap-backed values still
+// need `&mut Table`.
+#[derive(Debug)]
+pub struct SharedRows {
+    row_length: usize,
+    row_count: usize,
+    // Row `i` lives in stripe `i % stripes.len()` at position
+    // `i / stripes.len()`
+    stripes: Vec<RwLock<Vec<u8>>>,
+}
+
+impl SharedRows {
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+
+    pub fn stripe_count(&self) -> usize {
+        self.stripes.len()
+    }
+
+    // Copies out a row's fixed bytes, holding only its stripe's read lock
+    pub fn read_row_locked(&self, index: usize) -> Result<Vec<u8>, TableError> {
+        let (stripe, start) = self.locate(index)?;
+        let rows = stripe.read().unwrap();
+        Ok(rows[start..(start+self.row_length)].to_vec())
+    }
+
+    // Overwrites a row's fixed bytes, holding only its stripe's write lock
+    pub fn write_row_locked(&self, index: usize, row: &[u8]) -> Result<(), TableError> {
+        if row.len() != self.row_length {
+            return Err(TableError::InvalidRowLength { expected: self.row_length, actual: row.len() });
+        }
+        let (stripe, start) = self.locate(index)?;
+        let mut rows = stripe.write().unwrap();
+        rows[start..(start+self.row_length)].copy_from_slice(row);
+        Ok(())
+    }
+
+    fn locate(&self, index: usize) -> Result<(&RwLock<Vec<u8>>, usize), TableError> {
+        if index >= self.row_count {
+            return Err(TableError::RowOutOfBounds(index));
+        }
+        let stripe_count = self.stripes.len();
+        Ok((&self.stripes[index % stripe_count], (index / stripe_count) * self.row_length))
+    }
+}
+
+impl Table {
+    // Copies the fixed rows into `stripe_count` independently locked stripes
+    pub fn share_rows(&self, stripe_count: usize) -> SharedRows {
+        let stripe_count = stripe_count.max(1);
+        let mut stripes = vec![vec![]; stripe_count];
+        for index in 0..self.row_count() {
+            stripes[index % stripe_count].extend_from_slice(self.row(index));
+        }
+
+        SharedRows {
+            row_length: self.row_length(),
+            row_count: self.row_count(),
+            stripes: stripes.into_iter().map(RwLock::new).collect(),
+        }
+    }
+
+    // Writes rows taken with `share_rows` back into the table. Rows inserted
+    // since then are left untouched. If the merged rows would give two rows
+    // the same primary key, the table is left as it was.
+    pub fn merge_rows(&mut self, rows: &SharedRows) -> Result<(), TableError> {
+        if rows.row_length != self.row_length() {
+            return Err(TableError::InvalidRowLength { expected: self.row_length(), actual: rows.row_length });
+        }
+        if rows.row_count > self.row_count() {
+            return Err(TableError::RowOutOfBounds(rows.row_count - 1));
+        }
+
+        let row_length = self.row_length();
+        // Rows without fields have nothing to merge
+        if row_length == 0 {
+            return Ok(());
+        }
+        // The rows changed so far, as they were, to put back if the merge fails
+        let mut replaced = vec![];
+        for (stripe_index, stripe) in rows.stripes.iter().enumerate() {
+            let stripe = stripe.read().unwrap();
+            for (position, row) in stripe.chunks(row_length).enumerate() {
+                let index = position * rows.stripes.len() + stripe_index;
+                if self.row(index) != row {
+                    let old_row = self.row(index).to_vec();
+                    if let Err(err) = self.fixed_data.write_at(index * row_length, row) {
+                        self.restore_rows(replaced);
+                        return Err(err.into());
+                    }
+                    replaced.push((index, old_row));
+                    self.row_versions[index] += 1;
+                    self.record_checksum(index);
+                }
+            }
+        }
+        self.stats = None;
+
+        // Shared rows bypass key checks, so a merge can introduce duplicates.
+        // A failed rebuild leaves the old key in place, which matches the
+        // restored rows.
+        if let Err(err) = self.rebuild_primary_key() {
+            self.restore_rows(replaced);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn restore_rows(&mut self, replaced: Vec<(usize, Vec<u8>)>) {
+        let row_length = self.row_length();
+        for (index, old_row) in replaced {
+            // The bytes were just overwritten in place, so they can be again
+            self.fixed_data.write_at(index * row_length, &old_row)
+                .expect("overwriting a row that was just written");
+            self.row_versions[index] -= 1;
+            self.record_checksum(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::DBUInt32;
+    use crate::{DbType, FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn test_table(rows: u32) -> Table {
+        let mut table = Table::new("counters", Rc::new(vec![
+            FieldSpec::new("count", TypeSpec::new(DbType::UInt32, false, None)),
+        ]));
+        for count in 0..rows {
+            table.insert(&Tuple::new().with(DBUInt32(count))).unwrap();
+        }
+        table
+    }
+
+    #[test]
+    fn concurrent_updates_to_different_rows() {
+        let mut table = test_table(4);
+        let rows = Arc::new(table.share_rows(2));
+
+        let handles: Vec<_> = vec![(1, 100u32), (2, 200u32)].into_iter().map(|(index, value)| {
+            let rows = rows.clone();
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    let current = rows.read_row_locked(index).unwrap();
+                    assert_eq!(4, current.len());
+                    rows.write_row_locked(index, &value.to_le_bytes()).unwrap();
+                }
+            })
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        table.merge_rows(&rows).unwrap();
+        assert_eq!(0, table.row_version(0).unwrap());
+        assert_eq!(1, table.row_version(1).unwrap());
+        assert_eq!(&0u32.to_le_bytes(), table.row(0));
+        assert_eq!(&100u32.to_le_bytes(), table.row(1));
+        assert_eq!(&200u32.to_le_bytes(), table.row(2));
+        assert_eq!(&3u32.to_le_bytes(), table.row(3));
+    }
+
+    #[test]
+    fn merge_with_duplicate_keys_leaves_table_unchanged() {
+        let mut table = test_table(3);
+        table.set_primary_key("count").unwrap();
+        let rows = table.share_rows(2);
+        rows.write_row_locked(0, &7u32.to_le_bytes()).unwrap();
+        rows.write_row_locked(2, &1u32.to_le_bytes()).unwrap();
+
+        assert_eq!(Err(TableError::DuplicateKey("count".to_string())), table.merge_rows(&rows));
+        for index in 0..3 {
+            assert_eq!(&(index as u32).to_le_bytes(), table.row(index));
+            assert_eq!(0, table.row_version(index).unwrap());
+        }
+        // The key still knows every row
+        assert_eq!(Err(TableError::DuplicateKey("count".to_string())),
+            table.insert(&Tuple::new().with(DBUInt32(2))));
+        table.insert(&Tuple::new().with(DBUInt32(7))).unwrap();
+    }
+
+    #[test]
+    fn rows_without_fields_merge_as_nothing() {
+        let mut table = Table::new("empty", Rc::new(vec![]));
+        table.insert(&Tuple::new()).unwrap();
+        let rows = table.share_rows(2);
+        table.merge_rows(&rows).unwrap();
+        assert_eq!(0, table.row_version(0).unwrap());
+    }
+
+    #[test]
+    fn write_rejects_bad_rows() {
+        let rows = test_table(2).share_rows(4);
+
+        assert_eq!(Err(TableError::RowOutOfBounds(2)), rows.write_row_locked(2, &[0; 4]));
+        assert_eq!(
+            Err(TableError::InvalidRowLength { expected: 4, actual: 3 }),
+            rows.write_row_locked(0, &[0; 3]));
+    }
+}
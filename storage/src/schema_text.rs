@@ -0,0 +1,249 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Collation, DbType, FieldSpec, Schema, Table, TableError, TypeSpec};
+
+// Schemas can be written as text, one field per line:
+//
+//     id: uint64 not null
+//     name: varchar(30) nullable default 'anon' collate case_insensitive
+//
+// Defaults are quoted bytes, with `\'`, `\\` and `\xNN` escapes for
+// anything that isn't printable ASCII. Field names may not contain `:`.
+
+impl Table {
+    pub fn describe(&self) -> String {
+        describe_schema(&self.schema)
+    }
+}
+
+pub fn describe_schema(schema: &Schema) -> String {
+    let mut out = String::new();
+    for field_spec in schema {
+        let type_spec = &field_spec.type_spec;
+        out.push_str(&format!("{}: {} {}", field_spec.name, type_spec.db_type,
+            if type_spec.is_nullable { "nullable" } else { "not null" }));
+        if let Some(ref default) = type_spec.default {
+            out.push_str(" default '");
+            for &b in default {
+                match b {
+                    b'\'' => out.push_str("\\'"),
+                    b'\\' => out.push_str("\\\\"),
+                    0x20..=0x7e => out.push(b as char),
+                    _ => out.push_str(&format!("\\x{:02x}", b)),
+                }
+            }
+            out.push('\'');
+        }
+        if type_spec.collation == Collation::CaseInsensitive {
+            out.push_str(" collate case_insensitive");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Reads a schema written by `describe_schema`. Blank lines are ignored.
+pub fn parse_schema(text: &str) -> Result<Schema, TableError> {
+    text.lines()
+        .enumerate()
+        .filter(|&(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| parse_field(line)
+            .map_err(|msg| TableError::InvalidSchema(format!("line {}: {}", number + 1, msg))))
+        .collect()
+}
+
+fn parse_field(line: &str) -> Result<FieldSpec, String> {
+    let colon = line.find(':').ok_or("expected `name: type`")?;
+    let name = line[..colon].trim();
+    if name.is_empty() {
+        return Err("missing field name".to_string());
+    }
+
+    let rest = line[colon + 1..].trim_start();
+    let type_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let db_type: DbType = rest[..type_end].parse()?;
+
+    let rest = rest[type_end..].trim_start();
+    let (is_nullable, mut rest) = if let Some(rest) = rest.strip_prefix("not null") {
+        (false, rest)
+    } else if let Some(rest) = rest.strip_prefix("nullable") {
+        (true, rest)
+    } else {
+        return Err("expected `not null` or `nullable`".to_string());
+    };
+
+    let mut default = None;
+    rest = rest.trim_start();
+    if let Some(quoted) = rest.strip_prefix("default") {
+        let (bytes, after) = parse_quoted(quoted.trim_start())?;
+        default = Some(bytes);
+        rest = after.trim_start();
+    }
+
+    let mut type_spec = TypeSpec::new(db_type, is_nullable, default);
+    if let Some(collation) = rest.strip_prefix("collate") {
+        type_spec.collation = match collation.trim_start() {
+            "binary" => Collation::Binary,
+            "case_insensitive" => Collation::CaseInsensitive,
+            other => return Err(format!("unknown collation `{}`", other)),
+        };
+        rest = "";
+    }
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected `{}`", rest.trim()));
+    }
+
+    Ok(FieldSpec::new(name, type_spec))
+}
+
+// Splits a leading quoted default off `text`, returning its bytes and what
+// follows the closing quote
+fn parse_quoted(text: &str) -> Result<(Vec<u8>, &str), String> {
+    let body = text.strip_prefix('\'').ok_or("expected a quoted default")?;
+    let mut bytes = vec![];
+    let mut chars = body.char_indices();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\'' => return Ok((bytes, &body[offset + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '\'')) => bytes.push(b'\''),
+                Some((_, '\\')) => bytes.push(b'\\'),
+                Some((start, 'x')) => {
+                    let hex = body.get(start + 1..start + 3).ok_or("truncated `\\x` escape")?;
+                    bytes.push(u8::from_str_radix(hex, 16).map_err(|_| format!("bad escape `\\x{}`", hex))?);
+                    chars.next();
+                    chars.next();
+                }
+                _ => return Err("bad escape in default".to_string()),
+            },
+            c if c.is_ascii() => bytes.push(c as u8),
+            _ => return Err(format!("`{}` must be escaped", c)),
+        }
+    }
+    Err("unterminated default".to_string())
+}
+
+impl fmt::Display for DbType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DbType::Boolean => write!(f, "boolean"),
+            DbType::Int32 => write!(f, "int32"),
+            DbType::UInt32 => write!(f, "uint32"),
+            DbType::Int64 => write!(f, "int64"),
+            DbType::UInt64 => write!(f, "uint64"),
+            DbType::Float64 => write!(f, "float64"),
+            DbType::Varchar(len) => write!(f, "varchar({})", len),
+            DbType::LongVarchar(len) => write!(f, "long_varchar({})", len),
+            DbType::Blob => write!(f, "blob"),
+            DbType::Bytes(len) => write!(f, "bytes({})", len),
+            DbType::IpAddr => write!(f, "ipaddr"),
+            DbType::Char => write!(f, "char"),
+            DbType::AdaptiveVarchar { max_len, inline_len } =>
+                write!(f, "adaptive_varchar({},{})", max_len, inline_len),
+            DbType::Money { scale } => write!(f, "money({})", scale),
+            DbType::Array(ref element_type, None) => write!(f, "array({})", element_type),
+            DbType::Array(ref element_type, Some(len)) => write!(f, "array({},{})", element_type, len),
+            DbType::Serialized => write!(f, "serialized"),
+        }
+    }
+}
+
+// Parses the names `Display` writes
+impl FromStr for DbType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, args) = match s.find('(') {
+            Some(open) if s.ends_with(')') => (&s[..open], Some(&s[open + 1..s.len() - 1])),
+            Some(_) => return Err(format!("unclosed `(` in type `{}`", s)),
+            None => (s, None),
+        };
+        if let ("array", Some(args)) = (name, args) {
+            // The element type may have its own arguments, so a fixed
+            // length is whatever follows the last comma, if it's a number
+            let (element_type, fixed_len) = match args.rfind(',') {
+                Some(comma) => match args[comma + 1..].trim().parse::<usize>() {
+                    Ok(len) => (&args[..comma], Some(len)),
+                    Err(_) => (args, None),
+                },
+                None => (args, None),
+            };
+            return Ok(DbType::Array(Box::new(element_type.trim().parse()?), fixed_len));
+        }
+        let lengths = match args {
+            Some(args) => args.split(',')
+                .map(|arg| arg.trim().parse::<usize>().map_err(|_| format!("bad length `{}` in type `{}`", arg, s)))
+                .collect::<Result<Vec<usize>, String>>()?,
+            None => vec![],
+        };
+
+        match (name, lengths.as_slice()) {
+            ("boolean", []) => Ok(DbType::Boolean),
+            ("int32", []) => Ok(DbType::Int32),
+            ("uint32", []) => Ok(DbType::UInt32),
+            ("int64", []) => Ok(DbType::Int64),
+            ("uint64", []) => Ok(DbType::UInt64),
+            ("float64", []) => Ok(DbType::Float64),
+            ("varchar", &[len]) => Ok(DbType::Varchar(len)),
+            ("long_varchar", &[len]) if len <= usize::from(u16::MAX) => Ok(DbType::LongVarchar(len)),
+            ("blob", []) => Ok(DbType::Blob),
+            ("bytes", &[len]) => Ok(DbType::Bytes(len)),
+            ("ipaddr", []) => Ok(DbType::IpAddr),
+            ("char", []) => Ok(DbType::Char),
+            ("adaptive_varchar", &[max_len, inline_len]) => Ok(DbType::AdaptiveVarchar { max_len, inline_len }),
+            ("money", &[scale]) if scale <= usize::from(u8::MAX) => Ok(DbType::Money { scale: scale as u8 }),
+            ("serialized", []) => Ok(DbType::Serialized),
+            _ => Err(format!("unknown type `{}`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn test_schema() -> Schema {
+        vec![
+            FieldSpec::new("id", TypeSpec::new(DbType::UInt64, false, None)),
+            FieldSpec::new("name", TypeSpec::new(DbType::Varchar(30), true, Some(b"it's \\ \x01".to_vec()))
+                .with_collation(Collation::CaseInsensitive)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::AdaptiveVarchar { max_len: 4000, inline_len: 16 }, true, None)),
+            FieldSpec::new("bio", TypeSpec::new(DbType::LongVarchar(2000), true, None)),
+            FieldSpec::new("addr", TypeSpec::new(DbType::IpAddr, false, None)),
+            FieldSpec::new("grade", TypeSpec::new(DbType::Char, true, None)),
+            FieldSpec::new("balance", TypeSpec::new(DbType::Money { scale: 2 }, false, None)),
+            FieldSpec::new("tags", TypeSpec::new(DbType::Array(Box::new(DbType::UInt32), None), true, None)),
+            FieldSpec::new("key", TypeSpec::new(DbType::Array(Box::new(DbType::Bytes(4)), Some(2)), false, None)),
+        ]
+    }
+
+    #[test]
+    fn describe_then_parse_roundtrips() {
+        let text = Table::new("people", Rc::new(test_schema())).describe();
+        assert_eq!("\
+id: uint64 not null
+name: varchar(30) nullable default 'it\\'s \\\\ \\x01' collate case_insensitive
+notes: adaptive_varchar(4000,16) nullable
+bio: long_varchar(2000) nullable
+addr: ipaddr not null
+grade: char nullable
+balance: money(2) not null
+tags: array(uint32) nullable
+key: array(bytes(4),2) not null
+", text);
+        assert_eq!(test_schema(), parse_schema(&text).unwrap());
+    }
+
+    #[test]
+    fn parse_reports_bad_lines() {
+        match parse_schema("id: uint64 not null\n\nname: varchar(x) nullable") {
+            Err(TableError::InvalidSchema(msg)) => assert_eq!("line 3: bad length `x` in type `varchar(x)`", msg),
+            other => panic!("Expected a schema error, got {:?}", other),
+        }
+        assert!(parse_schema("id: uint64").is_err());
+        assert!(parse_schema("id: uint64 nullable default 'open").is_err());
+        assert!(parse_schema("id: uint64 nullable extra").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashSet;
+
+use crate::{DbType, Table};
+
+// Distinct values tracked per column before counting stops. Past this the
+// distinct count is only a lower bound.
+const MAX_TRACKED_DISTINCT: usize = 1024;
+
+// A summary of a table's contents, used to make rough cost decisions
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableStats {
+    // Live rows at the time of the analysis
+    pub row_count: usize,
+    pub columns: Vec<ColumnStats>,
+}
+
+// Stats for a single numeric column. Integers of every width are widened to
+// i128 so signed and unsigned columns can share one representation, and
+// booleans count as 0 and 1. NULLs are not included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnStats {
+    pub name: String,
+    // None when every value is NULL
+    pub min: Option<i128>,
+    pub max: Option<i128>,
+    pub distinct: usize,
+    // Whether `distinct` hit the tracking cap and may be an undercount
+    pub distinct_capped: bool,
+    pub null_count: usize,
+}
+
+impl TableStats {
+    pub fn column(&self, name: &str) -> Option<&ColumnStats> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+}
+
+impl Table {
+    // Scans every live row and records stats for each numeric column. The
+    // result is kept on the table until the next change.
+    pub fn analyze(&mut self) -> TableStats {
+        let mut columns = vec![];
+        for (field_index, field_spec) in self.schema.iter().enumerate() {
+            if !is_numeric(&field_spec.type_spec.db_type) {
+                continue;
+            }
+
+            let mut column = ColumnStats {
+                name: field_spec.name.clone(),
+                min: None,
+                max: None,
+                distinct: 0,
+                distinct_capped: false,
+                null_count: 0,
+            };
+            let mut seen = HashSet::new();
+            for index in (0..self.row_count()).filter(|&index| !self.tombstones[index]) {
+                let row = self.row(index);
+                if self.field_is_null(row, field_index) {
+                    column.null_count += 1;
+                    continue;
+                }
+                let value = numeric_value(&field_spec.type_spec.db_type, &self.field_bytes(row, field_index));
+                column.min = Some(column.min.map_or(value, |min| min.min(value)));
+                column.max = Some(column.max.map_or(value, |max| max.max(value)));
+                if seen.len() < MAX_TRACKED_DISTINCT {
+                    seen.insert(value);
+                } else if !seen.contains(&value) {
+                    column.distinct_capped = true;
+                }
+            }
+            column.distinct = seen.len();
+            columns.push(column);
+        }
+
+        let stats = TableStats {
+            row_count: self.live_row_count(),
+            columns,
+        };
+        self.stats = Some(stats.clone());
+        stats
+    }
+
+    // The stats from the last `analyze`, unless the table has changed since
+    pub fn stats(&self) -> Option<&TableStats> {
+        self.stats.as_ref()
+    }
+}
+
+fn is_numeric(db_type: &DbType) -> bool {
+    matches!(*db_type, DbType::Boolean | DbType::Int32 | DbType::UInt32 | DbType::Int64 | DbType::UInt64)
+}
+
+// Decodes a field of one of the `is_numeric` types
+fn numeric_value(db_type: &DbType, buf: &[u8]) -> i128 {
+    match *db_type {
+        DbType::Boolean => (buf[0] != 0) as i128,
+        DbType::Int32 => LittleEndian::read_i32(buf) as i128,
+        DbType::UInt32 => LittleEndian::read_u32(buf) as i128,
+        DbType::Int64 => LittleEndian::read_i64(buf) as i128,
+        DbType::UInt64 => LittleEndian::read_u64(buf) as i128,
+        _ => unreachable!("{:?} is not numeric", db_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DBExternalString, DBUInt32};
+    use crate::{FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    fn test_table() -> Table {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, true, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(1000), false, None)),
+        ]));
+        for age in &[5, 10, 15, 10] {
+            table.insert(&Tuple::new()
+                .with(DBUInt32(*age))
+                .with(DBExternalString("notes".to_string()))).unwrap();
+        }
+        table.insert(&Tuple::new()
+            .with_null()
+            .with(DBExternalString("unknown age".to_string()))).unwrap();
+        table
+    }
+
+    #[test]
+    fn analyze_uint32_column() {
+        let mut table = test_table();
+        let stats = table.analyze();
+
+        assert_eq!(5, stats.row_count);
+        assert_eq!(1, stats.columns.len());
+        let age = stats.column("age").unwrap();
+        assert_eq!(Some(5), age.min);
+        assert_eq!(Some(15), age.max);
+        assert_eq!(3, age.distinct);
+        assert!(!age.distinct_capped);
+        assert_eq!(1, age.null_count);
+    }
+
+    #[test]
+    fn stats_go_stale_on_change() {
+        let mut table = test_table();
+        table.analyze();
+        assert!(table.stats().is_some());
+
+        table.insert(&Tuple::new()
+            .with(DBUInt32(20))
+            .with(DBExternalString("notes".to_string()))).unwrap();
+        assert!(table.stats().is_none());
+
+        assert_eq!(Some(20), table.analyze().column("age").unwrap().max);
+        table.replace_row(0, &Tuple::new()
+            .with(DBUInt32(1))
+            .with(DBExternalString("notes".to_string()))).unwrap();
+        assert!(table.stats().is_none());
+    }
+}
@@ -0,0 +1,189 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+use std::collections::HashMap;
+
+use crate::{Table, TableError, POINTER_SIZE};
+
+impl Table {
+    // Reclaims the heap space freed by updates and compaction, moving the
+    // heap data rows still refer to down over it in order, then releases
+    // the spare capacity of both backends. Deleted rows keep their heap
+    // data until `compact_tombstones` frees it.
+    pub fn shrink_to_fit(&mut self) -> Result<(), TableError> {
+        // (heap offset, row index, where in the row the offset is stored)
+        let mut pointers = vec![];
+        for index in 0..self.row_count() {
+            let row = self.row(index);
+            for field_index in 0..self.schema.len() {
+                if let Some(pointer) = self.heap_pointer(row, field_index) {
+                    let heap_offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+                    pointers.push((heap_offset, index, pointer));
+                }
+            }
+        }
+        pointers.sort();
+
+        // Entries are moved lowest offset first, so an entry is only ever
+        // copied over freed space or itself
+        let row_length = self.row_length();
+        let mut heap_len = 0;
+        let mut last_moved: Option<(usize, usize)> = None;
+        for (heap_offset, index, pointer) in pointers {
+            let new_offset = match last_moved {
+                Some((old_offset, new_offset)) if old_offset == heap_offset => new_offset,
+                _ => {
+                    let entry = self.variable_data.get_prefixed_slice(heap_offset).to_vec();
+                    // Aligned if the heap is, unless that would move the entry
+                    // up over data not moved yet
+                    let new_offset = self.variable_data.aligned(heap_len).min(heap_offset);
+                    if heap_offset != new_offset {
+                        self.variable_data.write_at(new_offset, &entry)?;
+                    }
+                    heap_len = new_offset + entry.len();
+                    last_moved = Some((heap_offset, new_offset));
+                    new_offset
+                }
+            };
+            if new_offset != heap_offset {
+                let mut field = [0u8; POINTER_SIZE];
+                LittleEndian::write_uint(&mut field, new_offset as u64, POINTER_SIZE);
+                self.fixed_data.write_at(index * row_length + pointer, &field)?;
+            }
+        }
+
+        self.variable_data.truncate(heap_len);
+        self.variable_data.shrink_to_fit();
+        self.fixed_data.shrink_to_fit();
+        Ok(())
+    }
+
+    // Reclaims a little of the freed heap space, moving about `max_bytes`
+    // of heap data as `DbHeap::defragment_step` does and pointing the rows
+    // at its new place, and returns whether there is more to reclaim.
+    // Unlike `shrink_to_fit`, the work can be spread over many calls, and
+    // the table reads correctly between them. Deleted rows that haven't been
+    // compacted away keep their heap data, moved like any other.
+    pub fn defragment_step(&mut self, max_bytes: usize) -> Result<bool, TableError> {
+        let mut relocated = HashMap::new();
+        let more = self.variable_data.defragment_step(max_bytes, |old_offset, new_offset| {
+            relocated.insert(old_offset, new_offset);
+        })?;
+        if relocated.is_empty() {
+            return Ok(more);
+        }
+
+        let row_length = self.row_length();
+        for index in 0..self.row_count() {
+            for field_index in 0..self.schema.len() {
+                let row = self.row(index);
+                let pointer = match self.heap_pointer(row, field_index) {
+                    Some(pointer) => pointer,
+                    None => continue,
+                };
+                let heap_offset = LittleEndian::read_uint(&row[pointer..], POINTER_SIZE) as usize;
+                if let Some(&new_offset) = relocated.get(&heap_offset) {
+                    let mut field = [0u8; POINTER_SIZE];
+                    LittleEndian::write_uint(&mut field, new_offset as u64, POINTER_SIZE);
+                    self.fixed_data.write_at(index * row_length + pointer, &field)?;
+                }
+            }
+        }
+        Ok(more)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_value::{DbHeap, DBExternalString, DBUInt32};
+    use crate::{DbType, FieldSpec, Tuple, TypeSpec};
+    use std::rc::Rc;
+
+    #[test]
+    fn shrinking_reclaims_freed_heap_space() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(5000), false, None)),
+        ]));
+        for age in 0..4 {
+            table.insert(&Tuple::new().with(DBUInt32(age)).with(DBExternalString("x".repeat(4000)))).unwrap();
+        }
+        table.update_field(0, "notes", DBExternalString("short".to_string())).unwrap();
+        table.delete(1).unwrap();
+        table.compact_tombstones().unwrap();
+        let capacity = table.variable_data.capacity();
+
+        table.shrink_to_fit().unwrap();
+
+        let live_len = 2 * (POINTER_SIZE + 4000) + POINTER_SIZE + 5;
+        assert_eq!(live_len, table.variable_data.len());
+        assert!(table.variable_data.capacity() < capacity);
+        assert!(table.variable_data.capacity() - live_len < 64);
+        assert_eq!(0, table.variable_data.free_bytes());
+
+        assert_eq!("short", table.get_field(0, "notes").unwrap().to_display_string());
+        for index in 1..3 {
+            assert_eq!((index + 1).to_string(), table.get_field(index, "age").unwrap().to_display_string());
+            assert_eq!("x".repeat(4000), table.get_field(index, "notes").unwrap().to_display_string());
+        }
+    }
+
+    #[test]
+    fn shrinking_keeps_entries_aligned() {
+        let mut table = Table::with_backends("people", Rc::new(vec![
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(5000), false, None)),
+        ]), Box::new(Vec::new()), DbHeap::new().with_alignment(8));
+        for notes in &["one", "three", "fives"] {
+            table.insert(&Tuple::new().with(DBExternalString(notes.to_string()))).unwrap();
+        }
+        table.delete(0).unwrap();
+        table.compact_tombstones().unwrap();
+        table.shrink_to_fit().unwrap();
+
+        for (index, notes) in ["three", "fives"].iter().enumerate() {
+            assert_eq!(Some(index * 16), table.heap_offset_of(index, "notes"));
+            assert_eq!(*notes, table.get_field(index, "notes").unwrap().to_display_string());
+        }
+    }
+
+    #[test]
+    fn defragmenting_step_by_step_keeps_reads_correct() {
+        let mut table = Table::new("people", Rc::new(vec![
+            FieldSpec::new("age", TypeSpec::new(DbType::UInt32, false, None)),
+            FieldSpec::new("notes", TypeSpec::new(DbType::Varchar(5000), false, None)),
+        ]));
+        let mut expected = vec![];
+        for age in 0..10 {
+            let notes = age.to_string().repeat(300 + age as usize * 40);
+            table.insert(&Tuple::new().with(DBUInt32(age)).with(DBExternalString(notes.clone()))).unwrap();
+            expected.push(notes);
+        }
+        for index in (0..10).step_by(3) {
+            expected[index] = format!("short {}", index);
+            table.update_field(index, "notes", DBExternalString(expected[index].clone())).unwrap();
+        }
+        table.delete(4).unwrap();
+        table.compact_tombstones().unwrap();
+        expected.remove(4);
+        assert!(table.variable_data.free_bytes() > 0);
+
+        let mut steps = 0;
+        loop {
+            let more = table.defragment_step(500).unwrap();
+            steps += 1;
+            for (index, notes) in expected.iter().enumerate() {
+                assert_eq!(*notes, table.get_field(index, "notes").unwrap().to_display_string());
+            }
+            if !more {
+                break;
+            }
+            assert!(steps < 100, "defragmenting never finished");
+        }
+        assert!(steps > 1);
+
+        let live_len: usize = expected.iter().map(|notes| POINTER_SIZE + notes.len()).sum();
+        assert_eq!(live_len, table.variable_data.len());
+        assert_eq!(0, table.variable_data.free_bytes());
+        assert!(!table.defragment_step(500).unwrap());
+    }
+}
@@ -0,0 +1,180 @@
+use bytes::Bytes;
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use clock::VectorClock;
+use {Cluster, Envelope, Message, SendError};
+
+// How many of the most recent updates a node keeps to answer sync requests
+pub const DEFAULT_SYNC_LOG_SIZE: usize = 1000;
+
+// Asks a peer for every update it has that a node with clock `since` hasn't
+// seen. Messages can be dropped on the way to a peer whose channel is full;
+// asking for them again repairs the gap.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SyncRequest {
+    pub since: VectorClock,
+}
+
+// The updates a `SyncRequest` asked for, in the order the answering node
+// delivered them
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SyncResponse {
+    pub updates: Vec<Envelope>,
+}
+
+// The updates this node originated or delivered most recently, oldest
+// first. Only the last `capacity` are kept, so a node that has fallen
+// further behind is sent updates whose dependencies are gone, and holds
+// them back.
+#[derive(Debug)]
+pub(crate) struct SyncLog {
+    updates: VecDeque<Envelope>,
+    capacity: usize,
+}
+
+impl SyncLog {
+    pub(crate) fn new() -> Self {
+        SyncLog {
+            updates: VecDeque::new(),
+            capacity: DEFAULT_SYNC_LOG_SIZE,
+        }
+    }
+
+    pub(crate) fn record(&mut self, envelope: &Envelope) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.updates.len() == self.capacity {
+            self.updates.pop_front();
+        }
+        self.updates.push_back(envelope.clone());
+    }
+}
+
+impl Cluster {
+    // Keeps the last `size` updates to answer sync requests with, so 0
+    // turns answering them off
+    pub fn with_sync_log_size(mut self, size: usize) -> Self {
+        self.sync_log.capacity = size;
+        while self.sync_log.updates.len() > size {
+            self.sync_log.updates.pop_front();
+        }
+        self
+    }
+
+    // The kept updates a node with clock `since` hasn't seen, oldest first
+    pub fn updates_since(&self, since: &VectorClock) -> Vec<Envelope> {
+        self.sync_log.updates.iter()
+            .filter(|update| !since.has_seen(&update.sender, &update.clock))
+            .cloned()
+            .collect()
+    }
+
+    // Asks the peer at `addr` for the updates this node is missing. Like a
+    // handshake, the request isn't part of the causal history and doesn't
+    // advance this node's clock.
+    pub fn send_sync_request(&mut self, addr: SocketAddr) -> Result<(), SendError> {
+        let request = SyncRequest { since: self.clock.clone() };
+        let envelope = Envelope::new(self.node_id.clone(), self.clock.clone(), request);
+        self.send_frame(addr, Bytes::from(envelope.encode()))
+    }
+
+    // Answers a sync request that arrived from the peer at `origin`,
+    // returning whether the envelope was one. The response goes straight
+    // back to `origin`, stamped with this node's clock without advancing it,
+    // and `receive` delivers the updates in it.
+    pub fn answer_sync(&mut self, origin: SocketAddr, envelope: &Envelope) -> bool {
+        let since = match envelope.message {
+            Message::SyncRequestMsg(ref request) => &request.since,
+            _ => return false,
+        };
+        let response = SyncResponse { updates: self.updates_since(since) };
+        let envelope = Envelope::new(self.node_id.clone(), self.clock.clone(), response);
+        if let Err(err) = self.send_frame(origin, Bytes::from(envelope.encode())) {
+            self.events.on_error(&format!("Could not answer sync request: {}", err));
+        }
+        true
+    }
+
+    // Receives each update in a sync response, returning every message that
+    // was delivered as a result. Updates already seen are discarded as
+    // duplicates.
+    pub(crate) fn receive_sync(&mut self, response: SyncResponse) -> Vec<Envelope> {
+        let mut delivered = vec![];
+        for update in response.updates {
+            delivered.extend(self.receive(update));
+        }
+        delivered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+    use compression::Handshake;
+    use {peer_channel, LeaveCluster, Rx};
+
+    fn leave(port: u32) -> LeaveCluster {
+        LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port,
+        }
+    }
+
+    fn next_envelope(rx: Rx) -> Envelope {
+        let frames = rx.take(1).collect().wait().unwrap();
+        Envelope::decode(&frames[0]).unwrap()
+    }
+
+    #[test]
+    fn sync_request_is_answered_with_the_missing_updates() {
+        let a_addr: SocketAddr = "127.0.0.1:3400".parse().unwrap();
+        let b_addr: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let mut a = Cluster::new("A");
+        let mut b = Cluster::new("B");
+        let (to_b, b_rx) = peer_channel();
+        let (to_a, a_rx) = peer_channel();
+        a.add_peer(b_addr, to_b);
+        b.add_peer(a_addr, to_a);
+
+        let first = b.originate(leave(1));
+        let second = b.originate(leave(2));
+        let third = b.originate(leave(3));
+        assert_eq!(1, a.receive(first).len());
+
+        b.accept_handshake(a_addr, &Envelope::new("A", VectorClock::new(), Handshake {
+            compression: false,
+            cluster_id: String::new(),
+        }));
+        a.send_sync_request(b_addr).unwrap();
+        let request = next_envelope(b_rx);
+        assert!(b.receive_from(a_addr, request).is_empty());
+        assert_eq!(3, b.clock().get("B"));
+
+        let response = next_envelope(a_rx);
+        match response.message {
+            Message::SyncResponseMsg(ref response) => assert_eq!(vec![second.clone(), third.clone()], response.updates),
+            ref other => panic!("Expected a sync response, got {:?}", other),
+        }
+        assert_eq!(vec![second, third], a.receive(response));
+        assert_eq!(b.clock(), a.clock());
+    }
+
+    #[test]
+    fn only_the_latest_updates_are_kept() {
+        let mut cluster = Cluster::new("B").with_sync_log_size(2);
+        let updates: Vec<Envelope> = (0..3).map(|port| cluster.originate(leave(port))).collect();
+
+        assert_eq!(updates[1..].to_vec(), cluster.updates_since(&VectorClock::new()));
+        let clock = cluster.clock().clone();
+        assert!(cluster.updates_since(&clock).is_empty());
+
+        // A request that reaches `receive` directly isn't delivered
+        let request = Envelope::new("A", VectorClock::new(), SyncRequest { since: VectorClock::new() });
+        assert!(cluster.receive(request).is_empty());
+        assert_eq!(&clock, cluster.clock());
+    }
+}
@@ -0,0 +1,39 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+// Names the address to listen on when it isn't given on the command line
+pub const BIND_ADDR_VAR: &str = "CLUSTER_BIND_ADDR";
+
+// Nodes only listen on loopback unless told otherwise, so a cluster is never
+// exposed on every interface by accident
+pub const DEFAULT_BIND_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+// The address to listen on: `ip` if given, else the address in
+// `BIND_ADDR_VAR` if it is set, else loopback
+pub fn bind_addr(ip: Option<&str>, port: u16) -> Result<SocketAddr, String> {
+    let from_env = env::var(BIND_ADDR_VAR).ok();
+    let ip = match ip.or(from_env.as_deref()) {
+        Some(ip) => ip.parse::<IpAddr>().map_err(|_| format!("could not parse bind address {:?}", ip))?,
+        None => DEFAULT_BIND_IP,
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn given_address_is_used() {
+        let addr = bind_addr(Some("127.0.0.1"), 3400).unwrap();
+        assert_eq!("127.0.0.1:3400".parse::<SocketAddr>().unwrap(), addr);
+        assert!(addr.ip().is_loopback());
+        assert_eq!("[::1]:3400".parse::<SocketAddr>().unwrap(), bind_addr(Some("::1"), 3400).unwrap());
+    }
+
+    #[test]
+    fn unparseable_address_is_an_error() {
+        let err = bind_addr(Some("localhost:3400"), 3400).unwrap_err();
+        assert_eq!("could not parse bind address \"localhost:3400\"", err);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+use Cluster;
+
+// Names the addresses to refuse when none are given on the command line
+pub const BLACKLIST_VAR: &str = "CLUSTER_BLACKLIST";
+
+// Addresses this node refuses to talk to: whole hosts, or single ports on
+// a host. Connections from them are closed as soon as they are accepted and
+// joins advertising them are never dialed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Blacklist {
+    ips: HashSet<IpAddr>,
+    addrs: HashSet<SocketAddr>,
+}
+
+impl Blacklist {
+    pub fn new() -> Self {
+        Blacklist::default()
+    }
+
+    // Refuses every port on `ip`
+    pub fn with_ip(mut self, ip: IpAddr) -> Self {
+        self.ips.insert(ip);
+        self
+    }
+
+    pub fn with_addr(mut self, addr: SocketAddr) -> Self {
+        self.addrs.insert(addr);
+        self
+    }
+
+    // Reads a comma-separated list of hosts and host:port addresses, such as
+    // "10.0.0.5, 10.0.0.6:3400"
+    pub fn parse(list: &str) -> Result<Blacklist, String> {
+        list.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .try_fold(Blacklist::new(), |blacklist, entry| {
+                if let Ok(ip) = entry.parse::<IpAddr>() {
+                    return Ok(blacklist.with_ip(ip));
+                }
+                entry.parse::<SocketAddr>()
+                    .map(|addr| blacklist.with_addr(addr))
+                    .map_err(|_| format!("could not parse blacklisted address {:?}", entry))
+            })
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.ips.contains(&addr.ip()) || self.addrs.contains(addr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.addrs.is_empty()
+    }
+}
+
+impl Cluster {
+    pub fn with_blacklist(mut self, blacklist: Blacklist) -> Self {
+        self.blacklist = blacklist;
+        self
+    }
+
+    pub fn blacklist(&self) -> &Blacklist {
+        &self.blacklist
+    }
+
+    // Whether a connection just accepted from `addr` may be kept. The accept
+    // loop should drop a refused one, closing it, before reading from it or
+    // registering it as a peer.
+    pub fn admit(&self, addr: &SocketAddr) -> bool {
+        if self.blacklist.contains(addr) {
+            self.events.on_error(&format!("refusing connection from blacklisted address {}", addr));
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use JoinCluster;
+
+    #[test]
+    fn blacklisted_connection_is_closed_unread() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(&[4, 0, 0, 0, 1, 2, 3, 4]).unwrap();
+        let (socket, peer_addr) = listener.accept().unwrap();
+
+        let cluster = Cluster::new("local").with_blacklist(Blacklist::new().with_ip(peer_addr.ip()));
+        assert!(!cluster.admit(&peer_addr));
+        drop(socket);
+
+        // Either the end of the stream or a reset, since the frame was unread
+        let mut buf = [0u8; 1];
+        assert!(client.read(&mut buf).map(|read| read == 0).unwrap_or(true));
+        assert_eq!(0, cluster.metrics().snapshot().received);
+        assert!(cluster.peers_tx.is_empty());
+    }
+
+    #[test]
+    fn blacklisted_joins_are_ignored() {
+        let blacklist = Blacklist::parse("10.0.0.5, 10.0.0.6:3400,").unwrap();
+        let cluster = Cluster::new("local").with_blacklist(blacklist);
+        let join = |ip: &str, port| JoinCluster {
+            ip: ip.to_string(),
+            port,
+            handle: String::from("peer"),
+            cluster_id: String::new(),
+        };
+
+        assert_eq!(None, cluster.join_target(&join("10.0.0.5", 3401)));
+        assert_eq!(None, cluster.join_target(&join("10.0.0.6", 3400)));
+        assert_eq!(Some("10.0.0.6:3401".parse().unwrap()), cluster.join_target(&join("10.0.0.6", 3401)));
+        assert!(cluster.admit(&"10.0.0.7:3400".parse().unwrap()));
+
+        assert!(Blacklist::parse("").unwrap().is_empty());
+        assert_eq!(Err("could not parse blacklisted address \"host\"".to_string()), Blacklist::parse("host"));
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::iter::FromIterator;
+
+// Nodes are identified by the handle they advertise when joining
+pub type NodeId = String;
+
+// A vector clock maps each node to the number of its messages that
+// have been observed. Nodes that are absent have an implicit count of 0.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct VectorClock(BTreeMap<NodeId, u64>);
+
+impl VectorClock {
+    pub fn new() -> Self {
+        VectorClock(BTreeMap::new())
+    }
+
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).cloned().unwrap_or(0)
+    }
+
+    // Every node with a count, in node order
+    pub fn entries<'a>(&'a self) -> impl Iterator<Item = (&'a str, u64)> + 'a {
+        self.0.iter().map(|(node, &count)| (node.as_str(), count))
+    }
+
+    // Records a new local event for `node` and returns its new count
+    pub fn increment(&mut self, node: &str) -> u64 {
+        let count = self.0.entry(node.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    // Takes the pointwise maximum of both clocks
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node, &count) in &other.0 {
+            let local = self.0.entry(node.clone()).or_insert(0);
+            if count > *local {
+                *local = count;
+            }
+        }
+    }
+
+    // A message stamped with `msg_clock` by `sender` may be delivered when it
+    // is the very next message from the sender and we have already seen
+    // everything the sender had seen from every other node.
+    pub fn can_deliver(&self, sender: &str, msg_clock: &VectorClock) -> bool {
+        if msg_clock.get(sender) != self.get(sender) + 1 {
+            return false;
+        }
+
+        msg_clock.0.iter()
+            .filter(|&(node, _)| node != sender)
+            .all(|(node, &count)| count <= self.get(node))
+    }
+
+    // True when the message from `sender` has already been delivered
+    pub fn has_seen(&self, sender: &str, msg_clock: &VectorClock) -> bool {
+        msg_clock.get(sender) <= self.get(sender)
+    }
+}
+
+impl FromIterator<(NodeId, u64)> for VectorClock {
+    fn from_iter<I: IntoIterator<Item = (NodeId, u64)>>(entries: I) -> Self {
+        VectorClock(entries.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(entries: &[(&str, u64)]) -> VectorClock {
+        let mut clock = VectorClock::new();
+        for &(node, count) in entries {
+            for _ in 0..count {
+                clock.increment(node);
+            }
+        }
+        clock
+    }
+
+    #[test]
+    fn merge_takes_pointwise_max() {
+        let mut a = clock(&[("A", 3), ("B", 1)]);
+        let b = clock(&[("B", 4), ("C", 2)]);
+        a.merge(&b);
+
+        assert_eq!(clock(&[("A", 3), ("B", 4), ("C", 2)]), a);
+    }
+
+    #[test]
+    fn deliverable_only_when_next_from_sender() {
+        let local = clock(&[("A", 1)]);
+
+        assert!(local.can_deliver("A", &clock(&[("A", 2)])));
+        assert!(!local.can_deliver("A", &clock(&[("A", 3)])));
+        assert!(!local.can_deliver("A", &clock(&[("A", 1)])));
+    }
+
+    #[test]
+    fn deliverable_only_when_dependencies_seen() {
+        let local = clock(&[("B", 1)]);
+
+        assert!(local.can_deliver("A", &clock(&[("A", 1), ("B", 1)])));
+        assert!(!local.can_deliver("A", &clock(&[("A", 1), ("B", 2)])));
+    }
+}
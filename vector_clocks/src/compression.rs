@@ -0,0 +1,327 @@
+use bytes::Bytes;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+use encoding::DecodeError;
+use {Cluster, Envelope, Message, SendError};
+
+// Encoded envelopes at least this long are compressed for peers that
+// support it
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+// The most a compressed message may inflate to: the longest frame a peer
+// could have sent it in uncompressed, `LengthDelimitedCodec`'s default
+pub const MAX_DECOMPRESSED_SIZE: usize = 8 * 1024 * 1024;
+
+// Sent to a peer as soon as it connects, to say what this node understands
+// and which cluster it belongs to. Compressed messages are only sent to peers
+// whose handshake asked for them, so a peer that never sends one never
+// receives any.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Handshake {
+    pub compression: bool,
+    pub cluster_id: String,
+}
+
+// Stands in for another message: `payload` is the DEFLATE-compressed
+// encoding of it. The envelope around it keeps its sender and
+// clock, and `receive` swaps the original message back in.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Compressed {
+    pub payload: Vec<u8>,
+}
+
+impl Envelope {
+    // The same envelope with its message compressed
+    pub fn compress(&self) -> Envelope {
+        let encoded = self.message.encode();
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&encoded).expect("writing to a Vec never fails");
+        let payload = encoder.finish().expect("writing to a Vec never fails");
+        Envelope::new(self.sender.clone(), self.clock.clone(), Compressed { payload })
+    }
+
+    // The envelope with its original message, if the message is compressed.
+    // A payload inflating past `MAX_DECOMPRESSED_SIZE` is an error, and is
+    // given up on before any more of it is inflated.
+    pub fn decompress(self) -> Result<Envelope, DecodeError> {
+        let payload = match self.message {
+            Message::CompressedMsg(ref compressed) => &compressed.payload,
+            _ => return Ok(self),
+        };
+        let mut encoded = vec![];
+        DeflateDecoder::new(&payload[..]).take(MAX_DECOMPRESSED_SIZE as u64 + 1).read_to_end(&mut encoded)
+            .map_err(|err| DecodeError::Decompression(err.to_string()))?;
+        if encoded.len() > MAX_DECOMPRESSED_SIZE {
+            return Err(DecodeError::Decompression(format!("inflates past {} bytes", MAX_DECOMPRESSED_SIZE)));
+        }
+        let message = Message::decode(&encoded)?;
+        Ok(Envelope { message, ..self })
+    }
+}
+
+impl Cluster {
+    // Tells a newly connected peer what this node supports. The handshake
+    // isn't part of the causal history: it goes to one peer and doesn't
+    // advance this node's clock.
+    pub fn send_handshake(&mut self, addr: SocketAddr) -> Result<(), SendError> {
+        let handshake = Handshake { compression: true, cluster_id: self.cluster_id.clone() };
+        let envelope = Envelope::new(self.node_id.clone(), self.clock.clone(), handshake);
+        self.send_frame(addr, Bytes::from(envelope.encode()))
+    }
+
+    // Records what the peer at `origin` supports if `envelope` is its
+    // handshake, returning whether it was one. A peer in another cluster is
+    // dropped, which closes its channel; whoever reads its connection should
+    // stop once the peer is no longer in `peers_tx`.
+    pub fn accept_handshake(&mut self, origin: SocketAddr, envelope: &Envelope) -> bool {
+        match envelope.message {
+            Message::HandshakeMsg(ref handshake) => {
+                if handshake.cluster_id != self.cluster_id {
+                    self.events.on_error(&format!("dropping {}, which is in cluster {:?} rather than {:?}",
+                        origin, handshake.cluster_id, self.cluster_id));
+                    self.remove_peer(&origin);
+                    return true;
+                }
+                if self.peers_tx.contains_key(&origin) {
+                    self.handshaken.insert(origin);
+                }
+                if handshake.compression && self.peers_tx.contains_key(&origin) {
+                    self.compression.insert(origin);
+                } else {
+                    self.compression.remove(&origin);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Drops the peer at `origin` if `envelope` is something other than a
+    // handshake and the peer hasn't sent one yet, returning whether it did.
+    // A peer has to say which cluster it is in before anything it sends is
+    // acted on.
+    pub fn refuse_before_handshake(&mut self, origin: SocketAddr, envelope: &Envelope) -> bool {
+        if self.handshaken.contains(&origin) || matches!(envelope.message, Message::HandshakeMsg(_)) {
+            return false;
+        }
+        self.events.on_error(&format!("dropping {}, which sent {:?} before its handshake", origin, envelope.message));
+        self.remove_peer(&origin);
+        true
+    }
+
+    pub fn compresses_for(&self, addr: &SocketAddr) -> bool {
+        self.compression.contains(addr)
+    }
+}
+
+// One message on its way to several peers, in the forms they need: large
+// messages compressed for peers that support it, and never compressed for
+// those that don't. Each form is encoded at most once.
+pub(crate) struct Outgoing {
+    frame: Bytes,
+    // The decoded frame; None if it couldn't be decoded, in which case it is
+    // sent to every peer as it is
+    envelope: Option<Envelope>,
+    plain: Option<Bytes>,
+    compressed: Option<Bytes>,
+}
+
+impl Outgoing {
+    pub(crate) fn new(frame: Bytes, envelope: Option<Envelope>) -> Self {
+        Outgoing { frame, envelope, plain: None, compressed: None }
+    }
+
+    pub(crate) fn into_envelope(self) -> Option<Envelope> {
+        self.envelope
+    }
+
+    // The frame to send a peer, or None if it can't be sent in a form the
+    // peer understands
+    pub(crate) fn frame_for(&mut self, compression: bool) -> Option<Bytes> {
+        let Outgoing { ref frame, ref envelope, ref mut plain, ref mut compressed } = *self;
+        let envelope = match *envelope {
+            Some(ref envelope) => envelope,
+            None => return Some(frame.clone()),
+        };
+        let is_compressed = matches!(envelope.message, Message::CompressedMsg(_));
+
+        if compression {
+            if is_compressed || frame.len() < COMPRESSION_THRESHOLD {
+                return Some(frame.clone());
+            }
+            // Data that doesn't compress is sent as it is
+            return Some(compressed.get_or_insert_with(|| {
+                let smaller = encode(&envelope.compress());
+                if smaller.len() < frame.len() { smaller } else { frame.clone() }
+            }).clone());
+        }
+
+        if !is_compressed {
+            return Some(frame.clone());
+        }
+        if plain.is_none() {
+            *plain = Some(encode(&envelope.clone().decompress().ok()?));
+        }
+        plain.clone()
+    }
+}
+
+fn encode(envelope: &Envelope) -> Bytes {
+    Bytes::from(envelope.encode())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::VectorClock;
+    use futures::{Future, Stream};
+    use {peer_channel, ApplyInsert, LeaveCluster, Rx};
+
+    fn insert(len: usize) -> ApplyInsert {
+        ApplyInsert {
+            table_name: String::from("notes"),
+            row_bytes: vec![7; len],
+            heap_bytes: vec![],
+            clock: VectorClock::new(),
+        }
+    }
+
+    fn frames(rx: Rx) -> Vec<Envelope> {
+        rx.collect().wait().unwrap().iter().map(|frame| Envelope::decode(frame).unwrap()).collect()
+    }
+
+    #[test]
+    fn large_messages_are_compressed_for_peers_that_ask() {
+        let mut cluster = Cluster::new("local");
+        let compressing: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let plain: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (compressing_tx, compressing_rx) = peer_channel();
+        let (plain_tx, plain_rx) = peer_channel();
+        cluster.add_peer(compressing, compressing_tx);
+        cluster.add_peer(plain, plain_tx);
+        let handshake = Envelope::new("peer", VectorClock::new(), Handshake { compression: true, cluster_id: String::new() });
+        assert!(cluster.accept_handshake(compressing, &handshake));
+        assert!(cluster.compresses_for(&compressing));
+
+        let large = cluster.publish(insert(10_000));
+        let small = cluster.publish(LeaveCluster { ip: String::from("127.0.0.1"), port: 3400 });
+        drop(cluster);
+
+        let received = frames(compressing_rx);
+        match received[0].message {
+            Message::CompressedMsg(ref compressed) => assert!(compressed.payload.len() < 1000),
+            ref other => panic!("Expected a compressed message, got {:?}", other),
+        }
+        assert_eq!(large.clock, received[0].clock);
+        assert_eq!(small, received[1]);
+        let decompressed: Vec<Envelope> = received.into_iter().map(|env| env.decompress().unwrap()).collect();
+        assert_eq!(vec![large.clone(), small.clone()], decompressed);
+        assert_eq!(vec![large, small], frames(plain_rx));
+    }
+
+    #[test]
+    fn compressed_messages_are_delivered_decompressed() {
+        let mut cluster = Cluster::new("local");
+        let plain: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (plain_tx, plain_rx) = peer_channel();
+        cluster.add_peer(plain, plain_tx);
+
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+        let original = Envelope::new("A", clock, insert(5000));
+        let compressed = original.compress();
+        let delivered = cluster.receive(compressed.clone());
+        assert_eq!(vec![original.clone()], delivered);
+
+        // Relayed to a peer that never asked for compression, it is sent
+        // decompressed
+        cluster.try_broadcast(&"127.0.0.1:3401".parse().unwrap(), encode(&compressed));
+        drop(cluster);
+        assert_eq!(vec![original], frames(plain_rx));
+    }
+
+    #[test]
+    fn oversized_payloads_are_decode_failures() {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&vec![0; MAX_DECOMPRESSED_SIZE + 1]).unwrap();
+        let payload = encoder.finish().unwrap();
+        assert!(payload.len() < 100_000);
+        let envelope = Envelope::new("A", VectorClock::new(), Compressed { payload });
+        assert!(envelope.clone().decompress().is_err());
+
+        let mut cluster = Cluster::new("local");
+        assert!(cluster.receive(envelope).is_empty());
+        assert_eq!(1, cluster.metrics().snapshot().decode_failures);
+    }
+
+    #[test]
+    fn handshake_is_sent_without_advancing_the_clock() {
+        let mut cluster = Cluster::new("local");
+        let peer: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let (peer_tx, peer_rx) = peer_channel();
+        cluster.add_peer(peer, peer_tx);
+        cluster.send_handshake(peer).unwrap();
+        assert_eq!(0, cluster.clock().get("local"));
+        drop(cluster);
+
+        // A handshake reaching `receive` is not a message to deliver
+        let handshake = frames(peer_rx).remove(0);
+        assert_eq!(Message::from(Handshake { compression: true, cluster_id: String::new() }), handshake.message);
+        let mut other = Cluster::new("other");
+        assert!(other.receive(handshake).is_empty());
+    }
+
+    #[test]
+    fn handshake_from_another_cluster_is_rejected() {
+        let mut cluster = Cluster::new("local").with_cluster_id("blue");
+        let stranger: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let member: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (stranger_tx, stranger_rx) = peer_channel();
+        let (member_tx, _member_rx) = peer_channel();
+        cluster.add_peer(stranger, stranger_tx);
+        cluster.add_peer(member, member_tx);
+
+        let handshake = |cluster_id: &str| Envelope::new("peer", VectorClock::new(), Handshake {
+            compression: true,
+            cluster_id: String::from(cluster_id),
+        });
+        assert!(cluster.accept_handshake(stranger, &handshake("red")));
+        assert!(cluster.accept_handshake(member, &handshake("blue")));
+
+        assert!(!cluster.peers_tx.contains_key(&stranger));
+        assert!(!cluster.compresses_for(&stranger));
+        assert!(cluster.peers_tx.contains_key(&member));
+        assert!(cluster.compresses_for(&member));
+        // The dropped peer's channel is closed, ending its writer
+        assert!(frames(stranger_rx).is_empty());
+    }
+
+    #[test]
+    fn messages_before_the_handshake_are_refused() {
+        let mut cluster = Cluster::new("local");
+        let silent: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let member: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (silent_tx, silent_rx) = peer_channel();
+        let (member_tx, _member_rx) = peer_channel();
+        cluster.add_peer(silent, silent_tx);
+        cluster.add_peer(member, member_tx);
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+        let leave = |clock: &VectorClock| Envelope::new("A", clock.clone(), LeaveCluster { ip: String::from("127.0.0.1"), port: 3400 });
+
+        assert!(cluster.receive_from(silent, leave(&clock)).is_empty());
+        assert!(!cluster.peers_tx.contains_key(&silent));
+        assert_eq!(0, cluster.clock().get("A"));
+        assert!(frames(silent_rx).is_empty());
+
+        let handshake = Envelope::new("B", VectorClock::new(), Handshake { compression: false, cluster_id: String::new() });
+        assert!(cluster.receive_from(member, handshake).is_empty());
+        assert_eq!(1, cluster.receive_from(member, leave(&clock)).len());
+        assert!(cluster.peers_tx.contains_key(&member));
+    }
+}
@@ -0,0 +1,239 @@
+use futures::sync::oneshot;
+use futures::{future, Future};
+use tokio::net::TcpStream;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use Tx;
+
+// How many addresses are dialed at once before further dials are refused
+pub const DEFAULT_MAX_DIALS: usize = 16;
+
+enum Slot {
+    // A dial is in progress; each waiter is sent its result
+    Dialing(Vec<oneshot::Sender<Tx>>),
+    Open(Tx),
+}
+
+impl Slot {
+    // Whether the connection's writer has gone, closing its channel
+    fn is_closed(&self) -> bool {
+        match *self {
+            Slot::Open(ref tx) => tx.is_closed(),
+            Slot::Dialing(_) => false,
+        }
+    }
+}
+
+// The outbound connections this node has dialed, at most one per address.
+// Asking for an address that is connected, or being dialed, shares that
+// connection rather than opening another. A connection is forgotten once
+// the receiving end of its channel is dropped, which happens when its
+// writer stops, so the next request dials again. The pool's own sender
+// keeps that channel open, so whoever reads the connection should `remove`
+// it when reading stops. Clones share the same connections.
+//
+// At most `max_dials` addresses are dialed at once, so a flood of joins
+// naming addresses that never answer can't open unbounded sockets. A dial
+// past the limit fails straight away rather than waiting, since the peers
+// it would reach are announced again by later joins.
+#[derive(Clone)]
+pub struct PeerConnections {
+    slots: Arc<Mutex<HashMap<SocketAddr, Slot>>>,
+    max_dials: usize,
+}
+
+impl Default for PeerConnections {
+    fn default() -> Self {
+        PeerConnections {
+            slots: Arc::new(Mutex::new(HashMap::new())),
+            max_dials: DEFAULT_MAX_DIALS,
+        }
+    }
+}
+
+impl PeerConnections {
+    pub fn new() -> Self {
+        PeerConnections::default()
+    }
+
+    pub fn with_max_dials(mut self, max_dials: usize) -> Self {
+        self.max_dials = max_dials;
+        self
+    }
+
+    // How many addresses are being dialed right now
+    pub fn dialing(&self) -> usize {
+        self.slots.lock().unwrap().values().filter(|slot| matches!(**slot, Slot::Dialing(_))).count()
+    }
+
+    // A channel to the peer at `addr`, dialing it if there is no open
+    // connection. A new stream is handed to `on_connect`, which takes
+    // ownership of it, typically registering it with the cluster and
+    // starting its reader and writer, and returns the channel that feeds
+    // its writer. `on_connect` isn't called when a connection is reused.
+    // Fails with `WouldBlock` if a new dial is needed and `max_dials` are
+    // already in progress.
+    pub fn connect<F>(&self, addr: SocketAddr, on_connect: F) -> Box<dyn Future<Item = Tx, Error = io::Error>>
+        where F: FnOnce(TcpStream) -> io::Result<Tx> + 'static
+    {
+        let mut slots = self.slots.lock().unwrap();
+        slots.retain(|_, slot| !slot.is_closed());
+        match slots.get_mut(&addr) {
+            Some(&mut Slot::Open(ref tx)) => return Box::new(future::ok(tx.clone())),
+            Some(&mut Slot::Dialing(ref mut waiters)) => {
+                let (waiter, result) = oneshot::channel();
+                waiters.push(waiter);
+                return Box::new(result.map_err(move |_| {
+                    io::Error::new(io::ErrorKind::NotConnected, format!("could not connect to {}", addr))
+                }));
+            }
+            None => {
+                let dialing = slots.values().filter(|slot| matches!(**slot, Slot::Dialing(_))).count();
+                if dialing >= self.max_dials {
+                    return Box::new(future::err(io::Error::new(io::ErrorKind::WouldBlock,
+                        format!("not dialing {}, {} dials are already in progress", addr, dialing))));
+                }
+                slots.insert(addr, Slot::Dialing(vec![]));
+            }
+        }
+
+        let slots = self.slots.clone();
+        Box::new(TcpStream::connect(&addr)
+            .and_then(on_connect)
+            .then(move |result| {
+                let mut slots = slots.lock().unwrap();
+                // Waiters whose dial failed see their sender dropped
+                let waiters = match slots.remove(&addr) {
+                    Some(Slot::Dialing(waiters)) => waiters,
+                    _ => vec![],
+                };
+                let tx = result?;
+                for waiter in waiters {
+                    let _ = waiter.send(tx.clone());
+                }
+                slots.insert(addr, Slot::Open(tx.clone()));
+                Ok(tx)
+            }))
+    }
+
+    // Forgets the connection to `addr`, returning whether there was one.
+    // The stream itself stays open until its writer stops.
+    pub fn remove(&self, addr: &SocketAddr) -> bool {
+        matches!(self.slots.lock().unwrap().remove(addr), Some(Slot::Open(_)))
+    }
+
+    pub fn is_connected(&self, addr: &SocketAddr) -> bool {
+        match self.slots.lock().unwrap().get(addr) {
+            Some(slot @ &Slot::Open(_)) => !slot.is_closed(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use {peer_channel, Cluster, JoinCluster, DEFAULT_CLUSTER_ID};
+    use tokio::runtime::current_thread::Runtime;
+    use Rx;
+
+    // Registers each new stream by counting it and keeping its channel open
+    fn keep(dialed: &Arc<Mutex<Vec<(TcpStream, Rx)>>>) -> impl FnOnce(TcpStream) -> io::Result<Tx> {
+        let dialed = dialed.clone();
+        move |stream| {
+            let (tx, rx) = peer_channel();
+            dialed.lock().unwrap().push((stream, rx));
+            Ok(tx)
+        }
+    }
+
+    #[test]
+    fn connecting_twice_reuses_the_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = PeerConnections::new();
+        let dialed = Arc::new(Mutex::new(vec![]));
+        let mut runtime = Runtime::new().unwrap();
+
+        // The second request arrives while the first is still dialing
+        let both = connections.connect(addr, keep(&dialed)).join(connections.connect(addr, keep(&dialed)));
+        runtime.block_on(both).unwrap();
+        runtime.block_on(connections.connect(addr, keep(&dialed))).unwrap();
+        assert_eq!(1, dialed.lock().unwrap().len());
+        assert!(connections.is_connected(&addr));
+
+        listener.set_nonblocking(true).unwrap();
+        assert!(listener.accept().is_ok());
+        assert_eq!(io::ErrorKind::WouldBlock, listener.accept().unwrap_err().kind());
+
+        // Once the connection's receiver is gone, it is dialed again
+        dialed.lock().unwrap().clear();
+        assert!(!connections.is_connected(&addr));
+        runtime.block_on(connections.connect(addr, keep(&dialed))).unwrap();
+        assert_eq!(1, dialed.lock().unwrap().len());
+        assert!(connections.remove(&addr));
+        assert!(!connections.remove(&addr));
+    }
+
+    #[test]
+    fn failed_dial_is_forgotten() {
+        // Nothing listens on a port that was just released
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let connections = PeerConnections::new();
+        let dialed = Arc::new(Mutex::new(vec![]));
+        let mut runtime = Runtime::new().unwrap();
+
+        let dialing = connections.connect(addr, keep(&dialed)).then(|first| {
+            assert!(first.is_err());
+            Ok::<_, ()>(())
+        });
+        let waiting = connections.connect(addr, keep(&dialed)).then(|second| {
+            assert!(second.is_err());
+            Ok::<_, ()>(())
+        });
+        runtime.block_on(dialing.join(waiting)).unwrap();
+        assert!(dialed.lock().unwrap().is_empty());
+        assert!(!connections.is_connected(&addr));
+    }
+
+    #[test]
+    fn join_flood_dials_at_most_the_limit() {
+        let cluster = Cluster::new("local");
+        let connections = PeerConnections::new().with_max_dials(8);
+        let dialed = Arc::new(Mutex::new(vec![]));
+
+        // The futures are never run, so every dial stays in progress
+        let mut pending = vec![];
+        let mut refused = 0;
+        for port in 0..1000 {
+            let join = JoinCluster {
+                ip: String::from("127.0.0.1"),
+                port: 20_000 + port,
+                handle: format!("peer {}", port),
+                cluster_id: String::from(DEFAULT_CLUSTER_ID),
+            };
+            let addr = cluster.join_target(&join).unwrap();
+            let dialing = connections.dialing();
+            let dial = connections.connect(addr, keep(&dialed));
+            if connections.dialing() == dialing {
+                assert_eq!(io::ErrorKind::WouldBlock, dial.wait().unwrap_err().kind());
+                refused += 1;
+            } else {
+                pending.push(dial);
+            }
+            assert!(connections.dialing() <= 8);
+        }
+        assert_eq!(8, connections.dialing());
+        assert_eq!(992, refused);
+
+        // An address already being dialed shares that dial
+        let again = connections.connect(SocketAddr::from(([127, 0, 0, 1], 20_000)), keep(&dialed));
+        pending.push(again);
+        assert_eq!(8, connections.dialing());
+    }
+}
@@ -0,0 +1,440 @@
+use std::error::Error;
+use std::fmt;
+
+use anti_entropy::{SyncRequest, SyncResponse};
+use clock::VectorClock;
+use compression::{Compressed, Handshake};
+use sequence::{NextSeq, SeqGrant};
+use status::{StatusRequest, StatusResponse};
+use {ApplyInsert, Envelope, JoinCluster, LeaveCluster, Message};
+
+// Every message starts with a tag naming its variant. Tags are fixed here
+// rather than taken from the enum's declaration order, so variants can be
+// added or reordered without changing how existing ones are encoded. A tag
+// must never be reused.
+const JOIN_CLUSTER: u8 = 1;
+const LEAVE_CLUSTER: u8 = 2;
+const APPLY_INSERT: u8 = 3;
+const NEXT_SEQ: u8 = 4;
+const SEQ_GRANT: u8 = 5;
+const STATUS_REQUEST: u8 = 6;
+const STATUS_RESPONSE: u8 = 7;
+const HANDSHAKE: u8 = 8;
+const COMPRESSED: u8 = 9;
+const SYNC_REQUEST: u8 = 10;
+const SYNC_RESPONSE: u8 = 11;
+
+// After the tag come the variant's fields in order. Integers are
+// little-endian and booleans a byte of 0 or 1; strings and byte strings have
+// a u32 length prefix, and vector clocks a u32 entry count followed by
+// (node, u64 count) pairs. An envelope is its sender, its clock and its
+// encoded message as a byte string, and lists of envelopes have a u32 count.
+// Every frame sent to a peer is one encoded envelope.
+//
+// The updates in a sync response are messages as they were delivered, so
+// they are never sync responses or compressed themselves. Either one found
+// there is an error, which keeps decoding from recursing more than a level
+// deep however the input is crafted.
+//
+// Joins and handshakes end with their cluster id, a string, unless it is
+// empty, in which case they end before it and encode as they did before
+// clusters had ids.
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        match *self {
+            Message::JoinClusterMsg(ref msg) => {
+                out.push(JOIN_CLUSTER);
+                put_bytes(&mut out, msg.ip.as_bytes());
+                out.extend_from_slice(&msg.port.to_le_bytes());
+                put_bytes(&mut out, msg.handle.as_bytes());
+                put_cluster_id(&mut out, &msg.cluster_id);
+            }
+            Message::LeaveClusterMsg(ref msg) => {
+                out.push(LEAVE_CLUSTER);
+                put_bytes(&mut out, msg.ip.as_bytes());
+                out.extend_from_slice(&msg.port.to_le_bytes());
+            }
+            Message::ApplyInsertMsg(ref msg) => {
+                out.push(APPLY_INSERT);
+                put_bytes(&mut out, msg.table_name.as_bytes());
+                put_bytes(&mut out, &msg.row_bytes);
+                put_bytes(&mut out, &msg.heap_bytes);
+                put_clock(&mut out, &msg.clock);
+            }
+            Message::NextSeqMsg(ref msg) => {
+                out.push(NEXT_SEQ);
+                put_bytes(&mut out, msg.requester.as_bytes());
+                out.extend_from_slice(&msg.count.to_le_bytes());
+            }
+            Message::SeqGrantMsg(ref msg) => {
+                out.push(SEQ_GRANT);
+                put_bytes(&mut out, msg.requester.as_bytes());
+                out.extend_from_slice(&msg.start.to_le_bytes());
+                out.extend_from_slice(&msg.count.to_le_bytes());
+            }
+            Message::StatusRequestMsg(_) => out.push(STATUS_REQUEST),
+            Message::StatusResponseMsg(ref msg) => {
+                out.push(STATUS_RESPONSE);
+                put_bytes(&mut out, msg.node_id.as_bytes());
+                put_bytes(&mut out, msg.handle.as_bytes());
+                out.extend_from_slice(&msg.uptime_secs.to_le_bytes());
+                out.extend_from_slice(&msg.peer_count.to_le_bytes());
+                put_clock(&mut out, &msg.clock);
+            }
+            Message::HandshakeMsg(ref msg) => {
+                out.push(HANDSHAKE);
+                out.push(msg.compression as u8);
+                put_cluster_id(&mut out, &msg.cluster_id);
+            }
+            Message::CompressedMsg(ref msg) => {
+                out.push(COMPRESSED);
+                put_bytes(&mut out, &msg.payload);
+            }
+            Message::SyncRequestMsg(ref msg) => {
+                out.push(SYNC_REQUEST);
+                put_clock(&mut out, &msg.since);
+            }
+            Message::SyncResponseMsg(ref msg) => {
+                out.push(SYNC_RESPONSE);
+                out.extend_from_slice(&(msg.updates.len() as u32).to_le_bytes());
+                for update in &msg.updates {
+                    put_envelope(&mut out, update);
+                }
+            }
+        }
+        out
+    }
+
+    // Decodes exactly one message; extra bytes after it are an error
+    pub fn decode(buf: &[u8]) -> Result<Message, DecodeError> {
+        Message::decode_in(buf, false)
+    }
+
+    // `in_update` says whether the message is an update in a sync response
+    fn decode_in(buf: &[u8], in_update: bool) -> Result<Message, DecodeError> {
+        let mut reader = Reader(buf);
+        let tag = reader.u8()?;
+        if in_update && (tag == SYNC_RESPONSE || tag == COMPRESSED) {
+            return Err(DecodeError::NestedTag(tag));
+        }
+        let message = match tag {
+            JOIN_CLUSTER => JoinCluster {
+                ip: reader.string()?,
+                port: reader.u32()?,
+                handle: reader.string()?,
+                cluster_id: reader.cluster_id()?,
+            }.into(),
+            LEAVE_CLUSTER => LeaveCluster {
+                ip: reader.string()?,
+                port: reader.u32()?,
+            }.into(),
+            APPLY_INSERT => {
+                let table_name = reader.string()?;
+                let row_bytes = reader.bytes()?.to_vec();
+                let heap_bytes = reader.bytes()?.to_vec();
+                let clock = reader.clock()?;
+                ApplyInsert { table_name, row_bytes, heap_bytes, clock }.into()
+            }
+            NEXT_SEQ => NextSeq {
+                requester: reader.string()?,
+                count: reader.u64()?,
+            }.into(),
+            SEQ_GRANT => SeqGrant {
+                requester: reader.string()?,
+                start: reader.u64()?,
+                count: reader.u64()?,
+            }.into(),
+            STATUS_REQUEST => StatusRequest.into(),
+            STATUS_RESPONSE => StatusResponse {
+                node_id: reader.string()?,
+                handle: reader.string()?,
+                uptime_secs: reader.u64()?,
+                peer_count: reader.u64()?,
+                clock: reader.clock()?,
+            }.into(),
+            HANDSHAKE => Handshake {
+                compression: reader.u8()? != 0,
+                cluster_id: reader.cluster_id()?,
+            }.into(),
+            COMPRESSED => Compressed {
+                payload: reader.bytes()?.to_vec(),
+            }.into(),
+            SYNC_REQUEST => SyncRequest {
+                since: reader.clock()?,
+            }.into(),
+            SYNC_RESPONSE => {
+                let update_count = reader.u32()?;
+                let updates = (0..update_count)
+                    .map(|_| reader.envelope(true))
+                    .collect::<Result<_, DecodeError>>()?;
+                SyncResponse { updates }.into()
+            }
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+        if !reader.0.is_empty() {
+            return Err(DecodeError::TrailingBytes(reader.0.len()));
+        }
+        Ok(message)
+    }
+}
+
+impl Envelope {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        put_envelope(&mut out, self);
+        out
+    }
+
+    // Decodes exactly one envelope; extra bytes after it are an error
+    pub fn decode(buf: &[u8]) -> Result<Envelope, DecodeError> {
+        let mut reader = Reader(buf);
+        let envelope = reader.envelope(false)?;
+        if !reader.0.is_empty() {
+            return Err(DecodeError::TrailingBytes(reader.0.len()));
+        }
+        Ok(envelope)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    // The buffer ended in the middle of a message
+    Truncated,
+    UnknownTag(u8),
+    InvalidUtf8,
+    TrailingBytes(usize),
+    // An update in a sync response had a tag updates never have
+    NestedTag(u8),
+    // A compressed message's payload couldn't be inflated, or inflated past
+    // the most it may
+    Decompression(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated => write!(f, "Message is truncated"),
+            DecodeError::UnknownTag(tag) => write!(f, "Unknown message tag {}", tag),
+            DecodeError::InvalidUtf8 => write!(f, "String field is not valid UTF-8"),
+            DecodeError::TrailingBytes(len) => write!(f, "{} bytes after the message", len),
+            DecodeError::NestedTag(tag) => write!(f, "Message tag {} inside a sync response", tag),
+            DecodeError::Decompression(ref err) => write!(f, "Compressed message is invalid: {}", err),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_cluster_id(out: &mut Vec<u8>, cluster_id: &str) {
+    if !cluster_id.is_empty() {
+        put_bytes(out, cluster_id.as_bytes());
+    }
+}
+
+fn put_envelope(out: &mut Vec<u8>, envelope: &Envelope) {
+    put_bytes(out, envelope.sender.as_bytes());
+    put_clock(out, &envelope.clock);
+    put_bytes(out, &envelope.message.encode());
+}
+
+fn put_clock(out: &mut Vec<u8>, clock: &VectorClock) {
+    let entries: Vec<(&str, u64)> = clock.entries().collect();
+    out.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (node, count) in entries {
+        put_bytes(out, node.as_bytes());
+        out.extend_from_slice(&count.to_le_bytes());
+    }
+}
+
+// Reads fields off the front of a buffer
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.0.len() < len {
+            return Err(DecodeError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    // The last field of a message, and absent if empty
+    fn cluster_id(&mut self) -> Result<String, DecodeError> {
+        if self.0.is_empty() {
+            return Ok(String::new());
+        }
+        self.string()
+    }
+
+    fn clock(&mut self) -> Result<VectorClock, DecodeError> {
+        let entry_count = self.u32()?;
+        (0..entry_count).map(|_| Ok((self.string()?, self.u64()?))).collect()
+    }
+
+    fn envelope(&mut self, in_update: bool) -> Result<Envelope, DecodeError> {
+        Ok(Envelope {
+            sender: self.string()?,
+            clock: self.clock()?,
+            message: Message::decode_in(self.bytes()?, in_update)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leave() -> Message {
+        LeaveCluster { ip: String::from("10.0.0.1"), port: 3400 }.into()
+    }
+
+    fn apply_insert() -> Message {
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+        clock.increment("B");
+        clock.increment("B");
+        ApplyInsert {
+            table_name: String::from("notes"),
+            row_bytes: vec![1, 2, 3],
+            heap_bytes: vec![],
+            clock,
+        }.into()
+    }
+
+    // These bytes must never change, whatever happens to the enum
+    #[test]
+    fn encoding_is_stable() {
+        assert_eq!(vec![
+            2,
+            8, 0, 0, 0, b'1', b'0', b'.', b'0', b'.', b'0', b'.', b'1',
+            0x48, 0x0d, 0, 0,
+        ], leave().encode());
+
+        let join: Message = JoinCluster {
+            ip: String::from("h"),
+            port: 1,
+            handle: String::from("n"),
+            cluster_id: String::new(),
+        }.into();
+        assert_eq!(vec![1, 1, 0, 0, 0, b'h', 1, 0, 0, 0, 1, 0, 0, 0, b'n'], join.encode());
+
+        let grant: Message = SeqGrant { requester: String::from("A"), start: 3, count: 2 }.into();
+        assert_eq!(vec![
+            5,
+            1, 0, 0, 0, b'A',
+            3, 0, 0, 0, 0, 0, 0, 0,
+            2, 0, 0, 0, 0, 0, 0, 0,
+        ], grant.encode());
+
+        assert_eq!(vec![
+            3,
+            5, 0, 0, 0, b'n', b'o', b't', b'e', b's',
+            3, 0, 0, 0, 1, 2, 3,
+            0, 0, 0, 0,
+            2, 0, 0, 0,
+            1, 0, 0, 0, b'A', 1, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, b'B', 2, 0, 0, 0, 0, 0, 0, 0,
+        ], apply_insert().encode());
+    }
+
+    #[test]
+    fn messages_roundtrip() {
+        let next_seq = NextSeq { requester: String::from("B"), count: 1000 }.into();
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+        let status = StatusResponse {
+            node_id: String::from("A"),
+            handle: String::from("node1"),
+            uptime_secs: 90,
+            peer_count: 2,
+            clock,
+        }.into();
+        let handshake = Handshake { compression: true, cluster_id: String::new() }.into();
+        let named_handshake = Handshake { compression: false, cluster_id: String::from("blue") }.into();
+        let compressed = Compressed { payload: vec![1, 2, 3] }.into();
+        let sync_request = SyncRequest { since: VectorClock::new() }.into();
+        let sync_response = SyncResponse {
+            updates: vec![Envelope::new("A", VectorClock::new(), leave()), Envelope::new("B", VectorClock::new(), apply_insert())],
+        }.into();
+        let messages = [leave(), apply_insert(), next_seq, StatusRequest.into(), status, handshake, named_handshake, compressed, sync_request, sync_response];
+        for message in &messages {
+            assert_eq!(Ok(message), Message::decode(&message.encode()).as_ref());
+        }
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let encoded = apply_insert().encode();
+        for len in 0..encoded.len() {
+            assert_eq!(Err(DecodeError::Truncated), Message::decode(&encoded[..len]));
+        }
+
+        let mut extra = encoded.clone();
+        extra.push(0);
+        assert_eq!(Err(DecodeError::TrailingBytes(1)), Message::decode(&extra));
+        assert_eq!(Err(DecodeError::UnknownTag(0)), Message::decode(&[0]));
+    }
+
+    #[test]
+    fn envelopes_roundtrip() {
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+        let envelope = Envelope::new("A", clock, apply_insert());
+        assert_eq!(Ok(envelope.clone()), Envelope::decode(&envelope.encode()));
+
+        let mut extra = envelope.encode();
+        extra.push(0);
+        assert_eq!(Err(DecodeError::TrailingBytes(1)), Envelope::decode(&extra));
+    }
+
+    #[test]
+    fn sync_responses_nest_one_level_deep() {
+        let inner: Message = SyncResponse { updates: vec![Envelope::new("A", VectorClock::new(), leave())] }.into();
+        let compressed: Message = Compressed { payload: vec![1, 2, 3] }.into();
+        assert!(Message::decode(&inner.encode()).is_ok());
+
+        for (update, tag) in [(inner, SYNC_RESPONSE), (compressed, COMPRESSED)] {
+            let outer: Message = SyncResponse { updates: vec![Envelope::new("B", VectorClock::new(), update)] }.into();
+            assert_eq!(Err(DecodeError::NestedTag(tag)), Message::decode(&outer.encode()));
+        }
+
+        // However deep the input goes, decoding stops at the second level
+        let mut deep: Message = leave();
+        for _ in 0..1000 {
+            deep = SyncResponse { updates: vec![Envelope::new("A", VectorClock::new(), deep)] }.into();
+        }
+        assert_eq!(Err(DecodeError::NestedTag(SYNC_RESPONSE)), Message::decode(&deep.encode()));
+    }
+}
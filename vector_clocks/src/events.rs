@@ -0,0 +1,104 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use Envelope;
+
+// Receives what happens to a cluster as it happens: messages delivered,
+// peers coming and going, and errors from the tasks around it. A cluster is
+// shared between threads, so sinks must be too.
+pub trait EventSink: Send + Sync {
+    // A message has been delivered in causal order
+    fn on_message(&self, envelope: &Envelope);
+    // A peer's connection has been registered
+    fn on_peer_join(&self, addr: &SocketAddr);
+    // A peer has been removed, whether it left, was evicted or went idle
+    fn on_peer_leave(&self, addr: &SocketAddr);
+    fn on_error(&self, message: &str);
+}
+
+// Prints every event to stdout
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl EventSink for StdoutSink {
+    fn on_message(&self, envelope: &Envelope) {
+        println!("GOT: {:?}", envelope.message);
+    }
+
+    fn on_peer_join(&self, addr: &SocketAddr) {
+        println!("Peer connected: {}", addr);
+    }
+
+    fn on_peer_leave(&self, addr: &SocketAddr) {
+        println!("Peer left: {}", addr);
+    }
+
+    fn on_error(&self, message: &str) {
+        println!("{}", message);
+    }
+}
+
+pub fn stdout_sink() -> Arc<dyn EventSink> {
+    Arc::new(StdoutSink)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use {peer_channel, Cluster, LeaveCluster};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Message(String),
+        Join(SocketAddr),
+        Leave(SocketAddr),
+        Error(String),
+    }
+
+    #[derive(Default)]
+    struct RecordingSink(Mutex<Vec<Event>>);
+
+    impl EventSink for RecordingSink {
+        fn on_message(&self, envelope: &Envelope) {
+            self.0.lock().unwrap().push(Event::Message(envelope.sender.clone()));
+        }
+
+        fn on_peer_join(&self, addr: &SocketAddr) {
+            self.0.lock().unwrap().push(Event::Join(*addr));
+        }
+
+        fn on_peer_leave(&self, addr: &SocketAddr) {
+            self.0.lock().unwrap().push(Event::Leave(*addr));
+        }
+
+        fn on_error(&self, message: &str) {
+            self.0.lock().unwrap().push(Event::Error(message.to_string()));
+        }
+    }
+
+    #[test]
+    fn recording_sink_sees_peer_lifecycle() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut cluster = Cluster::new("local").with_event_sink(sink.clone());
+        let peer: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+
+        let (peer_tx, _peer_rx) = peer_channel();
+        cluster.add_peer(peer, peer_tx);
+        let mut clock = ::clock::VectorClock::new();
+        clock.increment("A");
+        cluster.receive(Envelope::new("A", clock, LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3402,
+        }));
+        cluster.evict(&peer);
+        cluster.events().on_error("something failed");
+
+        assert_eq!(vec![
+            Event::Join(peer),
+            Event::Message(String::from("A")),
+            Event::Leave(peer),
+            Event::Error(String::from("something failed")),
+        ], *sink.0.lock().unwrap());
+    }
+}
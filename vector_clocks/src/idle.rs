@@ -0,0 +1,137 @@
+use futures::{Future, Stream};
+use tokio::timer::Interval;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use {Cluster, LeaveCluster};
+
+// Connections are closed after this long without receiving anything
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Tracks when a connection last received a frame. This is separate from
+// detecting dead peers: an idle peer may be perfectly healthy.
+#[derive(Debug)]
+pub struct IdleTimer {
+    timeout: Duration,
+    last_activity: Instant,
+}
+
+impl IdleTimer {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        IdleTimer {
+            timeout,
+            last_activity: now,
+        }
+    }
+
+    // Records inbound activity, restarting the timer
+    pub fn touch(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.checked_duration_since(self.last_activity)
+            .is_some_and(|idle| idle >= self.timeout)
+    }
+}
+
+impl Cluster {
+    // Closes the connection to a peer that has gone quiet. Every peer,
+    // including the idle one, is sent a `LeaveCluster` for it first, so the
+    // message advances every peer's view of this node's clock alike. It
+    // stops being a member, as an evicted peer does.
+    pub fn close_idle(&mut self, addr: &SocketAddr) {
+        if !self.peers_tx.contains_key(addr) {
+            return;
+        }
+        self.publish(LeaveCluster {
+            ip: addr.ip().to_string(),
+            port: u32::from(addr.port()),
+        });
+        // The writer finishes once it has sent what is queued
+        self.remove_peer(addr);
+        self.remove_member(&addr.to_string());
+    }
+}
+
+// Checks a connection's timer every `check_every` and closes the connection
+// once it has been idle too long. The returned future finishes when it
+// does, at which point the connection's reader should be dropped; it must
+// run on a tokio runtime.
+pub fn watch_idle(addr: SocketAddr, timer: Arc<Mutex<IdleTimer>>, cluster: Arc<Mutex<Cluster>>, check_every: Duration)
+    -> impl Future<Item = (), Error = ()>
+{
+    Interval::new(Instant::now() + check_every, check_every)
+        .map_err(|_| ())
+        .skip_while(move |&now| Ok(!timer.lock().unwrap().is_expired(now)))
+        .into_future()
+        .map(move |_| cluster.lock().unwrap().close_idle(&addr))
+        .map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use {peer_channel, Envelope, Message};
+
+    #[test]
+    fn idle_connection_expires_and_active_one_does_not() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let mut idle = IdleTimer::new(timeout, start);
+        let mut active = IdleTimer::new(timeout, start);
+
+        for second in (10..=120).step_by(10) {
+            active.touch(start + Duration::from_secs(second));
+        }
+        idle.touch(start + Duration::from_secs(10));
+
+        let now = start + Duration::from_secs(125);
+        assert!(idle.is_expired(now));
+        assert!(!active.is_expired(now));
+        assert!(!idle.is_expired(start + Duration::from_secs(69)));
+    }
+
+    #[test]
+    fn closing_idle_peer_sends_leave_and_closes_channel() {
+        let idle: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let active: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let mut cluster = Cluster::new("local");
+        let (idle_tx, idle_rx) = peer_channel();
+        let (active_tx, active_rx) = peer_channel();
+        cluster.peers_tx.insert(idle, idle_tx);
+        cluster.peers_tx.insert(active, active_tx);
+
+        cluster.close_idle(&idle);
+
+        assert!(!cluster.peers_tx.contains_key(&idle));
+        assert!(cluster.peers_tx.contains_key(&active));
+        // The idle peer's channel is closed once its last frame is read
+        let frames: Vec<Bytes> = idle_rx.collect().wait().unwrap();
+        assert_eq!(1, frames.len());
+        let envelope = Envelope::decode(&frames[0]).unwrap();
+        assert_eq!(Message::LeaveClusterMsg(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3401,
+        }), envelope.message);
+
+        drop(cluster);
+        assert_eq!(1, active_rx.collect().wait().unwrap().len());
+    }
+
+    #[test]
+    fn closed_idle_coordinator_stops_coordinating() {
+        let idle: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let mut cluster = Cluster::new("local");
+        let (idle_tx, _idle_rx) = peer_channel();
+        cluster.peers_tx.insert(idle, idle_tx);
+        cluster.add_member(idle.to_string());
+        assert_eq!("127.0.0.1:3401", cluster.coordinator());
+
+        cluster.close_idle(&idle);
+        assert_eq!("local", cluster.coordinator());
+    }
+}
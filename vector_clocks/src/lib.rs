@@ -0,0 +1,858 @@
+#[macro_use]
+extern crate serde_derive;
+extern crate bincode;
+extern crate futures;
+extern crate bytes;
+extern crate flate2;
+extern crate tokio;
+
+pub mod anti_entropy;
+pub mod bind;
+pub mod blacklist;
+pub mod clock;
+pub mod compression;
+pub mod connections;
+pub mod encoding;
+pub mod events;
+pub mod idle;
+pub mod metrics;
+pub mod rate_limit;
+pub mod sequence;
+pub mod snapshot;
+pub mod status;
+pub mod wire;
+pub mod writer;
+
+use futures::sync::mpsc;
+use bytes::Bytes;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anti_entropy::{SyncLog, SyncRequest, SyncResponse};
+use blacklist::Blacklist;
+use clock::{NodeId, VectorClock};
+use compression::{Compressed, Handshake, Outgoing};
+use events::EventSink;
+use metrics::Metrics;
+use sequence::{NextSeq, SeqGrant, Sequence};
+use status::{StatusRequest, StatusResponse};
+
+pub type Tx = mpsc::Sender<Bytes>;
+pub type Rx = mpsc::Receiver<Bytes>;
+
+// Messages queued for a peer before further ones are dropped
+pub const PEER_BUFFER: usize = 64;
+
+// Nodes that aren't told which cluster they belong to share this one
+pub const DEFAULT_CLUSTER_ID: &str = "";
+// Names the cluster a node belongs to
+pub const CLUSTER_ID_VAR: &str = "CLUSTER_ID";
+
+// Creates the channel used to queue outgoing messages for one peer
+pub fn peer_channel() -> (Tx, Rx) {
+    mpsc::channel(PEER_BUFFER)
+}
+
+pub struct Cluster {
+    pub peers_tx: HashMap<SocketAddr, Tx>,
+    // This node's identity in vector clocks
+    node_id: NodeId,
+    // The address this node listens on, if known
+    local_addr: Option<SocketAddr>,
+    // The name this node reports in status responses
+    handle: String,
+    // Peers whose handshake names another cluster are dropped
+    cluster_id: String,
+    started: Instant,
+    // The peer each sender's messages last arrived from
+    routes: HashMap<NodeId, SocketAddr>,
+    // Peers whose handshake asked for large messages to be compressed
+    compression: HashSet<SocketAddr>,
+    // Peers whose handshake named this cluster. Anything else a peer sends
+    // before its handshake drops it.
+    handshaken: HashSet<SocketAddr>,
+    clock: VectorClock,
+    // The highest count seen for each node in any message that arrived,
+    // whether or not it could be delivered yet
+    advertised: VectorClock,
+    // Messages that arrived before their causal dependencies
+    hold_back: Vec<Envelope>,
+    metrics: Arc<Metrics>,
+    events: Arc<dyn EventSink>,
+    sequence: Sequence,
+    // Recent updates, to answer sync requests with
+    sync_log: SyncLog,
+    // Addresses whose connections and joins are refused
+    blacklist: Blacklist,
+    // Counts broadcasts, to pick which peer is sent to first
+    broadcasts: usize,
+}
+
+impl Cluster {
+    pub fn new<S>(node_id: S) -> Self where S: Into<NodeId> {
+        let node_id = node_id.into();
+        Cluster {
+            peers_tx: HashMap::new(),
+            handle: node_id.clone(),
+            node_id,
+            cluster_id: String::from(DEFAULT_CLUSTER_ID),
+            local_addr: None,
+            started: Instant::now(),
+            routes: HashMap::new(),
+            compression: HashSet::new(),
+            handshaken: HashSet::new(),
+            clock: VectorClock::new(),
+            advertised: VectorClock::new(),
+            hold_back: Vec::new(),
+            metrics: Arc::new(Metrics::new()),
+            events: events::stdout_sink(),
+            sequence: Sequence::new(),
+            sync_log: SyncLog::new(),
+            blacklist: Blacklist::new(),
+            broadcasts: 0,
+        }
+    }
+
+    pub fn with_local_addr(mut self, addr: SocketAddr) -> Self {
+        self.local_addr = Some(addr);
+        self
+    }
+
+    pub fn with_cluster_id<S>(mut self, cluster_id: S) -> Self where S: Into<String> {
+        self.cluster_id = cluster_id.into();
+        self
+    }
+
+    pub fn cluster_id(&self) -> &str {
+        &self.cluster_id
+    }
+
+    pub fn with_event_sink(mut self, events: Arc<dyn EventSink>) -> Self {
+        self.events = events;
+        self
+    }
+
+    // Where events are reported, for the tasks driving the cluster to
+    // report their own errors to
+    pub fn events(&self) -> Arc<dyn EventSink> {
+        self.events.clone()
+    }
+
+    // Registers a connected peer, whose outgoing messages are queued on `tx`
+    pub fn add_peer(&mut self, addr: SocketAddr, tx: Tx) {
+        self.peers_tx.insert(addr, tx);
+        self.events.on_peer_join(&addr);
+    }
+
+    // Forgets a peer, returning whether it was connected
+    pub(crate) fn remove_peer(&mut self, addr: &SocketAddr) -> bool {
+        if self.peers_tx.remove(addr).is_none() {
+            return false;
+        }
+        self.routes.retain(|_, route| route != addr);
+        self.compression.remove(addr);
+        self.handshaken.remove(addr);
+        self.events.on_peer_leave(addr);
+        true
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn clock(&self) -> &VectorClock {
+        &self.clock
+    }
+
+    // How many messages behind this node is on each node it has heard of:
+    // how far the clocks of the messages that arrived reach, less what has
+    // been delivered. Lag comes from messages held back for missing
+    // dependencies, so a node that has delivered everything it was sent
+    // reports 0 for every node.
+    pub fn replication_lag(&self) -> HashMap<NodeId, u64> {
+        self.advertised.entries()
+            .map(|(node, count)| (node.to_string(), count.saturating_sub(self.clock.get(node))))
+            .collect()
+    }
+
+    // Wraps a message sent by this node, advancing its clock
+    pub fn originate<M>(&mut self, message: M) -> Envelope where M: Into<Message> {
+        self.clock.increment(&self.node_id);
+        let envelope = Envelope::new(self.node_id.clone(), self.clock.clone(), message);
+        self.sync_log.record(&envelope);
+        envelope
+    }
+
+    // Sends a message from this node to every peer and returns it as sent
+    pub fn publish<M>(&mut self, message: M) -> Envelope where M: Into<Message> {
+        let envelope = self.originate(message);
+        let mut outgoing = Outgoing::new(Bytes::from(envelope.encode()), Some(envelope));
+        self.send_to_peers(None, &mut outgoing);
+        outgoing.into_envelope().unwrap()
+    }
+
+    // Drops a peer that has stopped responding and tells the remaining
+    // peers that it left
+    pub fn evict(&mut self, addr: &SocketAddr) {
+        if !self.remove_peer(addr) {
+            return;
+        }
+        self.remove_member(&addr.to_string());
+        self.publish(LeaveCluster {
+            ip: addr.ip().to_string(),
+            port: u32::from(addr.port()),
+        });
+    }
+
+    // The address a join asks this node to dial, or None if it should be
+    // ignored: when it can't be parsed, points back at this node, names a
+    // peer that is already connected or is blacklisted, or is for another
+    // cluster
+    pub fn join_target(&self, join: &JoinCluster) -> Option<SocketAddr> {
+        if join.cluster_id != self.cluster_id {
+            return None;
+        }
+        let ip: IpAddr = join.ip.parse().ok()?;
+        if join.port > u32::from(u16::MAX) {
+            return None;
+        }
+        let addr = SocketAddr::new(ip, join.port as u16);
+        if self.is_local(&addr) || self.peers_tx.contains_key(&addr) || self.blacklist.contains(&addr) {
+            return None;
+        }
+        Some(addr)
+    }
+
+    // A node listening on all interfaces can be reached at any loopback
+    // address on its port
+    fn is_local(&self, addr: &SocketAddr) -> bool {
+        match self.local_addr {
+            Some(local) if local.ip().is_unspecified() =>
+                local.port() == addr.port() && (addr.ip().is_loopback() || addr.ip().is_unspecified()),
+            Some(local) => local == *addr,
+            None => false,
+        }
+    }
+
+    // The counters can be read and updated without holding the cluster lock
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    // Sends an encoded message to every peer other than the one it came
+    // from, without waiting on any of them. A peer whose channel is full
+    // misses the message, and one whose channel is closed has disconnected
+    // and is removed. Like every message sent to several peers, it is
+    // compressed or decompressed to suit each peer.
+    pub fn try_broadcast(&mut self, origin: &SocketAddr, msg: Bytes) {
+        let envelope = Envelope::decode(&msg).ok();
+        self.send_to_peers(Some(origin), &mut Outgoing::new(msg, envelope));
+    }
+
+    // Queues an encoded message for one peer. A peer whose channel is
+    // closed has disconnected and is removed.
+    fn send_frame(&mut self, addr: SocketAddr, msg: Bytes) -> Result<(), SendError> {
+        let sent = match self.peers_tx.get_mut(&addr) {
+            Some(tx) => tx.try_send(msg),
+            None => return Err(SendError::UnknownPeer(addr)),
+        };
+        match sent {
+            Ok(()) => Ok(()),
+            Err(ref err) if err.is_full() => {
+                self.metrics.record_dropped_send();
+                Err(SendError::Full(addr))
+            }
+            Err(_) => {
+                self.remove_peer(&addr);
+                Err(SendError::Disconnected(addr))
+            }
+        }
+    }
+
+    fn send_to_peers(&mut self, skip: Option<&SocketAddr>, outgoing: &mut Outgoing) {
+        let mut disconnected = vec![];
+        for addr in self.broadcast_order() {
+            if Some(&addr) == skip {
+                continue;
+            }
+            let msg = match outgoing.frame_for(self.compression.contains(&addr)) {
+                Some(msg) => msg,
+                None => {
+                    self.metrics.record_dropped_send();
+                    continue;
+                }
+            };
+            if let Err(err) = self.peers_tx.get_mut(&addr).unwrap().try_send(msg) {
+                if err.is_full() {
+                    self.metrics.record_dropped_send();
+                } else {
+                    disconnected.push(addr);
+                }
+            }
+        }
+        for addr in disconnected {
+            self.remove_peer(&addr);
+        }
+        self.metrics.record_broadcast();
+    }
+
+    // The peers in the order the next broadcast should reach them: sorted by
+    // address, then rotated one place further each time, so every peer is
+    // regularly the first to be sent to rather than some always coming last
+    fn broadcast_order(&mut self) -> Vec<SocketAddr> {
+        let mut order: Vec<SocketAddr> = self.peers_tx.keys().cloned().collect();
+        order.sort();
+        if !order.is_empty() {
+            let first = self.broadcasts % order.len();
+            order.rotate_left(first);
+        }
+        self.broadcasts = self.broadcasts.wrapping_add(1);
+        order
+    }
+
+    // Accepts a message from the network and returns every message that has
+    // become deliverable as a result, in causal order. Messages whose
+    // dependencies are not yet satisfied are held back until they are, and
+    // messages that were already delivered are discarded.
+    //
+    // Senders of delivered messages are taken to be members, and a node
+    // that leaves stops being one; a node's id is the address it listens
+    // on. Sequence requests and grants are acted on as they are delivered.
+    //
+    // Compressed messages are decompressed first, and one that can't be is
+    // counted as a decode failure. Handshakes and sync requests aren't part
+    // of the causal history and are ignored; `receive_from` acts on them.
+    // The updates in a sync response are received one by one.
+    pub fn receive(&mut self, envelope: Envelope) -> Vec<Envelope> {
+        self.metrics.record_received();
+        let envelope = match envelope.decompress() {
+            Ok(envelope) => envelope,
+            Err(_) => {
+                self.metrics.record_decode_failure();
+                return vec![];
+            }
+        };
+        let envelope = match envelope.message {
+            Message::HandshakeMsg(_) | Message::SyncRequestMsg(_) => return vec![],
+            Message::SyncResponseMsg(response) => return self.receive_sync(response),
+            _ => envelope,
+        };
+        self.advertised.merge(&envelope.clock);
+        if self.clock.has_seen(&envelope.sender, &envelope.clock) {
+            self.metrics.record_dropped();
+            return vec![];
+        }
+        self.hold_back.push(envelope);
+
+        let mut delivered = vec![];
+        while let Some(idx) = self.hold_back.iter()
+            .position(|env| self.clock.can_deliver(&env.sender, &env.clock))
+        {
+            let envelope = self.hold_back.remove(idx);
+            self.clock.merge(&envelope.clock);
+            self.add_member(envelope.sender.clone());
+            if let Message::LeaveClusterMsg(ref leave) = envelope.message {
+                self.remove_member(&format!("{}:{}", leave.ip, leave.port));
+            }
+            self.apply_sequence(&envelope);
+            self.sync_log.record(&envelope);
+            self.events.on_message(&envelope);
+            delivered.push(envelope);
+        }
+
+        self.metrics.record_processed(delivered.len() as u64);
+        delivered
+    }
+}
+
+pub struct Peer {
+    pub addr: SocketAddr,
+    pub handle: String,
+    pub cluster: Arc<Mutex<Cluster>>,
+}
+
+impl Peer {
+
+    pub fn new<S>(addr: SocketAddr, handle: S, cluster: Arc<Mutex<Cluster>>) -> Self where S: Into<String> {
+        Peer {
+            addr,
+            handle: handle.into(),
+            cluster,
+        }
+    }
+
+    // Sends a message from this node to this peer alone. Like a handshake,
+    // it is stamped with this node's clock without advancing it. A peer
+    // whose channel is closed has disconnected and is removed from the
+    // cluster.
+    pub fn send(&self, msg: &Message) -> Result<(), SendError> {
+        let mut cluster = self.cluster.lock().unwrap();
+        if !cluster.peers_tx.contains_key(&self.addr) {
+            return Err(SendError::UnknownPeer(self.addr));
+        }
+        let envelope = Envelope::new(cluster.node_id.clone(), cluster.clock.clone(), msg.clone());
+        cluster.send_frame(self.addr, Bytes::from(envelope.encode()))
+    }
+}
+
+// Why a message couldn't be queued for a peer
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError {
+    // The peer isn't connected to this cluster
+    UnknownPeer(SocketAddr),
+    // The peer's queue is full, so the message was dropped
+    Full(SocketAddr),
+    // The peer's channel has closed
+    Disconnected(SocketAddr),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SendError::UnknownPeer(addr) => write!(f, "{} is not a peer", addr),
+            SendError::Full(addr) => write!(f, "Send queue for {} is full", addr),
+            SendError::Disconnected(addr) => write!(f, "{} has disconnected", addr),
+        }
+    }
+}
+
+impl Error for SendError {}
+
+// Wraps every message on the wire with its sender and the sender's vector
+// clock at the time it was sent, so the message structs stay clock-free
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Envelope {
+    pub sender: NodeId,
+    pub clock: VectorClock,
+    pub message: Message,
+}
+
+impl Envelope {
+    pub fn new<S, M>(sender: S, clock: VectorClock, message: M) -> Self
+        where S: Into<NodeId>, M: Into<Message>
+    {
+        Envelope {
+            sender: sender.into(),
+            clock,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Message {
+    JoinClusterMsg(JoinCluster),
+    LeaveClusterMsg(LeaveCluster),
+    ApplyInsertMsg(ApplyInsert),
+    NextSeqMsg(NextSeq),
+    SeqGrantMsg(SeqGrant),
+    HandshakeMsg(Handshake),
+    CompressedMsg(Compressed),
+    StatusRequestMsg(StatusRequest),
+    StatusResponseMsg(StatusResponse),
+    SyncRequestMsg(SyncRequest),
+    SyncResponseMsg(SyncResponse),
+}
+
+impl From<JoinCluster> for Message {
+    fn from(jc: JoinCluster) -> Self {
+        Message::JoinClusterMsg(jc)
+    }
+}
+
+impl From<LeaveCluster> for Message {
+    fn from(lc: LeaveCluster) -> Self {
+        Message::LeaveClusterMsg(lc)
+    }
+}
+
+impl From<ApplyInsert> for Message {
+    fn from(ai: ApplyInsert) -> Self {
+        Message::ApplyInsertMsg(ai)
+    }
+}
+
+impl From<NextSeq> for Message {
+    fn from(ns: NextSeq) -> Self {
+        Message::NextSeqMsg(ns)
+    }
+}
+
+impl From<SeqGrant> for Message {
+    fn from(sg: SeqGrant) -> Self {
+        Message::SeqGrantMsg(sg)
+    }
+}
+
+impl From<Handshake> for Message {
+    fn from(hs: Handshake) -> Self {
+        Message::HandshakeMsg(hs)
+    }
+}
+
+impl From<Compressed> for Message {
+    fn from(c: Compressed) -> Self {
+        Message::CompressedMsg(c)
+    }
+}
+
+impl From<StatusRequest> for Message {
+    fn from(sr: StatusRequest) -> Self {
+        Message::StatusRequestMsg(sr)
+    }
+}
+
+impl From<StatusResponse> for Message {
+    fn from(sr: StatusResponse) -> Self {
+        Message::StatusResponseMsg(sr)
+    }
+}
+
+impl From<SyncRequest> for Message {
+    fn from(sr: SyncRequest) -> Self {
+        Message::SyncRequestMsg(sr)
+    }
+}
+
+impl From<SyncResponse> for Message {
+    fn from(sr: SyncResponse) -> Self {
+        Message::SyncResponseMsg(sr)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct JoinCluster {
+    pub ip: String,
+    pub port: u32,
+    pub handle: String,
+    pub cluster_id: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct LeaveCluster {
+    pub ip: String,
+    pub port: u32
+}
+
+// A row inserted on the sending node, in the raw form produced by the
+// storage crate's `Table::export_row`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ApplyInsert {
+    pub table_name: String,
+    pub row_bytes: Vec<u8>,
+    pub heap_bytes: Vec<u8>,
+    // The sender's clock once the insert was made
+    pub clock: VectorClock,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+
+    fn leave_from(sender: &str, clock: VectorClock) -> Envelope {
+        Envelope::new(sender, clock, LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        })
+    }
+
+    fn join(ip: &str, port: u32) -> JoinCluster {
+        JoinCluster {
+            ip: String::from(ip),
+            port,
+            handle: String::from("peer"),
+            cluster_id: String::from(DEFAULT_CLUSTER_ID),
+        }
+    }
+
+    #[test]
+    fn self_and_known_joins_are_ignored() {
+        let local: SocketAddr = "0.0.0.0:3400".parse().unwrap();
+        let known: SocketAddr = "10.0.0.2:3400".parse().unwrap();
+        let mut cluster = Cluster::new("local").with_local_addr(local);
+        cluster.peers_tx.insert(known, peer_channel().0);
+
+        assert_eq!(None, cluster.join_target(&join("127.0.0.1", 3400)));
+        assert_eq!(None, cluster.join_target(&join("0.0.0.0", 3400)));
+        assert_eq!(None, cluster.join_target(&join("10.0.0.2", 3400)));
+        assert_eq!(None, cluster.join_target(&join("10.0.0.3", 70000)));
+        assert_eq!(None, cluster.join_target(&join("not an ip", 3400)));
+        assert_eq!(1, cluster.peers_tx.len());
+
+        assert_eq!(Some("127.0.0.1:3401".parse().unwrap()), cluster.join_target(&join("127.0.0.1", 3401)));
+        assert_eq!(Some("10.0.0.3:3400".parse().unwrap()), cluster.join_target(&join("10.0.0.3", 3400)));
+        let elsewhere = JoinCluster { cluster_id: String::from("red"), ..join("10.0.0.3", 3400) };
+        assert_eq!(None, cluster.join_target(&elsewhere));
+    }
+
+    #[test]
+    fn out_of_order_message_is_held_back() {
+        let mut cluster = Cluster::new("local");
+
+        let mut clock1 = VectorClock::new();
+        clock1.increment("A");
+        let mut clock2 = clock1.clone();
+        clock2.increment("A");
+
+        let delivered = cluster.receive(leave_from("A", clock2.clone()));
+        assert!(delivered.is_empty());
+
+        let delivered = cluster.receive(leave_from("A", clock1.clone()));
+        let clocks: Vec<VectorClock> = delivered.into_iter().map(|env| env.clock).collect();
+        assert_eq!(vec![clock1, clock2.clone()], clocks);
+        assert_eq!(&clock2, cluster.clock());
+    }
+
+    #[test]
+    fn message_waits_for_dependency_from_other_node() {
+        let mut cluster = Cluster::new("local");
+
+        let mut from_b = VectorClock::new();
+        from_b.increment("B");
+        let mut from_a = from_b.clone();
+        from_a.increment("A");
+
+        assert!(cluster.receive(leave_from("A", from_a)).is_empty());
+        assert_eq!(2, cluster.receive(leave_from("B", from_b)).len());
+    }
+
+    #[test]
+    fn lag_counts_messages_not_yet_delivered() {
+        let mut cluster = Cluster::new("local");
+
+        let mut from_b = VectorClock::new();
+        from_b.increment("B");
+        assert_eq!(1, cluster.receive(leave_from("B", from_b.clone())).len());
+        // B's fourth message arrives before its second and third
+        for _ in 0..3 {
+            from_b.increment("B");
+        }
+        let mut from_a = VectorClock::new();
+        from_a.increment("A");
+        assert!(cluster.receive(leave_from("B", from_b)).is_empty());
+        assert_eq!(1, cluster.receive(leave_from("A", from_a)).len());
+
+        let lag = cluster.replication_lag();
+        assert_eq!(2, lag.len());
+        assert_eq!(Some(&3), lag.get("B"));
+        assert_eq!(Some(&0), lag.get("A"));
+    }
+
+    #[test]
+    fn duplicate_message_is_discarded() {
+        let mut cluster = Cluster::new("local");
+
+        let mut clock = VectorClock::new();
+        clock.increment("A");
+
+        assert_eq!(1, cluster.receive(leave_from("A", clock.clone())).len());
+        assert!(cluster.receive(leave_from("A", clock)).is_empty());
+    }
+
+    #[test]
+    fn metrics_count_received_dropped_and_processed() {
+        let mut cluster = Cluster::new("local");
+
+        let mut clock1 = VectorClock::new();
+        clock1.increment("A");
+        let mut clock2 = clock1.clone();
+        clock2.increment("A");
+
+        cluster.receive(leave_from("A", clock1.clone()));
+        cluster.receive(leave_from("A", clock1));
+        cluster.receive(leave_from("A", clock2));
+
+        let snapshot = cluster.metrics().snapshot();
+        assert_eq!(3, snapshot.received);
+        assert_eq!(1, snapshot.dropped);
+        assert_eq!(2, snapshot.processed);
+        assert_eq!(0, snapshot.decode_failures);
+    }
+
+    #[test]
+    fn broadcast_skips_origin() {
+        let mut cluster = Cluster::new("local");
+        let origin: SocketAddr = "127.0.0.1:3400".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let (origin_tx, origin_rx) = peer_channel();
+        let (other_tx, other_rx) = peer_channel();
+        cluster.peers_tx.insert(origin, origin_tx);
+        cluster.peers_tx.insert(other, other_tx);
+
+        cluster.try_broadcast(&origin, Bytes::from_static(b"hello"));
+        drop(cluster);
+
+        assert_eq!(vec![Bytes::from_static(b"hello")], other_rx.collect().wait().unwrap());
+        assert!(origin_rx.collect().wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn broadcast_drops_for_full_peer_only() {
+        let mut cluster = Cluster::new("local");
+        let origin: SocketAddr = "127.0.0.1:3400".parse().unwrap();
+        let full: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let open: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+
+        // A zero-sized buffer still holds one message per sender
+        let (mut full_tx, full_rx) = mpsc::channel(0);
+        full_tx.try_send(Bytes::from_static(b"backlog")).unwrap();
+        let (open_tx, open_rx) = peer_channel();
+        cluster.peers_tx.insert(full, full_tx);
+        cluster.peers_tx.insert(open, open_tx);
+
+        cluster.try_broadcast(&origin, Bytes::from_static(b"hello"));
+        assert_eq!(1, cluster.metrics().snapshot().dropped_sends);
+        assert_eq!(2, cluster.peers_tx.len());
+        drop(cluster);
+
+        assert_eq!(vec![Bytes::from_static(b"hello")], open_rx.collect().wait().unwrap());
+        assert_eq!(vec![Bytes::from_static(b"backlog")], full_rx.collect().wait().unwrap());
+    }
+
+    #[test]
+    fn evict_announces_leave_to_remaining_peers() {
+        let mut cluster = Cluster::new("local");
+        let gone: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (gone_tx, _gone_rx) = peer_channel();
+        let (other_tx, other_rx) = peer_channel();
+        cluster.peers_tx.insert(gone, gone_tx);
+        cluster.peers_tx.insert(other, other_tx);
+
+        cluster.evict(&gone);
+        cluster.evict(&gone);
+        assert_eq!(1, cluster.clock().get("local"));
+        drop(cluster);
+
+        let frames = other_rx.collect().wait().unwrap();
+        assert_eq!(1, frames.len());
+        let envelope = Envelope::decode(&frames[0]).unwrap();
+        assert_eq!("local", envelope.sender);
+        assert_eq!(Message::LeaveClusterMsg(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3401,
+        }), envelope.message);
+    }
+
+    #[test]
+    fn publish_sends_to_every_peer() {
+        let mut cluster = Cluster::new("local");
+        let peer: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let (peer_tx, peer_rx) = peer_channel();
+        cluster.peers_tx.insert(peer, peer_tx);
+
+        let sent = cluster.publish(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        });
+        drop(cluster);
+
+        let frames = peer_rx.collect().wait().unwrap();
+        assert_eq!(sent, Envelope::decode(&frames[0]).unwrap());
+        assert_eq!(1, sent.clock.get("local"));
+    }
+
+    #[test]
+    fn peer_send_reaches_only_that_peer() {
+        let cluster = Arc::new(Mutex::new(Cluster::new("local")));
+        let addr: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (peer_tx, peer_rx) = peer_channel();
+        let (other_tx, other_rx) = peer_channel();
+        cluster.lock().unwrap().peers_tx.insert(addr, peer_tx);
+        cluster.lock().unwrap().peers_tx.insert(other, other_tx);
+
+        let leave = Message::from(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        });
+        Peer::new(addr, "peer", cluster.clone()).send(&leave).unwrap();
+        cluster.lock().unwrap().peers_tx.clear();
+
+        let frames = peer_rx.collect().wait().unwrap();
+        let envelope = Envelope::decode(&frames[0]).unwrap();
+        assert_eq!("local", envelope.sender);
+        assert_eq!(0, envelope.clock.get("local"));
+        assert_eq!(leave, envelope.message);
+        assert!(other_rx.collect().wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn peer_send_does_not_hold_back_later_broadcasts() {
+        let cluster = Arc::new(Mutex::new(Cluster::new("local")));
+        let addr: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (peer_tx, _peer_rx) = peer_channel();
+        let (other_tx, other_rx) = peer_channel();
+        cluster.lock().unwrap().add_peer(addr, peer_tx);
+        cluster.lock().unwrap().add_peer(other, other_tx);
+
+        Peer::new(addr, "peer", cluster.clone()).send(&Message::from(StatusRequest)).unwrap();
+        let published = cluster.lock().unwrap().publish(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        });
+        cluster.lock().unwrap().peers_tx.clear();
+
+        // The peer that got nothing directly delivers the broadcast at once
+        let frames = other_rx.collect().wait().unwrap();
+        assert_eq!(1, frames.len());
+        let mut receiver = Cluster::new("other");
+        assert_eq!(vec![published], receiver.receive(Envelope::decode(&frames[0]).unwrap()));
+    }
+
+    #[test]
+    fn peer_send_reports_missing_and_closed_peers() {
+        let cluster = Arc::new(Mutex::new(Cluster::new("local")));
+        let addr: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let peer = Peer::new(addr, "peer", cluster.clone());
+        let leave = Message::from(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        });
+        assert_eq!(Err(SendError::UnknownPeer(addr)), peer.send(&leave));
+
+        let (peer_tx, peer_rx) = peer_channel();
+        cluster.lock().unwrap().peers_tx.insert(addr, peer_tx);
+        drop(peer_rx);
+        assert_eq!(Err(SendError::Disconnected(addr)), peer.send(&leave));
+        assert!(cluster.lock().unwrap().peers_tx.is_empty());
+    }
+
+    #[test]
+    fn broadcasts_rotate_the_first_peer() {
+        let mut cluster = Cluster::new("local");
+        let peers: Vec<SocketAddr> = ["127.0.0.1:3403", "127.0.0.1:3401", "127.0.0.1:3402"].iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        for peer in &peers {
+            cluster.peers_tx.insert(*peer, peer_channel().0);
+        }
+
+        let firsts: Vec<SocketAddr> = (0..6).map(|_| cluster.broadcast_order()[0]).collect();
+        assert_eq!(vec![peers[1], peers[2], peers[0], peers[1], peers[2], peers[0]], firsts);
+
+        let order = cluster.broadcast_order();
+        assert_eq!(vec![peers[1], peers[2], peers[0]], order);
+    }
+
+    #[test]
+    fn broadcast_removes_closed_peer() {
+        let mut cluster = Cluster::new("local");
+        let origin: SocketAddr = "127.0.0.1:3400".parse().unwrap();
+        let closed: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let (closed_tx, closed_rx) = peer_channel();
+        cluster.peers_tx.insert(closed, closed_tx);
+        drop(closed_rx);
+
+        cluster.try_broadcast(&origin, Bytes::from_static(b"hello"));
+        assert!(cluster.peers_tx.is_empty());
+        assert_eq!(0, cluster.metrics().snapshot().dropped_sends);
+    }
+}
@@ -1,130 +1,138 @@
-#[macro_use]
-extern crate serde_derive;
-#[macro_use]
-extern crate futures;
-extern crate bincode;
-extern crate bytes;
 extern crate tokio;
-extern crate tokio_serde_bincode;
+extern crate vector_clocks;
 
-use bincode::{serialize, deserialize};
-use tokio::io;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::prelude::*;
-use tokio::codec::{FramedRead, LengthDelimitedCodec, length_delimited};
-use futures::sync::mpsc;
-use futures::future::{self, Either};
-use bytes::{Bytes, BytesMut, BufMut};
-use tokio_serde_bincode::{ReadBincode, WriteBincode};
-
-use std::collections::HashMap;
-use std::net::{SocketAddr, Ipv4Addr};
-use std::sync::{Arc, Mutex};
-use std::env;
-
-type Tx = mpsc::UnboundedSender<Bytes>;
-type Rx = mpsc::UnboundedReceiver<Bytes>;
-
-struct Cluster {
-    peers_tx: HashMap<SocketAddr, Tx>,
-    clock: u64,
-}
-
-impl Cluster {
-    fn new() -> Self {
-        Cluster {
-            peers_tx: HashMap::new(),
-            clock: 0
-        }
-    }
-}
-
-struct Peer {
-    addr: SocketAddr,
-    handle: String,
-    cluster: Arc<Mutex<Cluster>>,
-}
-
-impl Peer {
-
-    fn new(cluster: Arc<Mutex<Cluster>>) -> Self {
-        Peer {
-
-        }
-    }
-}
-
-// How to we convenientl store the logical clock on each message without
-// modifying the message structs?
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-enum Message {
-    JoinClusterMsg(JoinCluster),
-    LeaveClusterMsg(LeaveCluster),
-}
+use tokio::codec::{FramedWrite, LengthDelimitedCodec};
 
-impl From<JoinCluster> for Message {
-    fn from(jc: JoinCluster) -> Self {
-        Message::JoinClusterMsg(jc)
-    }
-}
-
-impl From<LeaveCluster> for Message {
-    fn from(lc: LeaveCluster) -> Self {
-        Message::LeaveClusterMsg(lc)
-    }
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct JoinCluster {
-    ip: String,
-    port: u32,
-    handle: String
-}
-
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
-struct LeaveCluster {
-    ip: String,
-    port: u32
-}
-
-// FramedRead upgrades TcpStream from an AsyncRead to a Stream
-type IOErrorStream = FramedRead<TcpStream, LengthDelimitedCodec>;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, io};
 
-// stream::FromErr maps underlying IO errors into Bincode errors
-type BincodeErrStream = stream::FromErr<IOErrorStream, bincode::Error>;
+use vector_clocks::{peer_channel, Cluster, CLUSTER_ID_VAR, DEFAULT_CLUSTER_ID};
+use vector_clocks::bind::{self, BIND_ADDR_VAR};
+use vector_clocks::blacklist::{Blacklist, BLACKLIST_VAR};
+use vector_clocks::idle::{self, IdleTimer, DEFAULT_IDLE_TIMEOUT};
+use vector_clocks::rate_limit::{RateLimit, RateLimiter, Verdict};
+use vector_clocks::wire::{self, ReadError};
+use vector_clocks::writer::{self, DEFAULT_SEND_TIMEOUT};
 
-// ReadBincode maps underlying bytes into Bincode-deserializable structs
-type BincodeStream = ReadBincode<BincodeErrStream, Message>;
+// How often connections are checked for having gone idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <port>", args[0]);
+    if args.len() < 2 || args.len() > 3 {
+        println!("Usage: {} <port> [bind address]", args[0]);
+        println!("The bind address defaults to ${} if set, else 127.0.0.1", BIND_ADDR_VAR);
+        println!("Connections from the comma-separated addresses in ${} are refused", BLACKLIST_VAR);
+        println!("Only peers in the cluster named by ${} are kept", CLUSTER_ID_VAR);
         return;
     }
-    let cluster_state = Arc::new(Mutex::new(Cluster::new()));
-
-    let ip_addr: Ipv4Addr = "0.0.0.0".parse().unwrap();
     let port: u16 = args[1].parse().map_err(|_| "could not parse port").unwrap();
-    let addr = SocketAddr::from((ip_addr, port));
+    let addr = match bind::bind_addr(args.get(2).map(String::as_str), port) {
+        Ok(addr) => addr,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let blacklist = match Blacklist::parse(&env::var(BLACKLIST_VAR).unwrap_or_default()) {
+        Ok(blacklist) => blacklist,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    let cluster_state = Arc::new(Mutex::new(Cluster::new(addr.to_string())
+        .with_local_addr(addr)
+        .with_blacklist(blacklist)
+        .with_cluster_id(env::var(CLUSTER_ID_VAR).unwrap_or_else(|_| String::from(DEFAULT_CLUSTER_ID)))));
 
     let listener = TcpListener::bind(&addr).map_err(|_| "failed to bind").unwrap();
     println!("Listening on: {}", addr);
 
+    let events = cluster_state.lock().unwrap().events();
     let server = listener.incoming()
-        .map_err(|e| println!("error accepting socket; error = {:?}", e))
+        .map_err(move |e| events.on_error(&format!("error accepting socket; error = {:?}", e)))
         .for_each(move |socket| {
-            println!("Client connected");
-            let delimited_stream: BincodeErrStream = length_delimited::Builder::new()
-                .new_read(socket)
-                .from_err::<bincode::Error>();
-
-            let deserialized: BincodeStream = ReadBincode::new(delimited_stream);
-
+            let peer_addr = match socket.peer_addr() {
+                Ok(peer_addr) => peer_addr,
+                Err(_) => return Ok(()),
+            };
+            // Dropping the socket closes it
+            if !cluster_state.lock().unwrap().admit(&peer_addr) {
+                return Ok(());
+            }
+            let (read_half, write_half) = socket.split();
+
+            // Outgoing messages are queued per peer and written by their own
+            // task, which evicts the peer if its socket stops draining
+            let (tx, rx) = peer_channel();
+            {
+                let mut cluster = cluster_state.lock().unwrap();
+                cluster.add_peer(peer_addr, tx);
+                // A fresh channel always has room
+                cluster.send_handshake(peer_addr).unwrap();
+            }
+            tokio::spawn(writer::write_to_peer(
+                peer_addr,
+                rx,
+                FramedWrite::new(write_half, LengthDelimitedCodec::new()),
+                cluster_state.clone(),
+                DEFAULT_SEND_TIMEOUT,
+            ));
+
+            let envelopes = wire::read_envelopes(read_half);
+
+            // Messages are handed to the cluster first so that they are only
+            // handled once their causal dependencies have been delivered
+            let cluster = cluster_state.clone();
+            let metrics = cluster.lock().unwrap().metrics();
+            let limit_metrics = metrics.clone();
+            let mut limiter = RateLimiter::new(RateLimit::default(), Instant::now());
+            let idle_timer = Arc::new(Mutex::new(IdleTimer::new(DEFAULT_IDLE_TIMEOUT, Instant::now())));
+            let watch = idle::watch_idle(peer_addr, idle_timer.clone(), cluster.clone(), IDLE_CHECK_INTERVAL);
             tokio::spawn(
-                deserialized
-                    .for_each(|msg| Ok(println!("GOT: {:?}", msg)))
+                envelopes
+                    .for_each(move |envelope| {
+                        idle_timer.lock().unwrap().touch(Instant::now());
+                        match limiter.check(Instant::now()) {
+                            Verdict::Allow => (),
+                            Verdict::Drop => {
+                                limit_metrics.record_rate_limited();
+                                return Ok(());
+                            }
+                            Verdict::Disconnect => {
+                                limit_metrics.record_rate_limited();
+                                cluster.lock().unwrap().evict(&peer_addr);
+                                // Ending the stream closes the connection
+                                return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                    "peer exceeded its rate limit").into());
+                            }
+                        }
+                        // Delivered messages are reported to the cluster's sink,
+                        // status requests answered and handshakes recorded for
+                        // this connection
+                        let mut cluster = cluster.lock().unwrap();
+                        cluster.receive_from(peer_addr, envelope);
+                        // A handshake from another cluster, or anything sent
+                        // before the peer's handshake, drops the peer
+                        if !cluster.peers_tx.contains_key(&peer_addr) {
+                            return Err(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                "peer is no longer connected").into());
+                        }
+                        Ok(())
+                    })
+                    .map_err(move |err| {
+                        if let ReadError::Decode(_) = err {
+                            metrics.record_decode_failure();
+                        }
+                    })
+                    // Closing an idle connection drops its reader
+                    .select(watch)
+                    .map(|_| ())
                     .map_err(|_| ()),
             );
 
@@ -160,4 +168,4 @@ fn main() {
     //         },
     //     }
     // }
-}
\ No newline at end of file
+}
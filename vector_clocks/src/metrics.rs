@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counters for the message flow through a node. They are shared through an
+// `Arc` and only ever incremented, so no lock is needed to update them.
+#[derive(Default, Debug)]
+pub struct Metrics {
+    received: AtomicU64,
+    processed: AtomicU64,
+    broadcast: AtomicU64,
+    dropped: AtomicU64,
+    dropped_sends: AtomicU64,
+    rate_limited: AtomicU64,
+    decode_failures: AtomicU64,
+}
+
+// A point-in-time copy of the counters
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct MetricsSnapshot {
+    // Messages that arrived from the network
+    pub received: u64,
+    // Messages delivered to the handler
+    pub processed: u64,
+    // Messages sent on to other peers
+    pub broadcast: u64,
+    // Duplicate messages that were discarded
+    pub dropped: u64,
+    // Relayed messages a peer had no room for
+    pub dropped_sends: u64,
+    // Messages discarded because their peer was sending too fast
+    pub rate_limited: u64,
+    // Frames that could not be decoded
+    pub decode_failures: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn record_received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_processed(&self, count: u64) {
+        self.processed.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn record_broadcast(&self) {
+        self.broadcast.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped_send(&self) {
+        self.dropped_sends.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_decode_failure(&self) {
+        self.decode_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+            broadcast: self.broadcast.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            dropped_sends: self.dropped_sends.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            decode_failures: self.decode_failures.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+// How fast one peer may send. A peer can send `burst` messages at once and
+// then `per_second` messages a second after that. Each message dropped for
+// going over the rate is a strike and each one let through removes a strike,
+// so a peer that keeps sending too fast builds up strikes and is cut off at
+// `max_strikes`, while one that bursts now and then recovers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+    pub max_strikes: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            per_second: 100.0,
+            burst: 200,
+            max_strikes: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    // Over the rate; discard the message
+    Drop,
+    // Over the rate for too long; disconnect the peer
+    Disconnect,
+}
+
+// A token bucket tracking one peer connection
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    tokens: f64,
+    strikes: u32,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    // Starts with a full bucket
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        RateLimiter {
+            limit,
+            tokens: f64::from(limit.burst),
+            strikes: 0,
+            last_refill: now,
+        }
+    }
+
+    // Decides what to do with a message that arrived at `now`
+    pub fn check(&mut self, now: Instant) -> Verdict {
+        let elapsed = now.checked_duration_since(self.last_refill).unwrap_or_else(|| Duration::from_secs(0));
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.per_second)
+            .min(f64::from(self.limit.burst));
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.strikes = self.strikes.saturating_sub(1);
+            return Verdict::Allow;
+        }
+        self.strikes += 1;
+        if self.strikes >= self.limit.max_strikes {
+            Verdict::Disconnect
+        } else {
+            Verdict::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(now: Instant) -> RateLimiter {
+        RateLimiter::new(RateLimit { per_second: 10.0, burst: 5, max_strikes: 20 }, now)
+    }
+
+    #[test]
+    fn messages_over_the_rate_are_dropped() {
+        let start = Instant::now();
+        let mut limiter = limiter(start);
+
+        let verdicts: Vec<Verdict> = (0..7).map(|_| limiter.check(start)).collect();
+        assert_eq!(vec![Verdict::Allow; 5], &verdicts[..5]);
+        assert_eq!(vec![Verdict::Drop; 2], &verdicts[5..]);
+
+        // 100ms refills one token
+        assert_eq!(Verdict::Allow, limiter.check(start + Duration::from_millis(100)));
+        assert_eq!(Verdict::Drop, limiter.check(start + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn sustained_flood_disconnects() {
+        let start = Instant::now();
+        let mut limiter = limiter(start);
+
+        // A hundred messages a second against a limit of ten
+        let mut verdicts = vec![];
+        for tick in 0..1000 {
+            let verdict = limiter.check(start + Duration::from_millis(tick * 10));
+            verdicts.push(verdict);
+            if verdict == Verdict::Disconnect {
+                break;
+            }
+        }
+        assert_eq!(Some(&Verdict::Disconnect), verdicts.last());
+        assert!(verdicts.contains(&Verdict::Drop));
+        assert!(verdicts.len() < 100);
+    }
+
+    #[test]
+    fn occasional_bursts_recover() {
+        let start = Instant::now();
+        let mut limiter = limiter(start);
+
+        for second in 0..50 {
+            let now = start + Duration::from_secs(second);
+            for _ in 0..8 {
+                assert_ne!(Verdict::Disconnect, limiter.check(now));
+            }
+        }
+    }
+}
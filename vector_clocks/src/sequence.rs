@@ -0,0 +1,228 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use clock::NodeId;
+use {Cluster, Envelope, Message};
+
+// How many ids a node asks for at a time
+pub const DEFAULT_SEQ_BLOCK_SIZE: u64 = 1000;
+
+// Cluster-wide unique ids are handed out in blocks by a coordinator, the
+// member with the lowest node id. A node draws ids from its block and asks
+// the coordinator for another once it runs out. Requests and grants are
+// published to every peer, so every node knows how far the ids granted so
+// far reach, and whichever node becomes coordinator carries on from there.
+//
+// Ranges only stay disjoint while nodes agree on who the coordinator is;
+// two nodes that both believe they coordinate can grant overlapping ranges.
+#[derive(Debug)]
+pub(crate) struct Sequence {
+    // The other nodes known to be in the cluster
+    members: BTreeSet<NodeId>,
+    block_size: u64,
+    // Ids granted to this node and not yet used
+    block: Range<u64>,
+    // Whether a request for a block is waiting on the coordinator
+    requested: bool,
+    // One past the highest id granted to any node, as far as this node knows
+    granted: u64,
+}
+
+impl Sequence {
+    pub(crate) fn new() -> Self {
+        Sequence {
+            members: BTreeSet::new(),
+            block_size: DEFAULT_SEQ_BLOCK_SIZE,
+            block: 0..0,
+            requested: false,
+            granted: 0,
+        }
+    }
+}
+
+// Asks the coordinator for `count` ids for `requester`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct NextSeq {
+    pub requester: NodeId,
+    pub count: u64,
+}
+
+// Grants `requester` the ids from `start` up to `start + count`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SeqGrant {
+    pub requester: NodeId,
+    pub start: u64,
+    pub count: u64,
+}
+
+impl Cluster {
+    pub fn with_seq_block_size(mut self, block_size: u64) -> Self {
+        self.sequence.block_size = block_size.max(1);
+        self
+    }
+
+    pub fn add_member<S>(&mut self, node_id: S) where S: Into<NodeId> {
+        let node_id = node_id.into();
+        if node_id == self.node_id {
+            return;
+        }
+        let coordinator = self.coordinator().to_string();
+        self.sequence.members.insert(node_id);
+        self.coordinator_changed(&coordinator);
+    }
+
+    pub fn remove_member(&mut self, node_id: &str) {
+        let coordinator = self.coordinator().to_string();
+        self.sequence.members.remove(node_id);
+        self.coordinator_changed(&coordinator);
+    }
+
+    // The member that grants blocks of ids
+    pub fn coordinator(&self) -> &str {
+        match self.sequence.members.iter().next() {
+            Some(lowest) if *lowest < self.node_id => lowest,
+            _ => &self.node_id,
+        }
+    }
+
+    // Returns an id no other node will be given, drawn from this node's
+    // block. Ids from one node always increase. Once the block runs out a
+    // new one is requested and None is returned until it is granted, unless
+    // this node is the coordinator and can grant itself one straight away.
+    pub fn next_id(&mut self) -> Option<u64> {
+        if let Some(id) = self.sequence.block.next() {
+            return Some(id);
+        }
+        if self.coordinator() == self.node_id {
+            let (node_id, count) = (self.node_id.clone(), self.sequence.block_size);
+            self.grant(node_id, count);
+            return self.sequence.block.next();
+        }
+        self.request_block();
+        None
+    }
+
+    // Acts on a delivered request or grant
+    pub(crate) fn apply_sequence(&mut self, envelope: &Envelope) {
+        match envelope.message {
+            Message::NextSeqMsg(ref request) if self.coordinator() == self.node_id =>
+                self.grant(request.requester.clone(), request.count),
+            Message::SeqGrantMsg(ref grant) => {
+                let end = grant.start.saturating_add(grant.count);
+                self.sequence.granted = self.sequence.granted.max(end);
+                // A grant that arrives after a re-request was already
+                // answered is left unused rather than replacing the block
+                if grant.requester == self.node_id && self.sequence.requested {
+                    self.sequence.block = grant.start..end;
+                    self.sequence.requested = false;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn grant(&mut self, requester: NodeId, count: u64) {
+        let start = self.sequence.granted;
+        self.sequence.granted = start.saturating_add(count);
+        if requester == self.node_id {
+            self.sequence.block = start..self.sequence.granted;
+            self.sequence.requested = false;
+        }
+        self.publish(SeqGrant { requester, start, count });
+    }
+
+    fn request_block(&mut self) {
+        if self.sequence.requested {
+            return;
+        }
+        self.sequence.requested = true;
+        let (requester, count) = (self.node_id.clone(), self.sequence.block_size);
+        self.publish(NextSeq { requester, count });
+    }
+
+    // A request sent to a coordinator that has since left may never be
+    // answered, so it is made again to the new one
+    fn coordinator_changed(&mut self, previous: &str) {
+        if !self.sequence.requested || self.coordinator() == previous {
+            return;
+        }
+        self.sequence.requested = false;
+        if self.coordinator() == self.node_id {
+            let (node_id, count) = (self.node_id.clone(), self.sequence.block_size);
+            self.grant(node_id, count);
+        } else {
+            self.request_block();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{future, Async, Future, Poll, Stream};
+    use std::net::SocketAddr;
+    use {peer_channel, Rx};
+
+    // Two clusters whose published messages are queued for each other
+    fn pair(a: &str, b: &str) -> ((Cluster, Rx), (Cluster, Rx)) {
+        let addr: SocketAddr = "127.0.0.1:3400".parse().unwrap();
+        let (a_tx, a_rx) = peer_channel();
+        let (b_tx, b_rx) = peer_channel();
+        let mut cluster_a = Cluster::new(a).with_seq_block_size(3);
+        let mut cluster_b = Cluster::new(b).with_seq_block_size(3);
+        cluster_a.peers_tx.insert(addr, b_tx);
+        cluster_b.peers_tx.insert(addr, a_tx);
+        cluster_a.add_member(b);
+        cluster_b.add_member(a);
+        ((cluster_a, a_rx), (cluster_b, b_rx))
+    }
+
+    // Delivers everything already queued for `to`
+    fn pump(inbox: &mut Rx, to: &mut Cluster) {
+        future::poll_fn(|| -> Poll<(), ()> {
+            while let Async::Ready(Some(frame)) = inbox.poll()? {
+                to.receive(Envelope::decode(&frame).unwrap());
+            }
+            Ok(Async::Ready(()))
+        }).wait().unwrap();
+    }
+
+    #[test]
+    fn ids_are_unique_across_nodes() {
+        let ((mut a, mut a_rx), (mut b, mut b_rx)) = pair("A", "B");
+        assert_eq!("A", a.coordinator());
+        assert_eq!("A", b.coordinator());
+
+        let (mut from_a, mut from_b) = (vec![], vec![]);
+        for _ in 0..10 {
+            from_a.extend(a.next_id());
+            from_b.extend(b.next_id());
+            pump(&mut a_rx, &mut a);
+            pump(&mut b_rx, &mut b);
+        }
+
+        assert_eq!(10, from_a.len());
+        assert!(from_b.len() >= 5);
+        assert!(from_a.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(from_b.windows(2).all(|pair| pair[0] < pair[1]));
+        let mut ids: Vec<u64> = from_a.iter().chain(&from_b).cloned().collect();
+        let count = ids.len();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(count, ids.len());
+    }
+
+    #[test]
+    fn new_coordinator_continues_after_granted_ids() {
+        let ((mut a, _a_rx), (mut b, mut b_rx)) = pair("A", "B");
+        assert_eq!(Some(0), a.next_id());
+        pump(&mut b_rx, &mut b);
+
+        // B's request goes unanswered, then A leaves
+        assert_eq!(None, b.next_id());
+        b.remove_member("A");
+        assert_eq!("B", b.coordinator());
+        assert_eq!(Some(3), b.next_id());
+        assert_eq!(Some(4), b.next_id());
+    }
+}
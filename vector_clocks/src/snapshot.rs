@@ -0,0 +1,110 @@
+use bincode;
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use clock::{NodeId, VectorClock};
+use Cluster;
+
+// What a node needs to rejoin its cluster after a restart: who it is, which
+// peers it was connected to and how far its clock had got
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ClusterSnapshotData {
+    pub node_id: NodeId,
+    pub peers: Vec<SocketAddr>,
+    pub clock: VectorClock,
+}
+
+impl ClusterSnapshotData {
+    // Writes to a temporary file first, so a crash mid-save leaves the
+    // previous snapshot in place
+    pub fn save_to_path<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            bincode::serialize_into(&mut writer, self).map_err(|err| to_io_error(*err))?;
+            writer.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        }
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn load_from_path<P: AsRef<Path>>(path: P) -> io::Result<ClusterSnapshotData> {
+        let reader = BufReader::new(File::open(path)?);
+        bincode::deserialize_from(reader).map_err(|err| to_io_error(*err))
+    }
+}
+
+fn to_io_error(err: bincode::ErrorKind) -> io::Error {
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+impl Cluster {
+    // The connected peers are listed in address order
+    pub fn snapshot(&self) -> ClusterSnapshotData {
+        let mut peers: Vec<SocketAddr> = self.peers_tx.keys().cloned().collect();
+        peers.sort();
+        ClusterSnapshotData {
+            node_id: self.node_id.clone(),
+            peers,
+            clock: self.clock.clone(),
+        }
+    }
+
+    // A cluster with the snapshot's identity and clock but no peers yet;
+    // they are added as each one in `snapshot.peers` is reconnected
+    pub fn from_snapshot(snapshot: &ClusterSnapshotData) -> Self {
+        let mut cluster = Cluster::new(snapshot.node_id.clone());
+        cluster.clock = snapshot.clock.clone();
+        cluster
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use peer_channel;
+
+    #[test]
+    fn snapshot_roundtrips_through_a_file() {
+        let mut cluster = Cluster::new("local");
+        cluster.originate(::LeaveCluster { ip: String::from("127.0.0.1"), port: 1 });
+        for port in &[3402, 3401] {
+            cluster.peers_tx.insert(SocketAddr::from(([127, 0, 0, 1], *port)), peer_channel().0);
+        }
+
+        let path = env::temp_dir().join(format!("cluster-snapshot-{}.bin", process::id()));
+        let snapshot = cluster.snapshot();
+        snapshot.save_to_path(&path).unwrap();
+        let loaded = ClusterSnapshotData::load_from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(snapshot, loaded);
+        assert_eq!(vec![
+            SocketAddr::from(([127, 0, 0, 1], 3401)),
+            SocketAddr::from(([127, 0, 0, 1], 3402)),
+        ], loaded.peers);
+
+        let restored = Cluster::from_snapshot(&loaded);
+        assert_eq!("local", restored.node_id());
+        assert_eq!(1, restored.clock().get("local"));
+        assert!(restored.peers_tx.is_empty());
+    }
+
+    #[test]
+    fn corrupt_snapshot_is_an_error() {
+        let path = env::temp_dir().join(format!("cluster-snapshot-corrupt-{}.bin", process::id()));
+        fs::write(&path, [1, 2, 3]).unwrap();
+        let result = ClusterSnapshotData::load_from_path(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use clock::{NodeId, VectorClock};
+use {Cluster, Envelope, Message, SendError};
+
+// Asks a node to describe itself
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct StatusRequest;
+
+// A node's description of itself, sent in reply to a `StatusRequest`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct StatusResponse {
+    pub node_id: NodeId,
+    pub handle: String,
+    pub uptime_secs: u64,
+    pub peer_count: u64,
+    pub clock: VectorClock,
+}
+
+impl Cluster {
+    // The name this node gives itself in status responses. Defaults to its
+    // node id.
+    pub fn with_handle<S>(mut self, handle: S) -> Self where S: Into<String> {
+        self.handle = handle.into();
+        self
+    }
+
+    pub fn handle(&self) -> &str {
+        &self.handle
+    }
+
+    // How long ago the cluster was created
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn status(&self) -> StatusResponse {
+        StatusResponse {
+            node_id: self.node_id.clone(),
+            handle: self.handle.clone(),
+            uptime_secs: self.uptime().as_secs(),
+            peer_count: self.peers_tx.len() as u64,
+            clock: self.clock.clone(),
+        }
+    }
+
+    // Accepts a message that arrived from the peer at `origin`, as `receive`
+    // does, and answers every status request that is delivered as a result.
+    // A handshake from the peer is recorded rather than delivered, and a
+    // sync request is answered rather than delivered. Anything the peer
+    // sends before its handshake drops it instead.
+    // Each answer goes to the peer the request's sender was last heard from,
+    // which may not be `origin` if the request was held back.
+    pub fn receive_from(&mut self, origin: SocketAddr, envelope: Envelope) -> Vec<Envelope> {
+        if self.refuse_before_handshake(origin, &envelope)
+            || self.accept_handshake(origin, &envelope)
+            || self.answer_sync(origin, &envelope)
+        {
+            return vec![];
+        }
+        self.routes.insert(envelope.sender.clone(), origin);
+        let delivered = self.receive(envelope);
+        for envelope in &delivered {
+            if let Message::StatusRequestMsg(_) = envelope.message {
+                if let Err(err) = self.reply_status(&envelope.sender) {
+                    self.events.on_error(&format!("Could not answer status request: {}", err));
+                }
+            }
+        }
+        delivered
+    }
+
+    // The response is stamped with this node's clock as it stands, without
+    // advancing it: only the requester gets it, so other peers must not be
+    // left waiting for it before delivering this node's later messages.
+    // Requesters should read it as it arrives rather than through `receive`,
+    // which would discard it as already seen.
+    fn reply_status(&mut self, sender: &str) -> Result<(), SendError> {
+        let addr = match self.routes.get(sender) {
+            Some(addr) => *addr,
+            None => return Ok(()),
+        };
+        let envelope = Envelope::new(self.node_id.clone(), self.clock.clone(), self.status());
+        self.send_frame(addr, Bytes::from(envelope.encode()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+    use compression::Handshake;
+    use peer_channel;
+
+    #[test]
+    fn status_request_is_answered_by_its_sender() {
+        let mut cluster = Cluster::new("127.0.0.1:3400").with_handle("node1");
+        let requester: SocketAddr = "127.0.0.1:50123".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let (requester_tx, requester_rx) = peer_channel();
+        let (other_tx, other_rx) = peer_channel();
+        cluster.add_peer(requester, requester_tx);
+        cluster.add_peer(other, other_tx);
+        cluster.accept_handshake(requester, &Envelope::new("monitor", VectorClock::new(), Handshake {
+            compression: false,
+            cluster_id: String::new(),
+        }));
+
+        let mut clock = VectorClock::new();
+        clock.increment("monitor");
+        let delivered = cluster.receive_from(requester, Envelope::new("monitor", clock.clone(), StatusRequest));
+        assert_eq!(1, delivered.len());
+        assert_eq!(&clock, cluster.clock());
+        drop(cluster);
+
+        let frames = requester_rx.collect().wait().unwrap();
+        assert_eq!(1, frames.len());
+        let envelope = Envelope::decode(&frames[0]).unwrap();
+        assert_eq!("127.0.0.1:3400", envelope.sender);
+        assert_eq!(clock, envelope.clock);
+        match envelope.message {
+            Message::StatusResponseMsg(status) => {
+                assert_eq!("127.0.0.1:3400", status.node_id);
+                assert_eq!("node1", status.handle);
+                assert_eq!(2, status.peer_count);
+                assert_eq!(clock, status.clock);
+            }
+            other => panic!("Expected a status response, got {:?}", other),
+        }
+        assert!(other_rx.collect().wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn handle_defaults_to_node_id() {
+        let cluster = Cluster::new("127.0.0.1:3400");
+        assert_eq!("127.0.0.1:3400", cluster.handle());
+        assert_eq!(0, cluster.status().uptime_secs);
+        assert_eq!(0, cluster.status().peer_count);
+    }
+}
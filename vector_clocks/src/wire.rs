@@ -0,0 +1,228 @@
+use bytes::{Bytes, BytesMut};
+use futures::Stream;
+use tokio::codec::{length_delimited, Decoder, Encoder, LengthDelimitedCodec};
+use tokio::io::AsyncRead;
+
+use std::{fmt, io};
+
+use encoding::DecodeError;
+use {Envelope, Message};
+
+// Encodes a message as one frame, the way messages are framed for a peer: a
+// length-delimited frame around its encoding
+pub fn encode_message(message: &Message) -> Bytes {
+    let payload = message.encode();
+    let mut frame = BytesMut::new();
+    LengthDelimitedCodec::new().encode(Bytes::from(payload), &mut frame)
+        .expect("frames fit in the default length limit");
+    frame.freeze()
+}
+
+// Decodes a single frame produced by `encode_message`. A frame that is cut
+// short or followed by extra bytes is an I/O error, as it would be on a
+// socket.
+pub fn decode_message(buf: &[u8]) -> Result<Message, ReadError> {
+    let mut buf = BytesMut::from(buf);
+    let payload = match LengthDelimitedCodec::new().decode(&mut buf)? {
+        Some(payload) => payload,
+        None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame").into()),
+    };
+    if !buf.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{} bytes after the frame", buf.len())).into());
+    }
+    Ok(Message::decode(&payload)?)
+}
+
+// The envelopes a peer sends over a connection, one per length-delimited
+// frame. A whole frame that isn't a valid envelope is a `Decode` error
+// rather than an I/O one.
+pub fn read_envelopes<R>(read: R) -> impl Stream<Item = Envelope, Error = ReadError> where R: AsyncRead {
+    length_delimited::Builder::new()
+        .new_read(read)
+        .from_err::<ReadError>()
+        .and_then(|frame| Ok(Envelope::decode(&frame)?))
+}
+
+// Why reading from a peer stopped
+#[derive(Debug)]
+pub enum ReadError {
+    Io(io::Error),
+    // A whole frame arrived but wasn't a valid envelope
+    Decode(DecodeError),
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::Io(ref err) => write!(f, "{}", err),
+            ReadError::Decode(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        ReadError::Io(err)
+    }
+}
+
+impl From<DecodeError> for ReadError {
+    fn from(err: DecodeError) -> Self {
+        ReadError::Decode(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::VectorClock;
+    use compression::{Compressed, Handshake};
+    use sequence::{NextSeq, SeqGrant};
+    use status::{StatusRequest, StatusResponse};
+    use {ApplyInsert, JoinCluster, LeaveCluster};
+
+    // Generates arbitrary messages from a seed, so a failing one can be
+    // generated again
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> u64 {
+            // xorshift64*
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        fn string(&mut self) -> String {
+            let len = self.below(20);
+            // Includes multi-byte characters
+            (0..len).map(|_| ['a', 'z', '0', '.', ' ', 'é', '雪'][self.below(7) as usize]).collect()
+        }
+
+        fn bytes(&mut self) -> Vec<u8> {
+            let len = self.below(300);
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        fn clock(&mut self) -> VectorClock {
+            let mut clock = VectorClock::new();
+            for _ in 0..self.below(4) {
+                let node = self.string();
+                for _ in 0..=self.below(3) {
+                    clock.increment(&node);
+                }
+            }
+            clock
+        }
+
+        fn message(&mut self) -> Message {
+            match self.below(9) {
+                0 => JoinCluster {
+                    ip: self.string(),
+                    port: self.next() as u32,
+                    handle: self.string(),
+                    cluster_id: self.string(),
+                }.into(),
+                1 => LeaveCluster {
+                    ip: self.string(),
+                    port: self.next() as u32,
+                }.into(),
+                2 => NextSeq {
+                    requester: self.string(),
+                    count: self.next(),
+                }.into(),
+                3 => SeqGrant {
+                    requester: self.string(),
+                    start: self.next(),
+                    count: self.next(),
+                }.into(),
+                4 => StatusRequest.into(),
+                5 => StatusResponse {
+                    node_id: self.string(),
+                    handle: self.string(),
+                    uptime_secs: self.next(),
+                    peer_count: self.next(),
+                    clock: self.clock(),
+                }.into(),
+                6 => Handshake {
+                    compression: self.below(2) == 1,
+                    cluster_id: self.string(),
+                }.into(),
+                7 => Compressed {
+                    payload: self.bytes(),
+                }.into(),
+                _ => ApplyInsert {
+                    table_name: self.string(),
+                    row_bytes: self.bytes(),
+                    heap_bytes: self.bytes(),
+                    clock: self.clock(),
+                }.into(),
+            }
+        }
+    }
+
+    #[test]
+    fn envelopes_are_read_from_frames() {
+        let envelope = Envelope::new("A", VectorClock::new(), LeaveCluster { ip: String::from("127.0.0.1"), port: 3400 });
+        let mut frames = BytesMut::new();
+        let mut codec = LengthDelimitedCodec::new();
+        codec.encode(Bytes::from(envelope.encode()), &mut frames).unwrap();
+        codec.encode(Bytes::from(vec![0]), &mut frames).unwrap();
+
+        let mut read = read_envelopes(io::Cursor::new(frames.to_vec())).wait();
+        assert_eq!(envelope, read.next().unwrap().unwrap());
+        match read.next() {
+            Some(Err(ReadError::Decode(DecodeError::Truncated))) => (),
+            other => panic!("Expected a decode error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn generated_messages_roundtrip() {
+        let mut gen = Gen(0x5EED);
+        for _ in 0..500 {
+            let message = gen.message();
+            assert_eq!(message, decode_message(&encode_message(&message)).unwrap());
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_errors() {
+        let mut gen = Gen(7);
+        for _ in 0..20 {
+            let frame = encode_message(&gen.message());
+            for len in 0..frame.len() {
+                assert!(decode_message(&frame[..len]).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_payload_in_complete_frame_is_an_error() {
+        let payload = Message::from(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        }).encode();
+        let mut frame = BytesMut::new();
+        LengthDelimitedCodec::new().encode(Bytes::from(&payload[..payload.len() - 1]), &mut frame).unwrap();
+
+        assert!(decode_message(&frame).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes_are_an_error() {
+        let mut frame = encode_message(&Message::from(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3400,
+        })).to_vec();
+        frame.push(0);
+
+        assert!(decode_message(&frame).is_err());
+    }
+}
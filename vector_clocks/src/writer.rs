@@ -0,0 +1,171 @@
+use bytes::Bytes;
+use futures::{Future, Sink, Stream};
+use tokio::prelude::FutureExt;
+use tokio::timer::timeout;
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use {Cluster, Rx};
+
+// How long a single send to a peer may take before the peer is considered
+// stuck
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How sending one message to a peer ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendResult {
+    // Written and flushed
+    Sent,
+    // The socket didn't accept and flush it within the send timeout
+    WouldBlock,
+    // The socket failed
+    Failed,
+}
+
+// Forwards messages queued for a peer to its socket. If one send (including
+// the flush) takes longer than `send_timeout`, or the socket fails, the peer
+// is treated as dead and evicted from the cluster. The returned future
+// finishes once the peer's channel closes or the peer is evicted, and must
+// run on a tokio runtime for the timeout to fire.
+pub fn write_to_peer<S>(addr: SocketAddr, rx: Rx, sink: S, cluster: Arc<Mutex<Cluster>>, send_timeout: Duration)
+    -> impl Future<Item = (), Error = ()>
+    where S: Sink<SinkItem = Bytes>
+{
+    write_to_peer_with(addr, rx, sink, cluster, send_timeout, |_, _| ())
+}
+
+// Like `write_to_peer`, but calls `on_complete` with each message once its
+// send has finished, successfully or not. Each message is flushed before
+// the next is started. A message that fails is the last one reported, as
+// the peer is evicted.
+pub fn write_to_peer_with<S, F>(addr: SocketAddr, rx: Rx, sink: S, cluster: Arc<Mutex<Cluster>>,
+                                send_timeout: Duration, on_complete: F)
+    -> impl Future<Item = (), Error = ()>
+    where S: Sink<SinkItem = Bytes>, F: FnMut(&Bytes, SendResult)
+{
+    let on_complete = Arc::new(Mutex::new(on_complete));
+    rx.map_err(|_| None::<timeout::Error<S::SinkError>>)
+        .fold(sink, move |sink, msg| {
+            let on_complete = on_complete.clone();
+            sink.send(msg.clone())
+                .timeout(send_timeout)
+                .then(move |result| {
+                    let sent = match result {
+                        Ok(_) => SendResult::Sent,
+                        Err(ref err) if err.is_elapsed() => SendResult::WouldBlock,
+                        Err(_) => SendResult::Failed,
+                    };
+                    (*on_complete.lock().unwrap())(&msg, sent);
+                    result.map_err(Some)
+                })
+        })
+        .then(move |result| {
+            // Only a failed or timed out send evicts; the channel itself
+            // never errors
+            if let Err(Some(_)) = result {
+                cluster.lock().unwrap().evict(&addr);
+            }
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Async, AsyncSink, Poll, StartSend};
+    use tokio::runtime::Runtime;
+    use {peer_channel, LeaveCluster, Envelope, Message};
+
+    // Accepts nothing, like a socket whose reader has stopped reading
+    struct StuckSink;
+
+    impl Sink for StuckSink {
+        type SinkItem = Bytes;
+        type SinkError = ();
+
+        fn start_send(&mut self, item: Bytes) -> StartSend<Bytes, ()> {
+            Ok(AsyncSink::NotReady(item))
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), ()> {
+            Ok(Async::NotReady)
+        }
+    }
+
+    // Accepts every message, then flushes or fails according to a script
+    struct ScriptedSink {
+        flushes: Vec<bool>,
+    }
+
+    impl Sink for ScriptedSink {
+        type SinkItem = Bytes;
+        type SinkError = ();
+
+        fn start_send(&mut self, _item: Bytes) -> StartSend<Bytes, ()> {
+            Ok(AsyncSink::Ready)
+        }
+
+        fn poll_complete(&mut self) -> Poll<(), ()> {
+            if self.flushes.remove(0) {
+                Ok(Async::Ready(()))
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    #[test]
+    fn each_send_reports_its_result() {
+        let addr: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let cluster = Arc::new(Mutex::new(Cluster::new("local")));
+        let (mut tx, rx) = peer_channel();
+        tx.try_send(Bytes::from_static(b"first")).unwrap();
+        tx.try_send(Bytes::from_static(b"second")).unwrap();
+        tx.try_send(Bytes::from_static(b"never sent")).unwrap();
+        cluster.lock().unwrap().peers_tx.insert(addr, tx);
+
+        let results = Arc::new(Mutex::new(vec![]));
+        let recorded = results.clone();
+        let writer = write_to_peer_with(addr, rx, ScriptedSink { flushes: vec![true, false] }, cluster.clone(),
+            Duration::from_secs(5), move |msg, result| recorded.lock().unwrap().push((msg.clone(), result)));
+        Runtime::new().unwrap().block_on(writer).unwrap();
+
+        assert_eq!(vec![
+            (Bytes::from_static(b"first"), SendResult::Sent),
+            (Bytes::from_static(b"second"), SendResult::Failed),
+        ], *results.lock().unwrap());
+        assert!(cluster.lock().unwrap().peers_tx.is_empty());
+    }
+
+    #[test]
+    fn stuck_peer_is_evicted_after_timeout() {
+        let stuck: SocketAddr = "127.0.0.1:3401".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:3402".parse().unwrap();
+        let cluster = Arc::new(Mutex::new(Cluster::new("local")));
+
+        let (mut stuck_tx, stuck_rx) = peer_channel();
+        let (other_tx, other_rx) = peer_channel();
+        stuck_tx.try_send(Bytes::from_static(b"hello")).unwrap();
+        cluster.lock().unwrap().peers_tx.insert(stuck, stuck_tx);
+        cluster.lock().unwrap().peers_tx.insert(other, other_tx);
+
+        let results = Arc::new(Mutex::new(vec![]));
+        let recorded = results.clone();
+        let writer = write_to_peer_with(stuck, stuck_rx, StuckSink, cluster.clone(), Duration::from_millis(50),
+            move |_, result| recorded.lock().unwrap().push(result));
+        Runtime::new().unwrap().block_on(writer).unwrap();
+
+        assert_eq!(vec![SendResult::WouldBlock], *results.lock().unwrap());
+
+        assert!(!cluster.lock().unwrap().peers_tx.contains_key(&stuck));
+        let frame = other_rx.into_future().wait().ok().unwrap().0.unwrap();
+        let envelope = Envelope::decode(&frame).unwrap();
+        assert_eq!("local", envelope.sender);
+        assert_eq!(Message::LeaveClusterMsg(LeaveCluster {
+            ip: String::from("127.0.0.1"),
+            port: 3401,
+        }), envelope.message);
+    }
+}